@@ -13,6 +13,13 @@ pub mod gemini;
 #[cfg(feature = "realtime-clients")]
 pub mod openai_realtime;
 
+pub mod caching;
+pub mod fallback;
+pub mod load_balance;
 pub mod map;
+#[cfg(feature = "async-rt")]
+pub mod rate_limit;
+#[cfg(feature = "async-rt")]
+pub mod retry;
 pub mod router;
 pub mod tester;
@@ -0,0 +1,616 @@
+use crate::protocol::*;
+use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream};
+use crate::utils::hash::KeyHasher;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A completed response stored by a [`CachingClient`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    /// When the response was stored, used for expiration and eviction.
+    pub created_at: DateTime<Utc>,
+    /// The final content of the response.
+    pub content: MessageContent,
+    /// The bot that answered, kept apart as the content doesn't serialize it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answered_by: Option<BotId>,
+}
+
+/// Where a [`CachingClient`] keeps its responses.
+///
+/// Storages are expected to enforce their own size limits. Failures should be
+/// logged and treated as a cache miss, as the cache is never required to work.
+pub trait CacheStorage: Send + Sync + 'static {
+    /// Gets the response stored with the given key, if any.
+    fn get(&self, key: &str) -> BoxPlatformSendFuture<'static, Option<CachedResponse>>;
+
+    /// Stores a response with the given key, replacing the previous one.
+    fn insert(&self, key: &str, response: CachedResponse) -> BoxPlatformSendFuture<'static, ()>;
+
+    /// Removes the response stored with the given key, if any.
+    fn remove(&self, key: &str) -> BoxPlatformSendFuture<'static, ()>;
+
+    /// Removes all the stored responses.
+    fn clear(&self) -> BoxPlatformSendFuture<'static, ()>;
+}
+
+/// Stores responses in memory, for the lifetime of the storage.
+#[derive(Clone, Default)]
+pub struct MemoryCacheStorage {
+    entries: Arc<Mutex<HashMap<String, CachedResponse>>>,
+    max_entries: Option<usize>,
+}
+
+impl MemoryCacheStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits how many responses are kept, evicting the oldest ones first.
+    pub fn set_max_entries(&mut self, max_entries: Option<usize>) {
+        self.max_entries = max_entries;
+        let mut entries = self.entries.lock().unwrap();
+        evict_oldest(&mut entries, max_entries);
+    }
+
+    /// How many responses are currently stored.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn evict_oldest(entries: &mut HashMap<String, CachedResponse>, max_entries: Option<usize>) {
+    let Some(max_entries) = max_entries else {
+        return;
+    };
+
+    while entries.len() > max_entries {
+        let oldest = entries
+            .iter()
+            .min_by_key(|(_, response)| response.created_at)
+            .map(|(key, _)| key.clone());
+
+        match oldest {
+            Some(key) => entries.remove(&key),
+            None => break,
+        };
+    }
+}
+
+impl CacheStorage for MemoryCacheStorage {
+    fn get(&self, key: &str) -> BoxPlatformSendFuture<'static, Option<CachedResponse>> {
+        let response = self.entries.lock().unwrap().get(key).cloned();
+        Box::pin(async move { response })
+    }
+
+    fn insert(&self, key: &str, response: CachedResponse) -> BoxPlatformSendFuture<'static, ()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key.to_string(), response);
+        evict_oldest(&mut entries, self.max_entries);
+        Box::pin(async {})
+    }
+
+    fn remove(&self, key: &str) -> BoxPlatformSendFuture<'static, ()> {
+        self.entries.lock().unwrap().remove(key);
+        Box::pin(async {})
+    }
+
+    fn clear(&self) -> BoxPlatformSendFuture<'static, ()> {
+        self.entries.lock().unwrap().clear();
+        Box::pin(async {})
+    }
+}
+
+/// Stores responses as JSON files in a directory, one file per response.
+///
+/// The directory is created when needed. Attachments in responses are stored
+/// like any other serialized [`Attachment`], so in-memory ones become unavailable
+/// when read back.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct FileCacheStorage {
+    dir: std::path::PathBuf,
+    max_entries: Option<usize>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileCacheStorage {
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        FileCacheStorage {
+            dir: dir.into(),
+            max_entries: None,
+        }
+    }
+
+    /// Limits how many responses are kept, evicting the least recently written
+    /// ones first. Checked when inserting.
+    pub fn set_max_entries(&mut self, max_entries: Option<usize>) {
+        self.max_entries = max_entries;
+    }
+
+    pub fn dir(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &str) -> std::path::PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+
+    /// Runs file system work outside of the async context.
+    fn blocking<T: Send + 'static>(
+        f: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+    ) -> BoxPlatformSendFuture<'static, Option<T>> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        crate::utils::thread::queue_blocking(move || {
+            let _ = tx.send(f());
+        });
+
+        Box::pin(async move {
+            match rx.await {
+                Ok(Ok(value)) => Some(value),
+                Ok(Err(error)) => {
+                    log::warn!("Response cache file operation failed: {error}");
+                    None
+                }
+                Err(_) => None,
+            }
+        })
+    }
+
+    fn json_files(dir: &std::path::Path) -> std::io::Result<Vec<std::fs::DirEntry>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|e| e == "json") {
+                files.push(entry);
+            }
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CacheStorage for FileCacheStorage {
+    fn get(&self, key: &str) -> BoxPlatformSendFuture<'static, Option<CachedResponse>> {
+        let path = self.path(key);
+        let future = Self::blocking(move || match std::fs::read(&path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        });
+
+        Box::pin(async move { future.await.flatten() })
+    }
+
+    fn insert(&self, key: &str, response: CachedResponse) -> BoxPlatformSendFuture<'static, ()> {
+        let dir = self.dir.clone();
+        let path = self.path(key);
+        let max_entries = self.max_entries;
+
+        let future = Self::blocking(move || {
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&path, serde_json::to_vec(&response)?)?;
+
+            if let Some(max_entries) = max_entries {
+                let mut files = Self::json_files(&dir)?
+                    .into_iter()
+                    .map(|entry| {
+                        let modified = entry.metadata().and_then(|m| m.modified()).ok();
+                        (modified, entry.path())
+                    })
+                    .collect::<Vec<_>>();
+
+                if files.len() > max_entries {
+                    files.sort();
+                    for (_, path) in &files[..files.len() - max_entries] {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        Box::pin(async move {
+            future.await;
+        })
+    }
+
+    fn remove(&self, key: &str) -> BoxPlatformSendFuture<'static, ()> {
+        let path = self.path(key);
+        let future = Self::blocking(move || match std::fs::remove_file(&path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        });
+
+        Box::pin(async move {
+            future.await;
+        })
+    }
+
+    fn clear(&self) -> BoxPlatformSendFuture<'static, ()> {
+        let dir = self.dir.clone();
+        let future = Self::blocking(move || {
+            if !dir.exists() {
+                return Ok(());
+            }
+
+            for entry in Self::json_files(&dir)? {
+                std::fs::remove_file(entry.path())?;
+            }
+
+            Ok(())
+        });
+
+        Box::pin(async move {
+            future.await;
+        })
+    }
+}
+
+/// Computes the cache key of a request.
+///
+/// Message metadata (dates, etc) is ignored, and attachments are hashed by their
+/// content, so equivalent conversations get the same key.
+async fn cache_key(
+    namespace: &str,
+    bot_id: &BotId,
+    messages: &[Message],
+    tools: &[Tool],
+) -> String {
+    let mut hasher = KeyHasher::new();
+    hasher.update_json(&namespace);
+    hasher.update_json(bot_id);
+    hasher.update_json(&tools);
+
+    for message in messages {
+        hasher.update_json(&message.from);
+        hasher.update_json(&MessageContent {
+            attachments: Vec::new(),
+            ..message.content.clone()
+        });
+
+        for attachment in &message.content.attachments {
+            hasher.update_json(&(&attachment.name, &attachment.content_type));
+            match attachment.read().await {
+                Ok(content) => {
+                    let mut content_hasher = KeyHasher::new();
+                    content_hasher.update(&content);
+                    hasher.update_json(&content_hasher.finish());
+                }
+                Err(_) => hasher.update_json(&()),
+            }
+        }
+    }
+
+    hasher.finish()
+}
+
+struct Inner<C: BotClient> {
+    client: C,
+    storage: Arc<dyn CacheStorage>,
+    ttl: Option<Duration>,
+    namespace: String,
+}
+
+/// Utility wrapper client that caches the responses of the underlying client.
+///
+/// Requests are identified by the bot id, the messages (ignoring their metadata) and
+/// the tools. When an identical request is sent again, the stored response is
+/// replayed instead of reaching the underlying client.
+///
+/// Only responses completed without errors are stored. Responses upgrading to
+/// realtime are never stored.
+///
+/// Responses are kept in a [`MemoryCacheStorage`] by default. Use
+/// [`CachingClient::set_storage`] to use a [`FileCacheStorage`] or your own.
+pub struct CachingClient<C: BotClient> {
+    inner: Arc<Mutex<Inner<C>>>,
+}
+
+impl<C: BotClient> Clone for CachingClient<C> {
+    fn clone(&self) -> Self {
+        CachingClient {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<C: BotClient> CachingClient<C> {
+    pub fn new(client: C) -> Self {
+        CachingClient {
+            inner: Arc::new(Mutex::new(Inner {
+                client,
+                storage: Arc::new(MemoryCacheStorage::new()),
+                ttl: None,
+                namespace: String::new(),
+            })),
+        }
+    }
+
+    /// Sets where responses are stored. Previously stored responses are not moved.
+    pub fn set_storage(&mut self, storage: impl CacheStorage) {
+        self.inner.lock().unwrap().storage = Arc::new(storage);
+    }
+
+    /// Sets how long stored responses are valid. `None` (default) never expires them.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.inner.lock().unwrap().ttl = ttl;
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.inner.lock().unwrap().ttl
+    }
+
+    /// Sets a value that is part of every cache key.
+    ///
+    /// Use it to tell apart requests the cache can't see the difference between,
+    /// like the same bot configured with different options (temperature, etc).
+    pub fn set_namespace(&mut self, namespace: impl Into<String>) {
+        self.inner.lock().unwrap().namespace = namespace.into();
+    }
+
+    pub fn namespace(&self) -> String {
+        self.inner.lock().unwrap().namespace.clone()
+    }
+
+    /// Removes all the responses from the current storage.
+    pub fn clear(&self) -> BoxPlatformSendFuture<'static, ()> {
+        self.inner.lock().unwrap().storage.clear()
+    }
+}
+
+impl<C: BotClient> From<C> for CachingClient<C> {
+    fn from(client: C) -> Self {
+        CachingClient::new(client)
+    }
+}
+
+impl<C: BotClient + 'static> BotClient for CachingClient<C> {
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        Box::new(self.clone())
+    }
+
+    fn rate_limits(&self) -> Option<RateLimits> {
+        self.inner.lock().unwrap().client.rate_limits()
+    }
+
+    fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        self.inner.lock().unwrap().client.bots()
+    }
+
+    fn send(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        let inner = self.inner.clone();
+        let bot_id = bot_id.clone();
+        let messages = messages.to_vec();
+        let tools = tools.to_vec();
+
+        let stream = async_stream::stream! {
+            let (storage, ttl, namespace) = {
+                let inner = inner.lock().unwrap();
+                (inner.storage.clone(), inner.ttl, inner.namespace.clone())
+            };
+
+            let key = cache_key(&namespace, &bot_id, &messages, &tools).await;
+
+            if let Some(cached) = storage.get(&key).await {
+                let expired = ttl.is_some_and(|ttl| {
+                    let age = Utc::now() - cached.created_at;
+                    age.to_std().is_ok_and(|age| age > ttl)
+                });
+
+                if !expired {
+                    let mut content = cached.content;
+                    content.answered_by = cached.answered_by;
+                    yield ClientResult::new_ok(content);
+                    return;
+                }
+
+                storage.remove(&key).await;
+            }
+
+            let stream = inner.lock().unwrap().client.send(&bot_id, &messages, &tools);
+            let mut last_content = None;
+            let mut has_errors = false;
+
+            for await result in stream {
+                has_errors |= result.has_errors();
+                last_content = result.value().cloned();
+                yield result;
+            }
+
+            if let Some(content) = last_content
+                && !has_errors
+                && content.upgrade.is_none()
+            {
+                let response = CachedResponse {
+                    created_at: Utc::now(),
+                    answered_by: content.answered_by.clone(),
+                    content,
+                };
+                storage.insert(&key, response).await;
+            }
+        };
+
+        Box::pin(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Client answering with the amount of times it has been called.
+    fn counting() -> MockClient {
+        MockClient::responding(|call| MockReply::text(format!("answer {}", call.number)))
+    }
+
+    fn user_message(text: &str, attachments: Vec<Attachment>) -> Message {
+        Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: text.into(),
+                attachments,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn send_text(client: &mut CachingClient<MockClient>, messages: &[Message]) -> String {
+        let stream = client.send(&BotId::new("bot"), messages, &[]);
+        let results: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(stream));
+        results
+            .last()
+            .and_then(|r| r.value())
+            .map(|c| c.text.clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn replays_identical_requests() {
+        let inner = counting();
+        let mut client = CachingClient::new(inner.clone());
+
+        let first = vec![user_message("hi", vec![])];
+        // Same conversation, sent at another time.
+        let mut second = vec![user_message("hi", vec![])];
+        second[0].metadata = MessageMetadata::epoch();
+
+        assert_eq!(send_text(&mut client, &first), "answer 1");
+        assert_eq!(send_text(&mut client, &second), "answer 1");
+        assert_eq!(inner.call_count(), 1);
+
+        assert_eq!(
+            send_text(&mut client, &[user_message("bye", vec![])]),
+            "answer 2"
+        );
+
+        client.set_namespace("temperature=1");
+        assert_eq!(send_text(&mut client, &first), "answer 3");
+    }
+
+    #[test]
+    fn hashes_attachments_by_content() {
+        let a = Attachment::from_bytes("a.txt".into(), None, b"same");
+        let b = Attachment::from_bytes("a.txt".into(), None, b"same");
+        let c = Attachment::from_bytes("a.txt".into(), None, b"different");
+        assert_ne!(a, b);
+
+        let key = |attachment: Attachment| {
+            futures::executor::block_on(cache_key(
+                "",
+                &BotId::new("bot"),
+                &[user_message("hi", vec![attachment])],
+                &[],
+            ))
+        };
+
+        assert_eq!(key(a.clone()), key(b));
+        assert_ne!(key(a), key(c));
+    }
+
+    #[test]
+    fn does_not_store_errors() {
+        let failing = MockClient::responding(|_| {
+            MockReply::error(ClientError::new(ClientErrorKind::Network, "down".into()))
+        });
+        let mut client = CachingClient::new(failing.clone());
+
+        let messages = vec![user_message("hi", vec![])];
+        send_text(&mut client, &messages);
+        send_text(&mut client, &messages);
+        assert_eq!(failing.call_count(), 2);
+    }
+
+    #[test]
+    fn expires_entries_after_ttl() {
+        let mut client = CachingClient::new(counting());
+        let messages = vec![user_message("hi", vec![])];
+
+        client.set_ttl(Some(Duration::ZERO));
+        assert_eq!(send_text(&mut client, &messages), "answer 1");
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(send_text(&mut client, &messages), "answer 2");
+    }
+
+    #[test]
+    fn evicts_oldest_entries() {
+        let mut storage = MemoryCacheStorage::new();
+        storage.set_max_entries(Some(2));
+
+        for (i, key) in ["a", "b", "c"].into_iter().enumerate() {
+            let response = CachedResponse {
+                created_at: DateTime::from_timestamp(i as i64, 0).unwrap(),
+                content: MessageContent::default(),
+                answered_by: None,
+            };
+            futures::executor::block_on(storage.insert(key, response));
+        }
+
+        assert_eq!(storage.len(), 2);
+        assert!(futures::executor::block_on(storage.get("a")).is_none());
+        assert!(futures::executor::block_on(storage.get("c")).is_some());
+    }
+
+    #[test]
+    fn stores_responses_in_files() {
+        let dir = std::env::temp_dir().join(format!("aitk-cache-test-{}", std::process::id()));
+        let storage = FileCacheStorage::new(&dir);
+        let inner = counting();
+        let mut client = CachingClient::new(inner.clone());
+        client.set_storage(storage.clone());
+
+        let messages = vec![user_message("hi", vec![])];
+        assert_eq!(send_text(&mut client, &messages), "answer 1");
+
+        // A new client reading the same directory replays the response.
+        let mut other = CachingClient::new(counting());
+        other.set_storage(storage.clone());
+        assert_eq!(send_text(&mut other, &messages), "answer 1");
+        assert_eq!(inner.call_count(), 1);
+
+        futures::executor::block_on(storage.clear());
+        assert_eq!(send_text(&mut client, &messages), "answer 2");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn replays_who_answered() {
+        let dir = std::env::temp_dir().join(format!("aitk-cache-answered-{}", std::process::id()));
+        let storage = FileCacheStorage::new(&dir);
+        let mut client = CachingClient::new(MockClient::responding(|_| {
+            MockReply::content(MessageContent {
+                text: "answer".into(),
+                answered_by: Some(BotId::new("backup")),
+                ..Default::default()
+            })
+        }));
+        client.set_storage(storage);
+
+        let messages = vec![user_message("hi", vec![])];
+        let answered_by = |client: &mut CachingClient<MockClient>| {
+            let stream = client.send(&BotId::new("bot"), &messages, &[]);
+            let results: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(stream));
+            results[0].value().unwrap().answered_by.clone()
+        };
+
+        assert_eq!(answered_by(&mut client), Some(BotId::new("backup")));
+        // Replayed from the file, where the content alone would lose it.
+        assert_eq!(answered_by(&mut client), Some(BotId::new("backup")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,326 @@
+use std::sync::{Arc, Mutex};
+
+use crate::protocol::*;
+
+#[derive(Clone)]
+struct Target {
+    client: Box<dyn BotClient>,
+    bot_id: BotId,
+}
+
+#[derive(Clone)]
+struct Inner {
+    bot: Bot,
+    targets: Vec<Target>,
+}
+
+/// A client that tries an ordered list of bots, possibly from different providers,
+/// until one of them answers.
+///
+/// All targets are exposed together as a single [`Bot`], whose capabilities are the
+/// ones shared by all the targets.
+///
+/// When sending, the next target is tried if the current one fails before streaming
+/// any content. Once content has been streamed, errors are returned as they come.
+/// The bot that actually answered is reported in [`MessageContent::answered_by`],
+/// which the chat controller records in [`MessageMetadata::answered_by`].
+#[derive(Clone)]
+pub struct FallbackClient {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl BotClient for FallbackClient {
+    fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        let inner = self.inner.lock().unwrap().clone();
+
+        Box::pin(async move {
+            let mut targets = inner.targets;
+            let results =
+                futures::future::join_all(targets.iter_mut().map(|target| target.client.bots()))
+                    .await;
+
+            let mut errors = Vec::new();
+            let mut target_bots = Vec::new();
+            for (target, result) in targets.iter().zip(results) {
+                let (bots, bots_errors) = result.into_value_and_errors();
+                errors.extend(bots_errors);
+
+                if let Some(bot) = bots
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|b| b.id == target.bot_id)
+                {
+                    target_bots.push(bot);
+                }
+            }
+
+            // Being able to fallback is the point, so unavailable targets are not fatal.
+            if target_bots.is_empty() && !errors.is_empty() {
+                return ClientResult::new_err(errors);
+            }
+
+            for error in errors {
+                log::warn!("Fallback target could not be listed: {}", error);
+            }
+
+            let mut bot = inner.bot;
+            if let Some((first, rest)) = target_bots.split_first() {
+                bot.capabilities = BotCapabilities::new().with_capabilities(
+                    first
+                        .capabilities
+                        .iter()
+                        .filter(|c| rest.iter().all(|b| b.capabilities.has_capability(c)))
+                        .cloned(),
+                );
+            }
+
+            ClientResult::new_ok(vec![bot])
+        })
+    }
+
+    fn send(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        let inner = self.inner.lock().unwrap().clone();
+        let bot_id = bot_id.clone();
+        let messages = messages.to_vec();
+        let tools = tools.to_vec();
+
+        let stream = async_stream::stream! {
+            if bot_id != inner.bot.id {
+                yield ClientError::new(
+                    ClientErrorKind::Unknown,
+                    format!("The bot id does not belong to this fallback client: {:?}", bot_id),
+                ).into();
+                return;
+            }
+
+            let mut errors = Vec::new();
+
+            'targets: for mut target in inner.targets {
+                let stream = target.client.send(&target.bot_id, &messages, &tools);
+                let mut has_streamed = false;
+
+                for await result in stream {
+                    let has_content = result.value().is_some_and(|c| !c.is_empty());
+
+                    if !has_streamed && !has_content && result.has_errors() {
+                        log::warn!("Fallback target {} failed, trying the next one", target.bot_id);
+                        errors.extend(result.into_errors());
+                        continue 'targets;
+                    }
+
+                    // Nothing to show yet, and the target may still end without answering.
+                    if !has_streamed && !has_content && !result.has_errors() {
+                        continue;
+                    }
+
+                    has_streamed |= has_content;
+
+                    let (content, result_errors) = result.into_value_and_errors();
+                    let content = content.map(|mut c| {
+                        c.answered_by = Some(target.bot_id.clone());
+                        c
+                    });
+                    yield ClientResult::new_unchecked(content, result_errors);
+                }
+
+                if !has_streamed {
+                    log::warn!("Fallback target {} answered nothing, trying the next one", target.bot_id);
+                    errors.push(ClientError::new(
+                        ClientErrorKind::Response,
+                        format!("{} answered nothing.", target.bot_id.as_str()),
+                    ));
+                    continue 'targets;
+                }
+
+                return;
+            }
+
+            if errors.is_empty() {
+                errors.push(ClientError::new(
+                    ClientErrorKind::Unknown,
+                    "This fallback client has no targets to send to.".into(),
+                ));
+            }
+
+            yield ClientResult::new_err(errors);
+        };
+
+        Box::pin(stream)
+    }
+
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        Box::new(self.clone())
+    }
+}
+
+impl FallbackClient {
+    /// Creates a fallback client exposed as a bot with the given id.
+    pub fn new(bot_id: BotId) -> Self {
+        let name = bot_id.as_str().to_string();
+        let avatar = EntityAvatar::from_first_grapheme(&name.to_uppercase())
+            .unwrap_or_else(|| EntityAvatar::Text("?".into()));
+
+        FallbackClient {
+            inner: Arc::new(Mutex::new(Inner {
+                bot: Bot {
+                    id: bot_id,
+                    name,
+                    avatar,
+                    capabilities: BotCapabilities::new(),
+                },
+                targets: Vec::new(),
+            })),
+        }
+    }
+
+    /// Sets the name displayed for the bot exposed by this client.
+    pub fn set_name(&self, name: impl Into<String>) {
+        self.inner.lock().unwrap().bot.name = name.into();
+    }
+
+    /// Sets the avatar displayed for the bot exposed by this client.
+    pub fn set_avatar(&self, avatar: EntityAvatar) {
+        self.inner.lock().unwrap().bot.avatar = avatar;
+    }
+
+    /// Adds a target to try after all the existing ones.
+    pub fn push_target(&self, client: Box<dyn BotClient>, bot_id: BotId) {
+        self.inner
+            .lock()
+            .unwrap()
+            .targets
+            .push(Target { client, bot_id });
+    }
+
+    /// Removes all targets.
+    pub fn clear_targets(&self) {
+        self.inner.lock().unwrap().targets.clear();
+    }
+
+    /// The bot ids of the targets, in the order they are tried.
+    pub fn target_bot_ids(&self) -> Vec<BotId> {
+        let inner = self.inner.lock().unwrap();
+        inner.targets.iter().map(|t| t.bot_id.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Client with a single bot that always fails or always answers its own id.
+    fn stub(id: &str, capabilities: BotCapabilities, fails: bool) -> Box<dyn BotClient> {
+        let client = MockClient::new();
+        client.set_bots([Bot {
+            id: BotId::new(id),
+            name: id.into(),
+            avatar: EntityAvatar::Text("S".into()),
+            capabilities,
+        }]);
+
+        let text = id.to_string();
+        client.set_responder(move |_| match fails {
+            true => MockReply::error(ClientError::new(ClientErrorKind::Network, "down".into())),
+            false => MockReply::text(text.clone()),
+        });
+        Box::new(client)
+    }
+
+    /// Client whose answers end without any content.
+    fn silent() -> Box<dyn BotClient> {
+        Box::new(MockClient::responding(|_| {
+            MockReply::content(MessageContent::default())
+        }))
+    }
+
+    fn send(client: &mut FallbackClient) -> Vec<ClientResult<MessageContent>> {
+        let stream = client.send(&BotId::new("fallback"), &[], &[]);
+        futures::executor::block_on(futures::StreamExt::collect(stream))
+    }
+
+    #[test]
+    fn falls_back_to_next_target() {
+        let mut client = FallbackClient::new(BotId::new("fallback"));
+        client.push_target(
+            stub("primary", BotCapabilities::all(), true),
+            BotId::new("primary"),
+        );
+        client.push_target(
+            stub("secondary", BotCapabilities::all(), false),
+            BotId::new("secondary"),
+        );
+
+        let results = send(&mut client);
+        assert_eq!(results.len(), 1);
+        let content = results[0].value().unwrap();
+        assert_eq!(content.text, "secondary");
+        assert_eq!(content.answered_by, Some(BotId::new("secondary")));
+    }
+
+    #[test]
+    fn falls_back_when_target_answers_nothing() {
+        let mut client = FallbackClient::new(BotId::new("fallback"));
+        client.push_target(silent(), BotId::new("silent"));
+        client.push_target(
+            stub("secondary", BotCapabilities::all(), false),
+            BotId::new("secondary"),
+        );
+
+        let results = send(&mut client);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].value().unwrap().text, "secondary");
+
+        let mut client = FallbackClient::new(BotId::new("fallback"));
+        client.push_target(silent(), BotId::new("silent"));
+        let results = send(&mut client);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].errors().len(), 1);
+    }
+
+    #[test]
+    fn returns_all_errors_when_every_target_fails() {
+        let mut client = FallbackClient::new(BotId::new("fallback"));
+        client.push_target(stub("a", BotCapabilities::all(), true), BotId::new("a"));
+        client.push_target(stub("b", BotCapabilities::all(), true), BotId::new("b"));
+
+        let results = send(&mut client);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].errors().len(), 2);
+    }
+
+    #[test]
+    fn exposes_single_bot_with_shared_capabilities() {
+        let mut client = FallbackClient::new(BotId::new("fallback"));
+        client.push_target(stub("a", BotCapabilities::all(), false), BotId::new("a"));
+        client.push_target(
+            stub(
+                "b",
+                BotCapabilities::new().with_capability(BotCapability::TextInput),
+                false,
+            ),
+            BotId::new("b"),
+        );
+
+        let bots = futures::executor::block_on(client.bots())
+            .into_value()
+            .unwrap();
+        assert_eq!(bots.len(), 1);
+        assert_eq!(bots[0].id, BotId::new("fallback"));
+        assert!(
+            bots[0]
+                .capabilities
+                .has_capability(&BotCapability::TextInput)
+        );
+        assert!(
+            !bots[0]
+                .capabilities
+                .has_capability(&BotCapability::ToolInput)
+        );
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::protocol::*;
 use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream};
+use crate::utils::cassette::Cassette;
 use crate::utils::sse::parse_sse;
 use async_stream::stream;
 use reqwest::header::{HeaderMap, HeaderName};
@@ -19,6 +20,7 @@ struct GeminiClientInner {
     url: String,
     headers: HeaderMap,
     client: reqwest::Client,
+    cassette: Option<Cassette>,
 }
 
 /// A native Gemini API client using `/models` and `:streamGenerateContent`.
@@ -38,6 +40,7 @@ impl GeminiClient {
             url,
             headers: HeaderMap::new(),
             client: crate::utils::http::default_client(),
+            cassette: None,
         };
         Self(Arc::new(RwLock::new(inner)))
     }
@@ -58,6 +61,15 @@ impl GeminiClient {
     pub fn set_key(&mut self, key: &str) -> Result<(), &'static str> {
         self.set_header("x-goog-api-key", key)
     }
+
+    /// Records the HTTP exchanges of this client into the cassette, or replays them
+    /// from it, depending on its mode. See [`crate::utils::cassette`].
+    pub fn set_cassette(&mut self, cassette: Option<Cassette>) {
+        self.0
+            .write()
+            .expect("gemini client lock poisoned")
+            .cassette = cassette;
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -773,16 +785,12 @@ impl BotClient for GeminiClient {
                     Err(error) => return error.into(),
                 };
 
-                let response = match inner
-                    .client
-                    .get(&url)
-                    .headers(inner.headers.clone())
-                    .send()
-                    .await
-                {
-                    Ok(response) => response,
-                    Err(error) => {
-                        return ClientError::new_with_source(
+                let request = inner.client.get(&url).headers(inner.headers.clone());
+                let response =
+                    match crate::utils::cassette::send(request, inner.cassette.as_ref()).await {
+                        Ok(response) => response,
+                        Err(error) => {
+                            return ClientError::new_with_source(
                             ClientErrorKind::Network,
                             format!(
                                 "Could not send request to {url}. Verify your connection and key."
@@ -790,18 +798,19 @@ impl BotClient for GeminiClient {
                             Some(error),
                         )
                         .into();
-                    }
-                };
+                        }
+                    };
 
                 if !response.status().is_success() {
                     let status = response.status();
+                    let headers = response.headers().clone();
                     let details = response.text().await.unwrap_or_default();
-                    return ClientError::new(
+                    let error = ClientError::new(
                         ClientErrorKind::Response,
                         format!("Gemini models request failed with status {status}."),
                     )
-                    .with_details(details)
-                    .into();
+                    .with_details(details);
+                    return crate::utils::http::with_response_info(error, status, &headers).into();
                 }
 
                 let payload = match response.text().await {
@@ -876,14 +885,8 @@ impl BotClient for GeminiClient {
                 }
             };
 
-            let response = match inner
-                .client
-                .post(&url)
-                .headers(inner.headers)
-                .json(&request)
-                .send()
-                .await
-            {
+            let request = inner.client.post(&url).headers(inner.headers).json(&request);
+            let response = match crate::utils::cassette::send(request, inner.cassette.as_ref()).await {
                 Ok(response) => response,
                 Err(error) => {
                     yield ClientError::new_with_source(
@@ -899,11 +902,13 @@ impl BotClient for GeminiClient {
 
             if !response.status().is_success() {
                 let status = response.status();
+                let headers = response.headers().clone();
                 let details = response.text().await.unwrap_or_default();
-                yield ClientError::new(
+                let error = ClientError::new(
                     ClientErrorKind::Response,
                     format!("Gemini streaming request failed with status {status}."),
-                ).with_details(details).into();
+                ).with_details(details);
+                yield crate::utils::http::with_response_info(error, status, &headers).into();
                 return;
             }
 
@@ -1412,4 +1417,45 @@ mod tests {
             "no fallback call should have been wrongly renamed"
         );
     }
+
+    #[test]
+    fn replays_recorded_stream() {
+        use crate::utils::cassette::{Cassette, Chunk, Exchange};
+
+        let bot_id = BotId::new("gemini-2.0-flash");
+        let event = |text: &str| {
+            format!(r#"data: {{"candidates":[{{"content":{{"parts":[{{"text":"{text}"}}]}}}}]}}"#)
+        };
+        // Events split across chunks, like they may arrive from the network.
+        let body = format!("{}\n\n{}\n\n", event("Hello"), event(" world"));
+        let (first, second) = body.split_at(30);
+
+        let cassette = Cassette::replay(vec![Exchange {
+            method: "POST".into(),
+            url: build_stream_url("http://localhost", &bot_id).unwrap(),
+            request_body: None,
+            status: 200,
+            response_headers: vec![],
+            response_body: vec![Chunk::Text(first.into()), Chunk::Text(second.into())],
+        }]);
+
+        let mut client = GeminiClient::new("http://localhost".into());
+        client.set_cassette(Some(cassette.clone()));
+
+        let messages = [Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: "Hi".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let results: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(
+            client.send(&bot_id, &messages, &[]),
+        ));
+
+        let content = results.last().unwrap().value().unwrap();
+        assert_eq!(content.text, "Hello world");
+        assert_eq!(cassette.remaining(), 0);
+    }
 }
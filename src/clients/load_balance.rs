@@ -0,0 +1,503 @@
+use crate::protocol::*;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How a [`LoadBalancedClient`] picks the member to send to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadBalanceStrategy {
+    /// Each send goes to the next member, in order.
+    #[default]
+    RoundRobin,
+    /// Each send goes to the member with the fewest streams in progress.
+    LeastInFlight,
+    /// Sends are distributed proportionally to the weight of each member.
+    Weighted,
+}
+
+#[derive(Default)]
+struct MemberState {
+    in_flight: usize,
+    unhealthy_until: Option<DateTime<Utc>>,
+    /// Accumulated by the smooth weighted round-robin.
+    current_weight: i64,
+}
+
+impl MemberState {
+    fn is_healthy(&self, now: DateTime<Utc>) -> bool {
+        self.unhealthy_until.is_none_or(|until| until <= now)
+    }
+}
+
+struct Member {
+    client: Box<dyn BotClient>,
+    weight: u32,
+    state: Arc<Mutex<MemberState>>,
+}
+
+impl Clone for Member {
+    fn clone(&self) -> Self {
+        Member {
+            client: self.client.clone_box(),
+            weight: self.weight,
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+struct Inner {
+    members: Vec<Member>,
+    strategy: LoadBalanceStrategy,
+    cooldowns: HashMap<ClientErrorKind, Duration>,
+    /// Where the round-robin continues from.
+    next: usize,
+}
+
+impl Inner {
+    /// Chooses a member not in `tried`, or `None` if there is nothing left to try.
+    ///
+    /// Unhealthy members are skipped, unless all of them are unhealthy on the first
+    /// attempt, in which case the one recovering first is used anyway.
+    fn select(&mut self, tried: &[usize], now: DateTime<Utc>) -> Option<usize> {
+        let untried = (0..self.members.len()).filter(|index| !tried.contains(index));
+        let mut candidates: Vec<usize> = untried
+            .clone()
+            .filter(|&index| self.members[index].state.lock().unwrap().is_healthy(now))
+            .collect();
+
+        if candidates.is_empty() {
+            if !tried.is_empty() {
+                return None;
+            }
+
+            return untried
+                .min_by_key(|&index| self.members[index].state.lock().unwrap().unhealthy_until);
+        }
+
+        // Scan candidates starting from the round-robin position, so ties are broken
+        // fairly by every strategy.
+        let len = self.members.len();
+        let next = self.next;
+        candidates.sort_by_key(|&index| (index + len - next % len) % len);
+
+        let selected = match self.strategy {
+            LoadBalanceStrategy::RoundRobin => candidates[0],
+            LoadBalanceStrategy::LeastInFlight => *candidates
+                .iter()
+                .min_by_key(|&&index| self.members[index].state.lock().unwrap().in_flight)
+                .unwrap(),
+            LoadBalanceStrategy::Weighted => {
+                let mut total = 0;
+                let mut selected = candidates[0];
+                let mut selected_weight = i64::MIN;
+                for &index in &candidates {
+                    let member = &self.members[index];
+                    let mut state = member.state.lock().unwrap();
+                    state.current_weight += member.weight as i64;
+                    total += member.weight as i64;
+                    if state.current_weight > selected_weight {
+                        selected = index;
+                        selected_weight = state.current_weight;
+                    }
+                }
+                self.members[selected].state.lock().unwrap().current_weight -= total;
+                selected
+            }
+        };
+
+        self.next = (selected + 1) % len;
+        Some(selected)
+    }
+
+    /// How long a member failing with this error should be avoided, if at all.
+    fn cooldown(&self, error: &ClientError) -> Option<Duration> {
+        let cooldown = *self.cooldowns.get(&error.kind())?;
+
+        // Bad requests would fail the same way on any member, but rejected keys,
+        // rate limits and server errors are specific to this one.
+        if let Some(status_code) = error.status_code()
+            && !matches!(status_code, 401 | 403 | 408 | 429 | 500..)
+        {
+            return None;
+        }
+
+        Some(error.retry_after().map_or(cooldown, |r| r.max(cooldown)))
+    }
+}
+
+/// Counts a stream as in flight for its member while alive.
+struct InFlight(Arc<Mutex<MemberState>>);
+
+impl InFlight {
+    fn new(state: Arc<Mutex<MemberState>>) -> Self {
+        state.lock().unwrap().in_flight += 1;
+        InFlight(state)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().in_flight -= 1;
+    }
+}
+
+/// A client distributing sends across a pool of equivalent clients, like several
+/// API keys or mirror endpoints serving the same models.
+///
+/// Members failing with an error that is specific to them (network errors, rejected
+/// keys, rate limits and server errors) are marked as unhealthy and avoided during a
+/// cooldown, and the send is tried again on another member as long as no content
+/// has been streamed yet. Use [`LoadBalancedClient::set_cooldown`] to change which
+/// kinds of errors do this and for how long.
+///
+/// The bots of all members are exposed together, without duplicates.
+#[derive(Clone)]
+pub struct LoadBalancedClient {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for LoadBalancedClient {
+    fn default() -> Self {
+        Self::new(LoadBalanceStrategy::default())
+    }
+}
+
+impl LoadBalancedClient {
+    pub fn new(strategy: LoadBalanceStrategy) -> Self {
+        let default_cooldown = Duration::from_secs(30);
+
+        let mut cooldowns = HashMap::new();
+        cooldowns.insert(ClientErrorKind::Network, default_cooldown);
+        cooldowns.insert(ClientErrorKind::Response, default_cooldown);
+
+        LoadBalancedClient {
+            inner: Arc::new(Mutex::new(Inner {
+                members: Vec::new(),
+                strategy,
+                cooldowns,
+                next: 0,
+            })),
+        }
+    }
+
+    /// Adds a member with a weight of 1.
+    pub fn push_member(&self, client: Box<dyn BotClient>) {
+        self.push_weighted_member(client, 1);
+    }
+
+    /// Adds a member. The weight only matters with [`LoadBalanceStrategy::Weighted`].
+    pub fn push_weighted_member(&self, client: Box<dyn BotClient>, weight: u32) {
+        self.inner.lock().unwrap().members.push(Member {
+            client,
+            weight,
+            state: Arc::new(Mutex::new(MemberState::default())),
+        });
+    }
+
+    /// Removes all members.
+    pub fn clear_members(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.members.clear();
+        inner.next = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn set_strategy(&self, strategy: LoadBalanceStrategy) {
+        self.inner.lock().unwrap().strategy = strategy;
+    }
+
+    pub fn strategy(&self) -> LoadBalanceStrategy {
+        self.inner.lock().unwrap().strategy
+    }
+
+    /// Sets how long members failing with the given kind of error are avoided.
+    /// `None` means this kind of error doesn't affect the health of members.
+    ///
+    /// A longer `Retry-After` from the server takes precedence.
+    pub fn set_cooldown(&self, kind: ClientErrorKind, cooldown: Option<Duration>) {
+        let mut inner = self.inner.lock().unwrap();
+        match cooldown {
+            Some(cooldown) => inner.cooldowns.insert(kind, cooldown),
+            None => inner.cooldowns.remove(&kind),
+        };
+    }
+
+    pub fn cooldown(&self, kind: ClientErrorKind) -> Option<Duration> {
+        self.inner.lock().unwrap().cooldowns.get(&kind).copied()
+    }
+
+    /// The number of members not currently in cooldown.
+    pub fn healthy_len(&self) -> usize {
+        let now = Utc::now();
+        let inner = self.inner.lock().unwrap();
+        inner
+            .members
+            .iter()
+            .filter(|m| m.state.lock().unwrap().is_healthy(now))
+            .count()
+    }
+}
+
+impl BotClient for LoadBalancedClient {
+    fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        let mut members = self.inner.lock().unwrap().members.clone();
+
+        Box::pin(async move {
+            let results =
+                futures::future::join_all(members.iter_mut().map(|m| m.client.bots())).await;
+
+            let mut seen = HashSet::new();
+            let mut bots = Vec::new();
+            let mut errors = Vec::new();
+            for result in results {
+                let (member_bots, member_errors) = result.into_value_and_errors();
+                errors.extend(member_errors);
+                for bot in member_bots.unwrap_or_default() {
+                    if seen.insert(bot.id.clone()) {
+                        bots.push(bot);
+                    }
+                }
+            }
+
+            // Members are equivalent, so one listing is enough.
+            if bots.is_empty() && !errors.is_empty() {
+                return ClientResult::new_err(errors);
+            }
+
+            for error in errors {
+                log::warn!("Load balanced member could not be listed: {}", error);
+            }
+
+            ClientResult::new_ok(bots)
+        })
+    }
+
+    fn send(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        let inner = Arc::clone(&self.inner);
+        let bot_id = bot_id.clone();
+        let messages = messages.to_vec();
+        let tools = tools.to_vec();
+
+        let stream = async_stream::stream! {
+            let mut tried = Vec::new();
+            let mut errors = Vec::new();
+
+            loop {
+                let selected = {
+                    let mut inner = inner.lock().unwrap();
+                    inner
+                        .select(&tried, Utc::now())
+                        .map(|index| (index, inner.members[index].clone()))
+                };
+
+                let Some((index, mut member)) = selected else {
+                    break;
+                };
+
+                tried.push(index);
+                let _in_flight = InFlight::new(Arc::clone(&member.state));
+                let stream = member.client.send(&bot_id, &messages, &tools);
+                let mut has_streamed = false;
+                let mut cooldown = None;
+
+                for await result in stream {
+                    let has_content = result.value().is_some_and(|c| !c.is_empty());
+
+                    {
+                        let inner = inner.lock().unwrap();
+                        cooldown = result
+                            .errors()
+                            .iter()
+                            .filter_map(|e| inner.cooldown(e))
+                            .max()
+                            .or(cooldown);
+                    }
+
+                    if !has_streamed && !has_content && cooldown.is_some() {
+                        log::warn!("Load balanced member {} failed, trying another one", index);
+                        errors.extend(result.into_errors());
+                        break;
+                    }
+
+                    has_streamed |= has_content;
+                    yield result;
+                }
+
+                let mut state = member.state.lock().unwrap();
+                match cooldown {
+                    Some(cooldown) => {
+                        let cooldown = chrono::Duration::from_std(cooldown)
+                            .unwrap_or(chrono::Duration::MAX);
+                        state.unhealthy_until = Some(Utc::now() + cooldown);
+                    }
+                    None => state.unhealthy_until = None,
+                }
+                drop(state);
+
+                if cooldown.is_none() || has_streamed {
+                    return;
+                }
+            }
+
+            if errors.is_empty() {
+                errors.push(ClientError::new(
+                    ClientErrorKind::Unknown,
+                    "This load balanced client has no members to send to.".into(),
+                ));
+            }
+
+            yield ClientResult::new_err(errors);
+        };
+
+        Box::pin(stream)
+    }
+
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use futures::StreamExt;
+
+    /// Member serving a "model" bot, answering every send with the given reply.
+    fn member(reply: MockReply) -> Box<dyn BotClient> {
+        let client = MockClient::responding(move |_| reply.clone());
+        client.set_bot_ids(["model"]);
+        Box::new(client)
+    }
+
+    fn failure(kind: ClientErrorKind, status_code: Option<u16>) -> MockReply {
+        let mut error = ClientError::new(kind, "failed".into());
+        if let Some(status_code) = status_code {
+            error = error.with_status_code(status_code);
+        }
+        MockReply::error(error)
+    }
+
+    fn send(client: &mut LoadBalancedClient) -> ClientResult<MessageContent> {
+        let stream = client.send(&BotId::new("model"), &[], &[]);
+        let mut results = futures::executor::block_on(stream.collect::<Vec<_>>());
+        assert_eq!(results.len(), 1);
+        results.pop().unwrap()
+    }
+
+    fn answered_text(client: &mut LoadBalancedClient) -> String {
+        send(client).into_value().unwrap().text
+    }
+
+    #[test]
+    fn round_robin_rotates_members() {
+        let mut client = LoadBalancedClient::new(LoadBalanceStrategy::RoundRobin);
+        client.push_member(member(MockReply::text("a")));
+        client.push_member(member(MockReply::text("b")));
+        client.push_member(member(MockReply::text("c")));
+
+        let answers: Vec<String> = (0..4).map(|_| answered_text(&mut client)).collect();
+        assert_eq!(answers, ["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn least_in_flight_prefers_idle_members() {
+        let mut client = LoadBalancedClient::new(LoadBalanceStrategy::LeastInFlight);
+        client.push_member(member(MockReply::text("a").endless()));
+        client.push_member(member(MockReply::text("b").endless()));
+
+        let next_text = |stream: &mut BoxPlatformSendStream<'static, _>| {
+            let result: ClientResult<MessageContent> =
+                futures::executor::block_on(stream.next()).unwrap();
+            result.into_value().unwrap().text
+        };
+
+        let mut first = client.send(&BotId::new("model"), &[], &[]);
+        assert_eq!(next_text(&mut first), "a");
+        let mut second = client.send(&BotId::new("model"), &[], &[]);
+        assert_eq!(next_text(&mut second), "b");
+        drop(second);
+
+        // Round-robin would go back to "a", which is still streaming.
+        let mut third = client.send(&BotId::new("model"), &[], &[]);
+        assert_eq!(next_text(&mut third), "b");
+    }
+
+    #[test]
+    fn weighted_distributes_proportionally() {
+        let mut client = LoadBalancedClient::new(LoadBalanceStrategy::Weighted);
+        client.push_weighted_member(member(MockReply::text("a")), 3);
+        client.push_weighted_member(member(MockReply::text("b")), 1);
+
+        let answers: Vec<String> = (0..8).map(|_| answered_text(&mut client)).collect();
+        assert_eq!(answers.iter().filter(|a| *a == "a").count(), 6);
+        assert_eq!(answers.iter().filter(|a| *a == "b").count(), 2);
+    }
+
+    #[test]
+    fn unhealthy_members_are_skipped_during_cooldown() {
+        let mut client = LoadBalancedClient::new(LoadBalanceStrategy::RoundRobin);
+        client.push_member(member(failure(ClientErrorKind::Response, Some(429))));
+        client.push_member(member(MockReply::text("b")));
+
+        assert_eq!(answered_text(&mut client), "b");
+        assert_eq!(client.healthy_len(), 1);
+        assert_eq!(answered_text(&mut client), "b");
+        assert_eq!(answered_text(&mut client), "b");
+    }
+
+    #[test]
+    fn request_errors_are_not_retried_on_other_members() {
+        let mut client = LoadBalancedClient::new(LoadBalanceStrategy::RoundRobin);
+        client.push_member(member(failure(ClientErrorKind::Response, Some(400))));
+        client.push_member(member(failure(ClientErrorKind::Format, None)));
+
+        assert_eq!(send(&mut client).errors()[0].status_code(), Some(400));
+        assert_eq!(
+            send(&mut client).errors()[0].kind(),
+            ClientErrorKind::Format
+        );
+        assert_eq!(client.healthy_len(), 2);
+    }
+
+    #[test]
+    fn returns_all_errors_when_every_member_fails() {
+        let mut client = LoadBalancedClient::default();
+        client.push_member(member(failure(ClientErrorKind::Network, None)));
+        client.push_member(member(failure(ClientErrorKind::Network, None)));
+
+        assert_eq!(send(&mut client).errors().len(), 2);
+        assert_eq!(client.healthy_len(), 0);
+
+        // Still tries the member recovering first rather than failing outright.
+        assert_eq!(send(&mut client).errors().len(), 1);
+    }
+
+    #[test]
+    fn bots_are_deduplicated() {
+        let mut client = LoadBalancedClient::default();
+        let a = MockClient::responding(|_| MockReply::text("a"));
+        a.set_bot_ids(["model", "other"]);
+        client.push_member(Box::new(a));
+        client.push_member(member(MockReply::text("b")));
+
+        let bots = futures::executor::block_on(client.bots())
+            .into_value()
+            .unwrap();
+        let ids: Vec<&str> = bots.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["model", "other"]);
+    }
+}
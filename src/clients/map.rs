@@ -4,18 +4,37 @@ use crate::protocol::*;
 use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream};
 use std::sync::{Arc, Mutex};
 
+/// What is about to be sent to the underlying client, as seen by
+/// [`MapClient::set_map_request`].
+#[derive(Clone, Debug)]
+pub struct SendRequest {
+    pub bot_id: BotId,
+    pub messages: Vec<Message>,
+    pub tools: Vec<Tool>,
+}
+
+type MapRequest =
+    dyn FnMut(SendRequest) -> BoxPlatformSendFuture<'static, ClientResult<SendRequest>> + Send;
+type MapError = dyn FnMut(ClientError) -> BoxPlatformSendFuture<'static, ClientError> + Send;
+type FilterSend =
+    dyn FnMut(MessageContent) -> BoxPlatformSendFuture<'static, Option<MessageContent>> + Send;
+
 struct Inner<C: BotClient> {
     client: C,
     map_bots: Option<Box<dyn FnMut(Vec<Bot>) -> Vec<Bot> + Send + 'static>>,
     map_send: Option<Box<dyn FnMut(MessageContent) -> MessageContent + Send + 'static>>,
+    map_request: Option<Box<MapRequest>>,
+    map_error: Option<Box<MapError>>,
+    filter_send: Option<Box<FilterSend>>,
 }
 
-/// Utility wrapper client that transforms the output of the underlying client.
+/// Utility wrapper client that transforms the input and output of the underlying client.
 ///
-/// This is just limited to synchronous transformations over successful results.
+/// Useful to write simple middlewares, like injecting a system prompt, redacting
+/// messages, or dropping reasoning, without implementing [`BotClient`] by hand.
 ///
-/// In general, it's recommended to implement the [`BotClient`] trait directly for
-/// maximum control instead of using this.
+/// For anything more involved, it's recommended to implement the [`BotClient`] trait
+/// directly for maximum control instead of using this.
 pub struct MapClient<C: BotClient> {
     inner: Arc<Mutex<Inner<C>>>,
 }
@@ -35,6 +54,9 @@ impl<C: BotClient> MapClient<C> {
                 client,
                 map_bots: None,
                 map_send: None,
+                map_request: None,
+                map_error: None,
+                filter_send: None,
             })),
         }
     }
@@ -53,6 +75,66 @@ impl<C: BotClient> MapClient<C> {
         let mut inner = self.inner.lock().unwrap();
         inner.map_send = Some(Box::new(map));
     }
+
+    /// Sets an async transformation for the bot id, messages and tools before they are
+    /// sent to the underlying client.
+    ///
+    /// Returning errors aborts the send, yielding them instead.
+    pub fn set_map_request(
+        &mut self,
+        map: impl FnMut(SendRequest) -> BoxPlatformSendFuture<'static, ClientResult<SendRequest>>
+        + Send
+        + 'static,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.map_request = Some(Box::new(map));
+    }
+
+    /// Sets an async transformation for each error returned by the `bots` and `send`
+    /// methods.
+    pub fn set_map_error(
+        &mut self,
+        map: impl FnMut(ClientError) -> BoxPlatformSendFuture<'static, ClientError> + Send + 'static,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.map_error = Some(Box::new(map));
+    }
+
+    /// Sets an async transformation for the successful results of the `send` method,
+    /// applied after the one from [`MapClient::set_map_send`].
+    ///
+    /// Returning `None` drops the result from the stream. As each result holds the
+    /// whole content so far, this just skips an intermediate update, unless it's the
+    /// last one.
+    pub fn set_filter_send(
+        &mut self,
+        filter: impl FnMut(MessageContent) -> BoxPlatformSendFuture<'static, Option<MessageContent>>
+        + Send
+        + 'static,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.filter_send = Some(Box::new(filter));
+    }
+}
+
+/// Applies the error transformation, if any, to all the given errors.
+async fn map_errors<C: BotClient>(
+    inner: &Arc<Mutex<Inner<C>>>,
+    errors: Vec<ClientError>,
+) -> Vec<ClientError> {
+    let mut mapped = Vec::with_capacity(errors.len());
+    for error in errors {
+        // Don't retain the lock across the await point.
+        let future = match &mut inner.lock().unwrap().map_error {
+            Some(map_error) => map_error(error),
+            None => {
+                mapped.push(error);
+                continue;
+            }
+        };
+        mapped.push(future.await);
+    }
+    mapped
 }
 
 impl<C: BotClient> From<C> for MapClient<C> {
@@ -66,6 +148,10 @@ impl<C: BotClient + 'static> BotClient for MapClient<C> {
         Box::new(self.clone())
     }
 
+    fn rate_limits(&self) -> Option<RateLimits> {
+        self.inner.lock().unwrap().client.rate_limits()
+    }
+
     fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
         let inner = self.inner.clone();
         let future = self.inner.lock().unwrap().client.bots();
@@ -74,7 +160,8 @@ impl<C: BotClient + 'static> BotClient for MapClient<C> {
             let result = future.await;
 
             if result.has_errors() {
-                return result;
+                let (value, errors) = result.into_value_and_errors();
+                return ClientResult::new_unchecked(value, map_errors(&inner, errors).await);
             }
 
             let mut bots = result.into_value().unwrap();
@@ -94,17 +181,40 @@ impl<C: BotClient + 'static> BotClient for MapClient<C> {
         tools: &[Tool],
     ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
         let inner = self.inner.clone();
-        let stream = self
-            .inner
-            .lock()
-            .unwrap()
-            .client
-            .send(bot_id, messages, tools);
+        let request = SendRequest {
+            bot_id: bot_id.clone(),
+            messages: messages.to_vec(),
+            tools: tools.to_vec(),
+        };
 
         let stream = async_stream::stream! {
+            // Don't retain the lock across the await point.
+            let mapping = match &mut inner.lock().unwrap().map_request {
+                Some(map_request) => Ok(map_request(request)),
+                None => Err(request),
+            };
+
+            let request = match mapping {
+                Ok(future) => match future.await.into_result() {
+                    Ok(request) => request,
+                    Err(errors) => {
+                        yield ClientResult::new_err(map_errors(&inner, errors).await);
+                        return;
+                    }
+                },
+                Err(request) => request,
+            };
+
+            let stream = inner.lock().unwrap().client.send(
+                &request.bot_id,
+                &request.messages,
+                &request.tools,
+            );
+
             for await result in stream {
                 if result.has_errors() {
-                    yield result;
+                    let (value, errors) = result.into_value_and_errors();
+                    yield ClientResult::new_unchecked(value, map_errors(&inner, errors).await);
                     continue;
                 }
 
@@ -114,6 +224,19 @@ impl<C: BotClient + 'static> BotClient for MapClient<C> {
                     content = map_send(content);
                 }
 
+                let filtering = match &mut inner.lock().unwrap().filter_send {
+                    Some(filter_send) => Ok(filter_send(content)),
+                    None => Err(content),
+                };
+
+                let content = match filtering {
+                    Ok(future) => match future.await {
+                        Some(content) => content,
+                        None => continue,
+                    },
+                    Err(content) => content,
+                };
+
                 yield ClientResult::new_ok(content);
             }
         };
@@ -121,3 +244,90 @@ impl<C: BotClient + 'static> BotClient for MapClient<C> {
         Box::pin(stream)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use futures::StreamExt;
+
+    /// Client answering with the text of the last message, with some reasoning, or
+    /// failing if there are no messages.
+    fn echo() -> MockClient {
+        MockClient::responding(|call| match call.messages.last() {
+            Some(message) => MockReply::content(MessageContent {
+                text: message.content.text.clone(),
+                reasoning: "thinking".into(),
+                ..Default::default()
+            }),
+            None => MockReply::error(ClientError::new(
+                ClientErrorKind::Response,
+                "no messages".into(),
+            )),
+        })
+    }
+
+    fn send(client: &mut MapClient<MockClient>, text: &str) -> Vec<ClientResult<MessageContent>> {
+        let messages = [Message {
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let stream = client.send(&BotId::new("echo"), &messages, &[]);
+        futures::executor::block_on(stream.collect())
+    }
+
+    #[test]
+    fn maps_request_and_filters_stream() {
+        let mut client = MapClient::new(echo());
+        client.set_map_request(|mut request| {
+            Box::pin(async move {
+                for message in &mut request.messages {
+                    message.content.text = message.content.text.replace("secret", "[redacted]");
+                }
+                ClientResult::new_ok(request)
+            })
+        });
+        client.set_filter_send(|mut content| {
+            Box::pin(async move {
+                content.reasoning.clear();
+                Some(content)
+            })
+        });
+
+        let results = send(&mut client, "my secret");
+        let content = results[0].value().unwrap();
+        assert_eq!(content.text, "my [redacted]");
+        assert_eq!(content.reasoning, "");
+
+        client.set_filter_send(|_| Box::pin(async { None }));
+        assert!(send(&mut client, "dropped").is_empty());
+    }
+
+    #[test]
+    fn maps_errors() {
+        let mut client = MapClient::new(echo());
+        client.set_map_request(|mut request| {
+            Box::pin(async move {
+                request.messages.clear();
+                ClientResult::new_ok(request)
+            })
+        });
+        client.set_map_error(|error| {
+            Box::pin(async move {
+                ClientError::new(error.kind(), format!("mapped: {}", error.message()))
+            })
+        });
+
+        let results = send(&mut client, "hello");
+        assert_eq!(results[0].errors()[0].message(), "mapped: no messages");
+
+        client.set_map_request(|_| {
+            Box::pin(async { ClientError::new(ClientErrorKind::Unknown, "rejected".into()).into() })
+        });
+        let results = send(&mut client, "hello");
+        assert_eq!(results[0].errors()[0].message(), "mapped: rejected");
+    }
+}
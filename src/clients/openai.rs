@@ -5,10 +5,11 @@ use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     str::FromStr,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
 };
 
 use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream};
+use crate::utils::cassette::Cassette;
 use crate::utils::{serde::deserialize_null_default, sse::parse_sse};
 use crate::protocol::*;
 
@@ -414,6 +415,9 @@ struct OpenAiClientInner {
     headers: HeaderMap,
     client: reqwest::Client,
     tools_enabled: bool,
+    /// Shared by clones, updated from the headers of every response.
+    rate_limits: Arc<Mutex<Option<RateLimits>>>,
+    cassette: Option<Cassette>,
 }
 
 /// A client capable of interacting with Moly Server and other OpenAI-compatible APIs.
@@ -443,6 +447,8 @@ impl OpenAiClient {
             headers,
             client,
             tools_enabled: true, // Default to enabled for backward compatibility
+            rate_limits: Arc::default(),
+            cassette: None,
         }
         .into()
     }
@@ -478,6 +484,12 @@ impl OpenAiClient {
     pub fn set_tools_enabled(&mut self, enabled: bool) {
         self.0.write().unwrap().tools_enabled = enabled;
     }
+
+    /// Records the HTTP exchanges of this client into the cassette, or replays them
+    /// from it, depending on its mode. See [`crate::utils::cassette`].
+    pub fn set_cassette(&mut self, cassette: Option<Cassette>) {
+        self.0.write().unwrap().cassette = cassette;
+    }
 }
 
 impl BotClient for OpenAiClient {
@@ -494,9 +506,15 @@ impl BotClient for OpenAiClient {
                 BotCapability::ToolInput,
             ]);
 
-            crate::utils::openai::get_bots(&client, &base_url, headers, &capabilities)
-                .await
-                .into()
+            crate::utils::openai::get_bots(
+                &client,
+                &base_url,
+                headers,
+                &capabilities,
+                inner.cassette.as_ref(),
+            )
+            .await
+            .into()
         })
     }
 
@@ -504,6 +522,10 @@ impl BotClient for OpenAiClient {
         Box::new(self.clone())
     }
 
+    fn rate_limits(&self) -> Option<RateLimits> {
+        self.0.read().unwrap().rate_limits.lock().unwrap().clone()
+    }
+
     /// Stream pieces of content back as a ChatDelta instead of just a String.
     fn send(
         &mut self,
@@ -561,12 +583,17 @@ impl BotClient for OpenAiClient {
                 .headers(headers)
                 .json(&json);
 
-            let response = match request.send().await {
+            let response = match crate::utils::cassette::send(request, inner.cassette.as_ref()).await {
                 Ok(response) => {
+                    if let Some(rate_limits) = crate::utils::http::rate_limits(response.headers()) {
+                        *inner.rate_limits.lock().unwrap() = Some(rate_limits);
+                    }
+
                     if response.status().is_success() {
                         response
                     } else {
                         let status_code = response.status();
+                        let response_headers = response.headers().clone();
                         let body = response.text().await.unwrap();
                         let message = format!(
                             "Request failed with status {}",
@@ -574,10 +601,15 @@ impl BotClient for OpenAiClient {
                         );
 
                         log::error!("Error sending request to {}: status {}", url, status_code);
-                        yield ClientError::new(
+                        let error = ClientError::new(
                             ClientErrorKind::Response,
                             message,
-                        ).with_details(body).into();
+                        ).with_details(body);
+                        yield crate::utils::http::with_response_info(
+                            error,
+                            status_code,
+                            &response_headers,
+                        ).into();
                         return;
                     }
                 }
@@ -768,3 +800,70 @@ fn split_reasoning_tag(text: &str) -> (&str, &str) {
         ("", text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::cassette::{Chunk, Exchange};
+
+    fn replay_client(exchanges: Vec<Exchange>) -> OpenAiClient {
+        let mut client = OpenAiClient::new("http://localhost/v1".into());
+        client.set_cassette(Some(Cassette::replay(exchanges)));
+        client
+    }
+
+    #[test]
+    fn replays_recorded_exchanges() {
+        let models = Exchange {
+            method: "GET".into(),
+            url: "http://localhost/v1/models".into(),
+            request_body: None,
+            status: 200,
+            response_headers: vec![],
+            response_body: vec![Chunk::Text(r#"{"data":[{"id":"gpt-test"}]}"#.into())],
+        };
+        let completion = Exchange {
+            method: "POST".into(),
+            url: "http://localhost/v1/chat/completions".into(),
+            request_body: None,
+            status: 200,
+            response_headers: vec![("x-ratelimit-remaining-requests".into(), "41".into())],
+            response_body: vec![
+                Chunk::Text(r#"data: {"choices":[{"delta":{"content":"Hel"#.into()),
+                Chunk::Text("lo\"}}]}\n\ndata: [DONE]\n\n".into()),
+            ],
+        };
+        let mut client = replay_client(vec![models, completion]);
+
+        let bots = futures::executor::block_on(client.bots())
+            .into_result()
+            .unwrap();
+        assert_eq!(bots[0].id, BotId::new("gpt-test"));
+
+        let results: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(
+            client.send(&BotId::new("gpt-test"), &[], &[]),
+        ));
+        let content = results.last().unwrap().value().unwrap();
+        assert_eq!(content.text, "Hello");
+        assert_eq!(client.rate_limits().unwrap().remaining_requests, Some(41));
+    }
+
+    #[test]
+    fn replays_error_responses() {
+        let mut client = replay_client(vec![Exchange {
+            method: "POST".into(),
+            url: "http://localhost/v1/chat/completions".into(),
+            request_body: None,
+            status: 429,
+            response_headers: vec![("retry-after".into(), "3".into())],
+            response_body: vec![Chunk::Text("slow down".into())],
+        }]);
+
+        let results: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(
+            client.send(&BotId::new("gpt-test"), &[], &[]),
+        ));
+        let error = &results[0].errors()[0];
+        assert_eq!(error.status_code(), Some(429));
+        assert_eq!(error.details(), Some("slow down"));
+    }
+}
@@ -3,6 +3,7 @@
 use crate::protocol::Tool;
 use crate::protocol::*;
 use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream};
+use crate::utils::cassette::Cassette;
 use reqwest::header::{HeaderMap, HeaderName};
 use std::{
     str::FromStr,
@@ -19,6 +20,7 @@ struct OpenAiImageClientInner {
     url: String,
     client: reqwest::Client,
     headers: HeaderMap,
+    cassette: Option<Cassette>,
 }
 
 /// Specific OpenAI client to hit image generation endpoints.
@@ -44,6 +46,7 @@ impl OpenAiImageClient {
             url,
             client,
             headers,
+            cassette: None,
         };
 
         OpenAiImageClient(Arc::new(RwLock::new(inner)))
@@ -71,6 +74,14 @@ impl OpenAiImageClient {
         self.0.read().unwrap().url.clone()
     }
 
+    /// Records the HTTP exchanges of this client into the cassette, or replays them
+    /// from it, depending on its mode. See [`crate::utils::cassette`].
+    ///
+    /// Images given by URL are still downloaded from it.
+    pub fn set_cassette(&mut self, cassette: Option<Cassette>) {
+        self.0.write().unwrap().cassette = cassette;
+    }
+
     async fn generate_image(
         &self,
         bot_id: &BotId,
@@ -100,26 +111,32 @@ impl OpenAiImageClient {
             .headers(inner.headers.clone())
             .json(&request_json);
 
-        let response = request.send().await.map_err(|e| {
-            ClientError::new_with_source(
-                ClientErrorKind::Network,
-                format!(
-                    "Could not send request to {url}. Verify your connection and the server status."
-                ),
-                Some(e),
-            )
-        })?;
+        let response = crate::utils::cassette::send(request, inner.cassette.as_ref())
+            .await
+            .map_err(|e| {
+                ClientError::new_with_source(
+                    ClientErrorKind::Network,
+                    format!(
+                        "Could not send request to {url}. Verify your connection and the server status."
+                    ),
+                    Some(e),
+                )
+            })?;
 
         let status = response.status();
+        let headers = response.headers().clone();
         let text = response.text().await.unwrap_or_default();
 
         if !status.is_success() {
-            return Err(ClientError::new(
+            let error = ClientError::new(
                 ClientErrorKind::Response,
                 format!(
                     "Request to {url} failed with status {} and content: {}",
                     status, text
                 ),
+            );
+            return Err(crate::utils::http::with_response_info(
+                error, status, &headers,
             ));
         }
 
@@ -216,13 +233,20 @@ impl BotClient for OpenAiImageClient {
         let client = inner.client;
         let base_url = inner.url;
         let headers = inner.headers;
+        let cassette = inner.cassette;
 
         Box::pin(async move {
             let capabilities = BotCapabilities::new().with_capabilities([BotCapability::TextInput]);
 
-            crate::utils::openai::get_bots(&client, &base_url, headers, &capabilities)
-                .await
-                .into()
+            crate::utils::openai::get_bots(
+                &client,
+                &base_url,
+                headers,
+                &capabilities,
+                cassette.as_ref(),
+            )
+            .await
+            .into()
         })
     }
 
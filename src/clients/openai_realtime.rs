@@ -4,9 +4,10 @@ use base64::{Engine as _, engine::general_purpose};
 use chrono::{Local, Timelike};
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crate::protocol::*;
-use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream, spawn};
+use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream, sleep, spawn};
 use futures::StreamExt;
 
 // Realtime enabled + not wasm
@@ -260,6 +261,8 @@ pub struct OpenAiRealtimeClient {
     api_key: Option<String>,
     system_prompt: Option<String>,
     tools_enabled: bool,
+    reconnect_attempts: u32,
+    reconnect_delay: Duration,
 }
 
 impl OpenAiRealtimeClient {
@@ -269,6 +272,8 @@ impl OpenAiRealtimeClient {
             api_key: None,
             system_prompt: None,
             tools_enabled: true, // Default to enabled for backward compatibility
+            reconnect_attempts: 5,
+            reconnect_delay: Duration::from_millis(500),
         }
     }
 
@@ -285,6 +290,19 @@ impl OpenAiRealtimeClient {
     pub fn set_tools_enabled(&mut self, enabled: bool) {
         self.tools_enabled = enabled;
     }
+
+    /// Sets how many times a dropped session tries to reconnect before giving up.
+    ///
+    /// Set to `0` to end the session as soon as the connection is lost.
+    pub fn set_reconnect_attempts(&mut self, attempts: u32) {
+        self.reconnect_attempts = attempts;
+    }
+
+    /// Sets the wait before the first reconnection attempt, doubled on each
+    /// following attempt.
+    pub fn set_reconnect_delay(&mut self, delay: Duration) {
+        self.reconnect_delay = delay;
+    }
 }
 
 fn get_time_of_day() -> String {
@@ -354,10 +372,460 @@ fn default_instructions() -> String {
     )
 }
 
+/// Longest wait between two reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+#[cfg(not(target_arch = "wasm32"))]
+type WsStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Fixed parameters of a session, used to build the messages sent to the server.
+#[derive(Clone, Debug)]
+struct SessionSettings {
+    model: String,
+    system_prompt: Option<String>,
+    tools: Vec<Tool>,
+}
+
+impl SessionSettings {
+    fn session_update(&self, voice: &str, transcription_model: &str) -> OpenAiRealtimeMessage {
+        // Convert MCP tools to OpenAI realtime format
+        let realtime_tools: Vec<serde_json::Value> = self
+            .tools
+            .iter()
+            .map(|tool| {
+                // Use the same conversion logic as the regular OpenAI client
+                let mut parameters_map = (*tool.input_schema).clone();
+
+                // Ensure additionalProperties is set to false as required by OpenAI
+                parameters_map.insert(
+                    "additionalProperties".to_string(),
+                    serde_json::Value::Bool(false),
+                );
+
+                // Ensure properties field exists for object schemas
+                if parameters_map.get("type")
+                    == Some(&serde_json::Value::String("object".to_string()))
+                {
+                    if !parameters_map.contains_key("properties") {
+                        parameters_map.insert(
+                            "properties".to_string(),
+                            serde_json::Value::Object(serde_json::Map::new()),
+                        );
+                    }
+                }
+
+                let parameters = serde_json::Value::Object(parameters_map);
+
+                serde_json::json!({
+                    "type": "function",
+                    "name": tool.name,
+                    "description": tool.description.as_deref().unwrap_or(""),
+                    "parameters": parameters
+                })
+            })
+            .collect();
+
+        let instructions = self
+            .system_prompt
+            .as_ref()
+            .map(|s| instruction_with_context(s.clone()))
+            .unwrap_or_else(|| default_instructions());
+
+        let session_config = SessionConfig {
+            modalities: vec!["text".to_string(), "audio".to_string()],
+            instructions,
+            voice: voice.to_string(),
+            model: self.model.clone(),
+            input_audio_format: "pcm16".to_string(),
+            output_audio_format: "pcm16".to_string(),
+            input_audio_transcription: Some(TranscriptionConfig {
+                model: transcription_model.to_string(),
+            }),
+            input_audio_noise_reduction: Some(NoiseReductionConfig {
+                noise_reduction_type: "far_field".to_string(),
+            }),
+            turn_detection: Some(TurnDetectionConfig {
+                detection_type: "server_vad".to_string(),
+                threshold: 0.5,
+                prefix_padding_ms: 300,
+                silence_duration_ms: 200,
+                interrupt_response: true,
+                create_response: true,
+            }),
+            tools: realtime_tools,
+            tool_choice: if self.tools.is_empty() {
+                "none".to_string()
+            } else {
+                "auto".to_string()
+            },
+            temperature: 0.8,
+            max_response_output_tokens: Some(4096),
+        };
+
+        OpenAiRealtimeMessage::SessionUpdate {
+            session: session_config,
+        }
+    }
+
+    fn greeting_response(&self) -> OpenAiRealtimeMessage {
+        let mut instructions = self
+            .system_prompt
+            .as_ref()
+            .map(|s| instruction_with_context(s.clone()))
+            .unwrap_or_else(|| default_instructions());
+
+        instructions.push_str("\n  Start with a short, casual greeting (3-8 words).");
+
+        let response_config = ResponseConfig {
+            modalities: vec!["text".to_string(), "audio".to_string()],
+            instructions: Some(instructions),
+            voice: None,
+            output_audio_format: Some("pcm16".to_string()),
+            tools: vec![],
+            tool_choice: "none".to_string(),
+            temperature: Some(0.8),
+            max_output_tokens: Some(4096),
+        };
+
+        OpenAiRealtimeMessage::ResponseCreate {
+            response: response_config,
+        }
+    }
+
+    /// Translates a command into the messages to send to the server, keeping
+    /// track of what is needed to restore the session later.
+    ///
+    /// [`RealtimeCommand::StopSession`] is not translated.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn command_messages(
+        &self,
+        command: RealtimeCommand,
+        snapshot: &mut SessionSnapshot,
+    ) -> Vec<OpenAiRealtimeMessage> {
+        match command {
+            RealtimeCommand::UpdateSessionConfig {
+                voice,
+                transcription_model,
+            } => {
+                log::debug!(
+                    "Updating session config with voice: {}, transcription: {}",
+                    voice,
+                    transcription_model
+                );
+                let message = self.session_update(&voice, &transcription_model);
+                snapshot.session_config = Some((voice, transcription_model));
+                vec![message]
+            }
+            RealtimeCommand::CreateGreetingResponse => {
+                log::debug!("Creating AI greeting response");
+                vec![self.greeting_response()]
+            }
+            RealtimeCommand::SendAudio(audio_data) => {
+                let base64_audio = general_purpose::STANDARD.encode(&audio_data);
+                vec![OpenAiRealtimeMessage::InputAudioBufferAppend {
+                    audio: base64_audio,
+                }]
+            }
+            RealtimeCommand::SendText(text) => {
+                let item = text_item("user", ContentPart::InputText { text });
+                snapshot.items.push(item.clone());
+                vec![OpenAiRealtimeMessage::ConversationItemCreate { item }]
+            }
+            RealtimeCommand::Interrupt => {
+                // Send truncate message to interrupt current response
+                vec![OpenAiRealtimeMessage::InputAudioBufferCommit]
+            }
+            RealtimeCommand::SendFunctionCallResult { call_id, output } => {
+                let item = FunctionCallOutputItem {
+                    item_type: "function_call_output".to_string(),
+                    call_id,
+                    output,
+                };
+                let item = serde_json::to_value(item).unwrap();
+                snapshot.items.push(item.clone());
+
+                // Trigger a new response after sending function results
+                let response_config = ResponseConfig {
+                    modalities: vec!["text".to_string(), "audio".to_string()],
+                    instructions: None,
+                    voice: None,
+                    output_audio_format: Some("pcm16".to_string()),
+                    tools: vec![],
+                    tool_choice: "auto".to_string(),
+                    temperature: Some(0.8),
+                    max_output_tokens: Some(4096),
+                };
+
+                vec![
+                    OpenAiRealtimeMessage::ConversationItemCreate { item },
+                    OpenAiRealtimeMessage::ResponseCreate {
+                        response: response_config,
+                    },
+                ]
+            }
+            RealtimeCommand::StopSession => vec![],
+        }
+    }
+}
+
+/// What is needed to restore a session over a new connection.
+#[derive(Debug, Default)]
+struct SessionSnapshot {
+    /// Last `(voice, transcription_model)` requested, re-sent after reconnecting.
+    session_config: Option<(String, String)>,
+    /// Conversation items so far, replayed after reconnecting.
+    ///
+    /// `Null` keeps the place of a user transcript that is still on its way.
+    items: Vec<serde_json::Value>,
+}
+
+impl SessionSnapshot {
+    /// Starts a snapshot from a previous conversation.
+    fn from_messages(messages: &[crate::protocol::Message]) -> Self {
+        let mut items = Vec::new();
+
+        for message in messages {
+            let text = message.content.text.clone();
+            match &message.from {
+                EntityId::User if !text.is_empty() => {
+                    items.push(text_item("user", ContentPart::InputText { text }));
+                }
+                EntityId::Bot(_) => {
+                    if !text.is_empty() {
+                        items.push(text_item("assistant", ContentPart::Text { text }));
+                    }
+
+                    for tool_call in &message.content.tool_calls {
+                        items.push(function_call_item(
+                            &tool_call.name,
+                            &tool_call.id,
+                            &serde_json::to_string(&tool_call.arguments).unwrap_or_default(),
+                        ));
+                    }
+                }
+                EntityId::Tool => {
+                    for tool_result in &message.content.tool_results {
+                        let item = FunctionCallOutputItem {
+                            item_type: "function_call_output".to_string(),
+                            call_id: tool_result.tool_call_id.clone(),
+                            output: tool_result.content.clone(),
+                        };
+                        items.push(serde_json::to_value(item).unwrap());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            session_config: None,
+            items,
+        }
+    }
+
+    /// Keeps track of the conversation items created by the server.
+    fn record_event(&mut self, event: &RealtimeEvent) {
+        match event {
+            RealtimeEvent::SpeechStopped => {
+                self.items.push(serde_json::Value::Null);
+            }
+            RealtimeEvent::UserTranscriptCompleted(transcript, _) => {
+                // Audio can't be replayed, but its transcript can.
+                let item = text_item(
+                    "user",
+                    ContentPart::InputText {
+                        text: transcript.clone(),
+                    },
+                );
+
+                match self.items.iter_mut().find(|i| i.is_null()) {
+                    Some(placeholder) => *placeholder = item,
+                    None => self.items.push(item),
+                }
+            }
+            RealtimeEvent::AudioTranscriptCompleted(transcript, _) => {
+                self.items.push(text_item(
+                    "assistant",
+                    ContentPart::Text {
+                        text: transcript.clone(),
+                    },
+                ));
+            }
+            RealtimeEvent::FunctionCallRequest {
+                name,
+                call_id,
+                arguments,
+            } => {
+                // The same call may be reported more than once.
+                let is_known = self
+                    .items
+                    .iter()
+                    .any(|i| i["type"] == "function_call" && i["call_id"] == call_id.as_str());
+
+                if !is_known {
+                    self.items
+                        .push(function_call_item(name, call_id, arguments));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Messages that recreate the conversation on a new connection.
+    fn restore_messages(
+        &self,
+        settings: &SessionSettings,
+        include_session_config: bool,
+    ) -> Vec<OpenAiRealtimeMessage> {
+        let mut messages = Vec::new();
+
+        if include_session_config && let Some((voice, transcription_model)) = &self.session_config {
+            messages.push(settings.session_update(voice, transcription_model));
+        }
+
+        messages.extend(
+            self.items
+                .iter()
+                .filter(|item| !item.is_null())
+                .map(|item| OpenAiRealtimeMessage::ConversationItemCreate { item: item.clone() }),
+        );
+
+        messages
+    }
+}
+
+fn text_item(role: &str, content: ContentPart) -> serde_json::Value {
+    let item = ConversationItem {
+        id: None,
+        item_type: "message".to_string(),
+        status: None,
+        role: Some(role.to_string()),
+        content: Some(vec![content]),
+    };
+    serde_json::to_value(item).unwrap()
+}
+
+fn function_call_item(name: &str, call_id: &str, arguments: &str) -> serde_json::Value {
+    serde_json::json!({
+        "type": "function_call",
+        "name": name,
+        "call_id": call_id,
+        "arguments": arguments,
+    })
+}
+
+/// Delay before the given reconnection attempt (starting at 1), doubling each time.
+fn reconnect_delay(base: Duration, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    base.saturating_mul(factor).min(MAX_RECONNECT_DELAY)
+}
+
+/// Translates a server message into the event exposed to the UI, if relevant.
+#[cfg(not(target_arch = "wasm32"))]
+fn event_from_response(response: OpenAiRealtimeResponse) -> Option<RealtimeEvent> {
+    match response {
+        OpenAiRealtimeResponse::SessionCreated { .. } => Some(RealtimeEvent::SessionReady),
+        OpenAiRealtimeResponse::ResponseAudioDelta { delta, .. } => {
+            if let Ok(audio_bytes) = general_purpose::STANDARD.decode(&delta) {
+                Some(RealtimeEvent::AudioData(audio_bytes))
+            } else {
+                None
+            }
+        }
+        OpenAiRealtimeResponse::ResponseAudioTranscriptDelta { delta, .. } => {
+            Some(RealtimeEvent::AudioTranscript(delta))
+        }
+        OpenAiRealtimeResponse::ResponseAudioTranscriptDone {
+            transcript,
+            item_id,
+            ..
+        } => Some(RealtimeEvent::AudioTranscriptCompleted(transcript, item_id)),
+        OpenAiRealtimeResponse::ConversationItemInputAudioTranscriptionCompleted {
+            transcript,
+            item_id,
+            ..
+        } => Some(RealtimeEvent::UserTranscriptCompleted(transcript, item_id)),
+        OpenAiRealtimeResponse::InputAudioBufferSpeechStarted { .. } => {
+            Some(RealtimeEvent::SpeechStarted)
+        }
+        OpenAiRealtimeResponse::InputAudioBufferSpeechStopped { .. } => {
+            Some(RealtimeEvent::SpeechStopped)
+        }
+        OpenAiRealtimeResponse::ResponseDone { response } => {
+            // Check if the response contains function calls
+            let mut function_call_event = None;
+            for output_item in &response.output {
+                if let ResponseOutputItem::FunctionCall {
+                    name,
+                    call_id,
+                    arguments,
+                    ..
+                } = output_item
+                {
+                    function_call_event = Some(RealtimeEvent::FunctionCallRequest {
+                        name: name.clone(),
+                        call_id: call_id.clone(),
+                        arguments: arguments.clone(),
+                    });
+                    break;
+                }
+            }
+            function_call_event.or(Some(RealtimeEvent::ResponseCompleted))
+        }
+        OpenAiRealtimeResponse::Error { error } => Some(RealtimeEvent::Error(error.message)),
+        OpenAiRealtimeResponse::ResponseFunctionCallArgumentsDone {
+            call_id,
+            name,
+            arguments,
+            ..
+        } => Some(RealtimeEvent::FunctionCallRequest {
+            name,
+            call_id,
+            arguments,
+        }),
+        _ => None,
+    }
+}
+
+/// Takes the commands queued while reconnecting, dropping the ones gone stale by
+/// then, like audio spoken during the outage.
+///
+/// Gives `None` if the session was stopped meanwhile.
+#[cfg(not(target_arch = "wasm32"))]
+fn take_queued_commands(
+    command_receiver: &mut futures::channel::mpsc::UnboundedReceiver<RealtimeCommand>,
+) -> Option<Vec<RealtimeCommand>> {
+    let mut queued = Vec::new();
+    loop {
+        match command_receiver.try_next() {
+            Ok(Some(RealtimeCommand::StopSession)) | Ok(None) => return None,
+            Ok(Some(
+                RealtimeCommand::SendAudio(_)
+                | RealtimeCommand::Interrupt
+                | RealtimeCommand::CreateGreetingResponse,
+            )) => {}
+            Ok(Some(command)) => queued.push(command),
+            // Nothing else queued.
+            Err(_) => return Some(queued),
+        }
+    }
+}
+
+/// How a connection of a session ended.
+#[cfg(not(target_arch = "wasm32"))]
+enum ConnectionEnd {
+    /// The session was stopped on purpose.
+    Stopped,
+    /// The connection was lost for the given reason.
+    Lost(String),
+}
+
 impl OpenAiRealtimeClient {
     fn create_realtime_session(
         &self,
         bot_id: &BotId,
+        messages: &[crate::protocol::Message],
         tools: &[Tool],
     ) -> BoxPlatformSendFuture<'static, ClientResult<RealtimeChannel>> {
         let address = self.address.clone();
@@ -371,17 +839,23 @@ impl OpenAiRealtimeClient {
         };
 
         let bot_id = bot_id.clone();
-        // Only include tools if they are enabled for this client
-        let tools = if self.tools_enabled {
-            tools.to_vec()
-        } else {
-            Vec::new()
+        let settings = SessionSettings {
+            model: bot_id.id().to_string(),
+            system_prompt: self.system_prompt.clone(),
+            // Only include tools if they are enabled for this client
+            tools: if self.tools_enabled {
+                tools.to_vec()
+            } else {
+                Vec::new()
+            },
         };
-        let system_prompt = self.system_prompt.clone();
+        let snapshot = SessionSnapshot::from_messages(messages);
+        let reconnect_attempts = self.reconnect_attempts;
+        let reconnect_base_delay = self.reconnect_delay;
+
         let future = async move {
             let (event_sender, event_receiver) = futures::channel::mpsc::unbounded();
-            let (command_sender, mut command_receiver) = futures::channel::mpsc::unbounded();
-            let is_connected = Arc::new(Mutex::new(true));
+            let (command_sender, command_receiver) = futures::channel::mpsc::unbounded();
 
             #[cfg(not(target_arch = "wasm32"))]
             {
@@ -405,351 +879,61 @@ impl OpenAiRealtimeClient {
                     }
                 };
 
-                let (mut write, mut read) = ws_stream.split();
                 log::debug!("WebSocket connection created");
 
-                // Spawn task to handle incoming messages
-                let event_sender_clone = event_sender.clone();
-                let is_connected_read = is_connected.clone();
+                let event_sender = event_sender.clone();
                 spawn(async move {
-                    while let Some(msg) = read.next().await {
-                        match msg {
-                            Ok(WsMessage::Text(text)) => {
-                                log::debug!("Received WebSocket message: {}", text);
-                                // log::info!("Received WebSocket message: {}", text);
-                                if let Ok(response) =
-                                    serde_json::from_str::<OpenAiRealtimeResponse>(&text)
-                                {
-                                    let event = match response {
-                                        OpenAiRealtimeResponse::SessionCreated { .. } => {
-                                            Some(RealtimeEvent::SessionReady)
-                                        }
-                                        OpenAiRealtimeResponse::ResponseAudioDelta {
-                                            delta,
-                                            ..
-                                        } => {
-                                            if let Ok(audio_bytes) =
-                                                general_purpose::STANDARD.decode(&delta)
-                                            {
-                                                Some(RealtimeEvent::AudioData(audio_bytes))
-                                            } else {
-                                                None
-                                            }
-                                        }
-                                        OpenAiRealtimeResponse::ResponseAudioTranscriptDelta {
-                                            delta,
-                                            ..
-                                        } => Some(RealtimeEvent::AudioTranscript(delta)),
-                                        OpenAiRealtimeResponse::ResponseAudioTranscriptDone {
-                                            transcript,
-                                            item_id,
-                                            ..
-                                        } => Some(RealtimeEvent::AudioTranscriptCompleted(transcript, item_id)),
-                                        OpenAiRealtimeResponse::ConversationItemInputAudioTranscriptionCompleted {
-                                            transcript,
-                                            item_id,
-                                            ..
-                                        } => Some(RealtimeEvent::UserTranscriptCompleted(transcript, item_id)),
-                                        OpenAiRealtimeResponse::InputAudioBufferSpeechStarted {
-                                            ..
-                                        } => Some(RealtimeEvent::SpeechStarted),
-                                        OpenAiRealtimeResponse::InputAudioBufferSpeechStopped {
-                                            ..
-                                        } => Some(RealtimeEvent::SpeechStopped),
-                                        OpenAiRealtimeResponse::ResponseDone { response } => {
-                                            // Check if the response contains function calls
-                                            let mut function_call_event = None;
-                                            for output_item in &response.output {
-                                                if let ResponseOutputItem::FunctionCall {
-                                                    name,
-                                                    call_id,
-                                                    arguments,
-                                                    ..
-                                                } = output_item
-                                                {
-                                                    function_call_event = Some(RealtimeEvent::FunctionCallRequest {
-                                                        name: name.clone(),
-                                                        call_id: call_id.clone(),
-                                                        arguments: arguments.clone(),
-                                                    });
-                                                    break;
-                                                }
-                                            }
-                                            function_call_event.or(Some(RealtimeEvent::ResponseCompleted))
-                                        }
-                                        OpenAiRealtimeResponse::Error { error } => {
-                                            Some(RealtimeEvent::Error(error.message))
-                                        }
-                                        OpenAiRealtimeResponse::ResponseFunctionCallArgumentsDone { item_id: _, output_index: _, sequence_number: _, call_id, name, arguments } => {
-                                            Some(RealtimeEvent::FunctionCallRequest {
-                                                name,
-                                                call_id: call_id,
-                                                arguments,
-                                            })
-                                        },
-                                        _ => None,
-                                    };
-
-                                    if let Some(event) = event {
-                                        let _ = event_sender_clone.unbounded_send(event);
-                                    }
-                                }
-                            }
-                            Ok(WsMessage::Close(_)) => {
-                                log::info!("WebSocket closed by server");
-                                *is_connected_read.lock().unwrap() = false;
-                                let _ = event_sender_clone.unbounded_send(RealtimeEvent::Error(
-                                    "Connection closed by server".to_string(),
-                                ));
-                                break;
+                    let mut command_receiver = command_receiver;
+                    let mut snapshot = snapshot;
+                    let mut ws_stream = ws_stream;
+                    let mut is_reconnection = false;
+
+                    loop {
+                        let end = Self::run_connection(
+                            ws_stream,
+                            &mut command_receiver,
+                            &event_sender,
+                            &settings,
+                            &mut snapshot,
+                            is_reconnection,
+                        )
+                        .await;
+
+                        let ConnectionEnd::Lost(reason) = end else {
+                            break;
+                        };
+
+                        log::warn!("Realtime connection lost: {}", reason);
+
+                        match Self::reconnect(
+                            &url_str,
+                            &api_key,
+                            reconnect_attempts,
+                            reconnect_base_delay,
+                            &event_sender,
+                        )
+                        .await
+                        {
+                            Some(stream) => {
+                                ws_stream = stream;
+                                is_reconnection = true;
                             }
-                            Err(e) => {
-                                log::error!("WebSocket read error: {}", e);
-                                *is_connected_read.lock().unwrap() = false;
-                                let _ = event_sender_clone.unbounded_send(RealtimeEvent::Error(
-                                    format!("Connection lost: {}", e),
-                                ));
+                            None => {
+                                let _ = event_sender.unbounded_send(RealtimeEvent::Error(reason));
                                 break;
                             }
-                            _ => {}
                         }
                     }
-                });
-
-                // Spawn task to handle outgoing commands
-                let is_connected_write = is_connected.clone();
-                let event_sender_write = event_sender.clone();
-                spawn(async move {
-                    let model = bot_id.id().to_string();
-
-                    // Helper macro to send messages with error handling
-                    // Note: This is a macro because Rust closures can't return futures
-                    // that borrow from the closure itself (lifetime issue)
-                    macro_rules! send_message {
-                        ($json:expr) => {{
-                            if let Err(e) = write.send(WsMessage::text($json)).await {
-                                log::error!("WebSocket send failed: {}", e);
-                                *is_connected_write.lock().unwrap() = false;
-                                let _ = event_sender_write.unbounded_send(RealtimeEvent::Error(
-                                    format!("Connection lost: {}", e),
-                                ));
-                                break;
-                            }
-                        }};
-                    }
-
-                    // Handle commands
-                    while let Some(command) = command_receiver.next().await {
-                        // Check if still connected before processing commands
-                        if !*is_connected_write.lock().unwrap() {
-                            log::warn!("Dropping command - connection lost");
-                            continue;
-                        }
-                        match command {
-                            RealtimeCommand::UpdateSessionConfig {
-                                voice,
-                                transcription_model,
-                            } => {
-                                log::debug!(
-                                    "Updating session config with voice: {}, transcription: {}",
-                                    voice,
-                                    transcription_model
-                                );
-                                // Convert MCP tools to OpenAI realtime format
-                                let realtime_tools: Vec<serde_json::Value> = tools.iter().map(|tool| {
-                                    // Use the same conversion logic as the regular OpenAI client
-                                    let mut parameters_map = (*tool.input_schema).clone();
-
-                                    // Ensure additionalProperties is set to false as required by OpenAI
-                                    parameters_map.insert(
-                                        "additionalProperties".to_string(),
-                                        serde_json::Value::Bool(false),
-                                    );
-
-                                    // Ensure properties field exists for object schemas
-                                    if parameters_map.get("type") == Some(&serde_json::Value::String("object".to_string())) {
-                                        if !parameters_map.contains_key("properties") {
-                                            parameters_map.insert(
-                                                "properties".to_string(),
-                                                serde_json::Value::Object(serde_json::Map::new()),
-                                            );
-                                        }
-                                    }
-
-                                    let parameters = serde_json::Value::Object(parameters_map);
-
-                                    serde_json::json!({
-                                        "type": "function",
-                                        "name": tool.name,
-                                        "description": tool.description.as_deref().unwrap_or(""),
-                                        "parameters": parameters
-                                    })
-                                }).collect();
-
-                                let instructions = system_prompt
-                                    .as_ref()
-                                    .map(|s| instruction_with_context(s.clone()))
-                                    .unwrap_or_else(|| default_instructions());
-
-                                let session_config = SessionConfig {
-                                    modalities: vec!["text".to_string(), "audio".to_string()],
-                                    instructions,
-                                    voice: voice.clone(),
-                                    model: model.clone(),
-                                    input_audio_format: "pcm16".to_string(),
-                                    output_audio_format: "pcm16".to_string(),
-                                    input_audio_transcription: Some(TranscriptionConfig {
-                                        model: transcription_model,
-                                    }),
-                                    input_audio_noise_reduction: Some(NoiseReductionConfig {
-                                        noise_reduction_type: "far_field".to_string(),
-                                    }),
-                                    turn_detection: Some(TurnDetectionConfig {
-                                        detection_type: "server_vad".to_string(),
-                                        threshold: 0.5,
-                                        prefix_padding_ms: 300,
-                                        silence_duration_ms: 200,
-                                        interrupt_response: true,
-                                        create_response: true,
-                                    }),
-                                    tools: realtime_tools,
-                                    tool_choice: if tools.is_empty() {
-                                        "none".to_string()
-                                    } else {
-                                        "auto".to_string()
-                                    },
-                                    temperature: 0.8,
-                                    max_response_output_tokens: Some(4096),
-                                };
-
-                                let session_message = OpenAiRealtimeMessage::SessionUpdate {
-                                    session: session_config,
-                                };
-
-                                if let Ok(json) = serde_json::to_string(&session_message) {
-                                    log::debug!("Sending session update: {}", json);
-                                    send_message!(json);
-                                }
-                            }
-                            RealtimeCommand::CreateGreetingResponse => {
-                                log::debug!("Creating AI greeting response");
-                                let mut instructions = system_prompt
-                                    .as_ref()
-                                    .map(|s| instruction_with_context(s.clone()))
-                                    .unwrap_or_else(|| default_instructions());
-
-                                instructions.push_str(
-                                    "\n  Start with a short, casual greeting (3-8 words).",
-                                );
-
-                                let response_config = ResponseConfig {
-                                    modalities: vec!["text".to_string(), "audio".to_string()],
-                                    instructions: Some(instructions),
-                                    voice: None,
-                                    output_audio_format: Some("pcm16".to_string()),
-                                    tools: vec![],
-                                    tool_choice: "none".to_string(),
-                                    temperature: Some(0.8),
-                                    max_output_tokens: Some(4096),
-                                };
-
-                                let message = OpenAiRealtimeMessage::ResponseCreate {
-                                    response: response_config,
-                                };
-
-                                if let Ok(json) = serde_json::to_string(&message) {
-                                    log::debug!("Sending greeting response: {}", json);
-                                    send_message!(json);
-                                }
-                            }
-                            RealtimeCommand::SendAudio(audio_data) => {
-                                let base64_audio = general_purpose::STANDARD.encode(&audio_data);
-                                let message = OpenAiRealtimeMessage::InputAudioBufferAppend {
-                                    audio: base64_audio,
-                                };
-                                if let Ok(json) = serde_json::to_string(&message) {
-                                    // log::debug!("Sending audio data: {}", json);
-                                    send_message!(json);
-                                }
-                            }
-                            RealtimeCommand::SendText(text) => {
-                                let item = ConversationItem {
-                                    id: None,
-                                    item_type: "message".to_string(),
-                                    status: None,
-                                    role: Some("user".to_string()),
-                                    content: Some(vec![ContentPart::InputText { text }]),
-                                };
-                                let message = OpenAiRealtimeMessage::ConversationItemCreate {
-                                    item: serde_json::to_value(item).unwrap(),
-                                };
-                                if let Ok(json) = serde_json::to_string(&message) {
-                                    log::debug!("Sending text message: {}", json);
-                                    send_message!(json);
-                                }
-                            }
-                            RealtimeCommand::Interrupt => {
-                                // Send truncate message to interrupt current response
-                                let message = OpenAiRealtimeMessage::InputAudioBufferCommit;
-                                if let Ok(json) = serde_json::to_string(&message) {
-                                    log::debug!("Sending interrupt message: {}", json);
-                                    send_message!(json);
-                                }
-                            }
-                            RealtimeCommand::SendFunctionCallResult { call_id, output } => {
-                                let item = FunctionCallOutputItem {
-                                    item_type: "function_call_output".to_string(),
-                                    call_id,
-                                    output,
-                                };
-                                let message = OpenAiRealtimeMessage::ConversationItemCreate {
-                                    item: serde_json::to_value(item).unwrap(),
-                                };
-                                if let Ok(json) = serde_json::to_string(&message) {
-                                    log::debug!("Sending function call result: {}", json);
-                                    send_message!(json);
-                                }
-
-                                // Trigger a new response after sending function results
-                                let response_config = ResponseConfig {
-                                    modalities: vec!["text".to_string(), "audio".to_string()],
-                                    instructions: None,
-                                    voice: None,
-                                    output_audio_format: Some("pcm16".to_string()),
-                                    tools: vec![],
-                                    tool_choice: "auto".to_string(),
-                                    temperature: Some(0.8),
-                                    max_output_tokens: Some(4096),
-                                };
-
-                                let response_message = OpenAiRealtimeMessage::ResponseCreate {
-                                    response: response_config,
-                                };
 
-                                if let Ok(json) = serde_json::to_string(&response_message) {
-                                    log::debug!(
-                                        "Triggering response after function call: {}",
-                                        json
-                                    );
-                                    send_message!(json);
-                                }
-                            }
-                            RealtimeCommand::StopSession => {
-                                // Close the WebSocket connection
-                                log::debug!("Closing WebSocket connection");
-                                *is_connected_write.lock().unwrap() = false;
-                                // Try to send close message but don't report errors since user initiated this
-                                let _ = write.send(WsMessage::Close(None)).await;
-                                break;
-                            }
-                        }
-                    }
+                    let _ = event_sender.unbounded_send(RealtimeEvent::SessionClosed);
                 });
             }
 
             #[cfg(target_arch = "wasm32")]
             {
                 // Fallback mock implementation when websocket feature is not enabled or on WASM
+                let _ = (settings, snapshot, reconnect_attempts, reconnect_base_delay);
+                let _ = command_receiver;
                 let mut event_sender_clone = event_sender.clone();
                 spawn(async move {
                     let _ = event_sender_clone.unbounded_send(RealtimeEvent::Error(
@@ -768,6 +952,130 @@ impl OpenAiRealtimeClient {
         Box::pin(future)
     }
 
+    /// Drives a single connection of a session until it is stopped or lost.
+    ///
+    /// The conversation recorded in the snapshot is recreated first, which is
+    /// how a session is resumed after reconnecting.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn run_connection(
+        ws_stream: WsStream,
+        command_receiver: &mut futures::channel::mpsc::UnboundedReceiver<RealtimeCommand>,
+        event_sender: &futures::channel::mpsc::UnboundedSender<RealtimeEvent>,
+        settings: &SessionSettings,
+        snapshot: &mut SessionSnapshot,
+        is_reconnection: bool,
+    ) -> ConnectionEnd {
+        use futures::future::{Either, select};
+
+        let (mut write, mut read) = ws_stream.split();
+
+        let queued = match is_reconnection {
+            true => match take_queued_commands(command_receiver) {
+                Some(queued) => queued,
+                None => {
+                    let _ = write.send(WsMessage::Close(None)).await;
+                    return ConnectionEnd::Stopped;
+                }
+            },
+            false => Vec::new(),
+        };
+
+        let mut messages = snapshot.restore_messages(settings, is_reconnection);
+        for command in queued {
+            messages.extend(settings.command_messages(command, snapshot));
+        }
+
+        for message in messages {
+            if let Ok(json) = serde_json::to_string(&message)
+                && let Err(e) = write.send(WsMessage::text(json)).await
+            {
+                return ConnectionEnd::Lost(format!("Connection lost: {}", e));
+            }
+        }
+
+        if is_reconnection {
+            let _ = event_sender.unbounded_send(RealtimeEvent::Reconnected);
+        }
+
+        loop {
+            match select(read.next(), command_receiver.next()).await {
+                Either::Left((message, _)) => match message {
+                    Some(Ok(WsMessage::Text(text))) => {
+                        log::debug!("Received WebSocket message: {}", text);
+                        let Ok(response) = serde_json::from_str::<OpenAiRealtimeResponse>(&text)
+                        else {
+                            continue;
+                        };
+
+                        // The session was already announced, `Reconnected` is enough.
+                        if is_reconnection
+                            && matches!(response, OpenAiRealtimeResponse::SessionCreated { .. })
+                        {
+                            continue;
+                        }
+
+                        if let Some(event) = event_from_response(response) {
+                            snapshot.record_event(&event);
+                            let _ = event_sender.unbounded_send(event);
+                        }
+                    }
+                    Some(Ok(WsMessage::Close(_))) | None => {
+                        log::info!("WebSocket closed by server");
+                        return ConnectionEnd::Lost("Connection closed by server".to_string());
+                    }
+                    Some(Err(e)) => {
+                        log::error!("WebSocket read error: {}", e);
+                        return ConnectionEnd::Lost(format!("Connection lost: {}", e));
+                    }
+                    _ => {}
+                },
+                Either::Right((command, _)) => {
+                    let command = match command {
+                        Some(RealtimeCommand::StopSession) | None => {
+                            // Close the WebSocket connection
+                            log::debug!("Closing WebSocket connection");
+                            // Try to send close message but don't report errors since user initiated this
+                            let _ = write.send(WsMessage::Close(None)).await;
+                            return ConnectionEnd::Stopped;
+                        }
+                        Some(command) => command,
+                    };
+
+                    for message in settings.command_messages(command, snapshot) {
+                        if let Ok(json) = serde_json::to_string(&message)
+                            && let Err(e) = write.send(WsMessage::text(json)).await
+                        {
+                            log::error!("WebSocket send failed: {}", e);
+                            return ConnectionEnd::Lost(format!("Connection lost: {}", e));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tries to connect again, waiting longer after each failed attempt.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn reconnect(
+        url_str: &str,
+        api_key: &str,
+        attempts: u32,
+        base_delay: Duration,
+        event_sender: &futures::channel::mpsc::UnboundedSender<RealtimeEvent>,
+    ) -> Option<WsStream> {
+        for attempt in 1..=attempts {
+            let _ = event_sender.unbounded_send(RealtimeEvent::Reconnecting { attempt });
+            sleep(reconnect_delay(base_delay, attempt)).await;
+
+            match Self::connect_with_redirects(url_str, api_key, 5).await {
+                Ok((ws_stream, _)) => return Some(ws_stream),
+                Err(e) => log::warn!("Reconnection attempt {} failed: {}", attempt, e),
+            }
+        }
+
+        None
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     fn build_websocket_request(
         url_str: &str,
@@ -1031,6 +1339,21 @@ impl OpenAiRealtimeClient {
     }
 }
 
+impl RealtimeClient for OpenAiRealtimeClient {
+    fn start(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[crate::protocol::Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendFuture<'static, ClientResult<RealtimeChannel>> {
+        self.create_realtime_session(bot_id, messages, tools)
+    }
+
+    fn clone_box(&self) -> Box<dyn RealtimeClient> {
+        Box::new(self.clone())
+    }
+}
+
 impl BotClient for OpenAiRealtimeClient {
     fn send(
         &mut self,
@@ -1039,7 +1362,7 @@ impl BotClient for OpenAiRealtimeClient {
         tools: &[Tool],
     ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
         // For realtime, we create a session and return the upgrade in the message content
-        let future = self.create_realtime_session(bot_id, tools);
+        let future = self.create_realtime_session(bot_id, &[], tools);
 
         let stream = async_stream::stream! {
             match future.await.into_result() {
@@ -1140,3 +1463,231 @@ impl BotClient for OpenAiRealtimeClient {
         Box::new(self.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Reads messages from the client until the given amount of text ones arrived.
+    async fn read_texts(
+        ws: &mut tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
+        count: usize,
+    ) -> Vec<serde_json::Value> {
+        let mut texts = Vec::new();
+        while texts.len() < count {
+            match ws.next().await {
+                Some(Ok(WsMessage::Text(text))) => {
+                    texts.push(serde_json::from_str(&text).unwrap());
+                }
+                Some(Ok(_)) => {}
+                other => panic!("unexpected message from client: {other:?}"),
+            }
+        }
+        texts
+    }
+
+    async fn next_event(
+        receiver: &mut futures::channel::mpsc::UnboundedReceiver<RealtimeEvent>,
+    ) -> RealtimeEvent {
+        tokio::time::timeout(Duration::from_secs(5), receiver.next())
+            .await
+            .expect("timed out waiting for an event")
+            .expect("event stream ended")
+    }
+
+    #[test]
+    fn reconnects_and_resumes_session() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            // Stand-in server that drops the first connection after the session is set up.
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = format!("ws://{}", listener.local_addr().unwrap());
+
+            let server = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let first = read_texts(&mut ws, 3).await;
+                drop(ws);
+
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                ws.send(WsMessage::text(
+                    r#"{"type":"session.created","session":{}}"#,
+                ))
+                .await
+                .unwrap();
+                let second = read_texts(&mut ws, 3).await;
+                (first, second, ws)
+            });
+
+            let mut client = OpenAiRealtimeClient::new(address);
+            client.set_reconnect_delay(Duration::from_millis(10));
+
+            let history = vec![crate::protocol::Message {
+                from: EntityId::User,
+                content: MessageContent {
+                    text: "Hello".to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }];
+
+            let channel = RealtimeClient::start(&mut client, &BotId::new("test"), &history, &[])
+                .await
+                .into_result()
+                .unwrap();
+            let mut events = channel.event_receiver.lock().unwrap().take().unwrap();
+
+            channel
+                .command_sender
+                .unbounded_send(RealtimeCommand::UpdateSessionConfig {
+                    voice: "alloy".to_string(),
+                    transcription_model: "whisper-1".to_string(),
+                })
+                .unwrap();
+            channel
+                .command_sender
+                .unbounded_send(RealtimeCommand::SendText("How are you?".to_string()))
+                .unwrap();
+
+            assert!(matches!(
+                next_event(&mut events).await,
+                RealtimeEvent::Reconnecting { attempt: 1 }
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                RealtimeEvent::Reconnected
+            ));
+
+            let (first, second, _ws) = server.await.unwrap();
+            let types = |messages: &[serde_json::Value]| {
+                messages
+                    .iter()
+                    .map(|m| m["type"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(
+                types(&first),
+                [
+                    "conversation.item.create",
+                    "session.update",
+                    "conversation.item.create"
+                ]
+            );
+            assert_eq!(
+                types(&second),
+                [
+                    "session.update",
+                    "conversation.item.create",
+                    "conversation.item.create"
+                ]
+            );
+            assert_eq!(second[0]["session"]["voice"], "alloy");
+            assert_eq!(second[1]["item"]["content"][0]["text"], "Hello");
+            assert_eq!(second[2]["item"]["content"][0]["text"], "How are you?");
+
+            // The new session is not announced again.
+            channel
+                .command_sender
+                .unbounded_send(RealtimeCommand::StopSession)
+                .unwrap();
+            assert!(matches!(
+                next_event(&mut events).await,
+                RealtimeEvent::SessionClosed
+            ));
+        });
+    }
+
+    #[test]
+    fn gives_up_after_reconnect_attempts() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = format!("ws://{}", listener.local_addr().unwrap());
+
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                // Drop the connection and stop listening.
+                drop(ws);
+            });
+
+            let mut client = OpenAiRealtimeClient::new(address);
+            client.set_reconnect_attempts(2);
+            client.set_reconnect_delay(Duration::from_millis(10));
+
+            let channel = RealtimeClient::start(&mut client, &BotId::new("test"), &[], &[])
+                .await
+                .into_result()
+                .unwrap();
+            let mut events = channel.event_receiver.lock().unwrap().take().unwrap();
+
+            assert!(matches!(
+                next_event(&mut events).await,
+                RealtimeEvent::Reconnecting { attempt: 1 }
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                RealtimeEvent::Reconnecting { attempt: 2 }
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                RealtimeEvent::Error(_)
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                RealtimeEvent::SessionClosed
+            ));
+        });
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_max() {
+        let base = Duration::from_millis(500);
+        assert_eq!(reconnect_delay(base, 1), base);
+        assert_eq!(reconnect_delay(base, 2), base * 2);
+        assert_eq!(reconnect_delay(base, 3), base * 4);
+        assert_eq!(reconnect_delay(base, 30), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn stale_commands_queued_while_reconnecting_are_dropped() {
+        let (sender, mut receiver) = futures::channel::mpsc::unbounded();
+        for command in [
+            RealtimeCommand::SendAudio(vec![0; 4]),
+            RealtimeCommand::Interrupt,
+            RealtimeCommand::SendFunctionCallResult {
+                call_id: "call-1".to_string(),
+                output: "42".to_string(),
+            },
+            RealtimeCommand::SendAudio(vec![0; 4]),
+        ] {
+            sender.unbounded_send(command).unwrap();
+        }
+
+        let queued = take_queued_commands(&mut receiver).unwrap();
+        assert!(matches!(
+            &queued[..],
+            [RealtimeCommand::SendFunctionCallResult { call_id, .. }] if call_id == "call-1"
+        ));
+        assert!(take_queued_commands(&mut receiver).unwrap().is_empty());
+
+        sender
+            .unbounded_send(RealtimeCommand::SendAudio(vec![0; 4]))
+            .unwrap();
+        sender.unbounded_send(RealtimeCommand::StopSession).unwrap();
+        assert!(take_queued_commands(&mut receiver).is_none());
+    }
+}
@@ -1,6 +1,7 @@
 //! Client based on the OpenAI one, but hits the speech-to-text API instead.
 
 use crate::protocol::*;
+use crate::utils::cassette::Cassette;
 use reqwest::header::{HeaderMap, HeaderName};
 use std::{
     str::FromStr,
@@ -12,6 +13,7 @@ struct OpenAiSttClientInner {
     url: String,
     client: reqwest::Client,
     headers: HeaderMap,
+    cassette: Option<Cassette>,
 }
 
 /// Specific OpenAI client to hit speech-to-text endpoints.
@@ -33,6 +35,7 @@ impl OpenAiSttClient {
             url,
             client,
             headers,
+            cassette: None,
         };
 
         OpenAiSttClient(Arc::new(RwLock::new(inner)))
@@ -59,6 +62,15 @@ impl OpenAiSttClient {
         self.0.read().unwrap().url.clone()
     }
 
+    /// Records the HTTP exchanges of this client into the cassette, or replays them
+    /// from it, depending on its mode. See [`crate::utils::cassette`].
+    ///
+    /// The audio is sent as a multipart form, which is not recorded, so replays match
+    /// transcriptions by their order.
+    pub fn set_cassette(&mut self, cassette: Option<Cassette>) {
+        self.0.write().unwrap().cassette = cassette;
+    }
+
     async fn transcribe_audio(
         &self,
         bot_id: &BotId,
@@ -112,26 +124,32 @@ impl OpenAiSttClient {
             .headers(inner.headers.clone())
             .multipart(form);
 
-        let response = request.send().await.map_err(|e| {
-            ClientError::new_with_source(
-                ClientErrorKind::Network,
-                format!(
-                    "Could not send request to {url}. Verify your connection and the server status."
-                ),
-                Some(e),
-            )
-        })?;
+        let response = crate::utils::cassette::send(request, inner.cassette.as_ref())
+            .await
+            .map_err(|e| {
+                ClientError::new_with_source(
+                    ClientErrorKind::Network,
+                    format!(
+                        "Could not send request to {url}. Verify your connection and the server status."
+                    ),
+                    Some(e),
+                )
+            })?;
 
         let status = response.status();
+        let headers = response.headers().clone();
         let text = response.text().await.unwrap_or_default();
 
         if !status.is_success() {
-            return Err(ClientError::new(
+            let error = ClientError::new(
                 ClientErrorKind::Response,
                 format!(
                     "Request to {url} failed with status {} and content: {}",
                     status, text
                 ),
+            );
+            return Err(crate::utils::http::with_response_info(
+                error, status, &headers,
             ));
         }
 
@@ -167,14 +185,21 @@ impl BotClient for OpenAiSttClient {
         let client = inner.client;
         let base_url = inner.url;
         let headers = inner.headers;
+        let cassette = inner.cassette;
 
         Box::pin(async move {
             let capabilities =
                 BotCapabilities::new().with_capabilities([BotCapability::AttachmentInput]);
 
-            crate::utils::openai::get_bots(&client, &base_url, headers, &capabilities)
-                .await
-                .into()
+            crate::utils::openai::get_bots(
+                &client,
+                &base_url,
+                headers,
+                &capabilities,
+                cassette.as_ref(),
+            )
+            .await
+            .into()
         })
     }
 
@@ -200,3 +225,38 @@ impl BotClient for OpenAiSttClient {
         Box::new(self.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::cassette::{Chunk, Exchange};
+
+    #[test]
+    fn replays_transcriptions() {
+        let mut client = OpenAiSttClient::new("http://localhost/v1".into());
+        client.set_cassette(Some(Cassette::replay(vec![Exchange {
+            method: "POST".into(),
+            url: "http://localhost/v1/audio/transcriptions".into(),
+            request_body: None,
+            status: 200,
+            response_headers: vec![],
+            response_body: vec![Chunk::Text(r#"{"text":"Hello there"}"#.into())],
+        }])));
+
+        let messages = [Message {
+            content: MessageContent {
+                attachments: vec![Attachment::from_bytes(
+                    "hello.wav".into(),
+                    Some("audio/wav".into()),
+                    b"RIFF",
+                )],
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let results: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(
+            client.send(&BotId::new("whisper-1"), &messages, &[]),
+        ));
+        assert_eq!(results[0].value().unwrap().text, "Hello there");
+    }
+}
@@ -0,0 +1,549 @@
+use crate::protocol::*;
+use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream, sleep};
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Window of the per-minute limits.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// How many times a send is queued again after being rate limited by the provider.
+const MAX_RATE_LIMITED_ATTEMPTS: u32 = 5;
+
+/// Wait used when the provider rate limits without saying for how long.
+const DEFAULT_RATE_LIMITED_WAIT: Duration = Duration::from_secs(1);
+
+/// Token bucket refilled continuously along the [`WINDOW`].
+#[derive(Debug, Clone)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn new(capacity: u64, now: DateTime<Utc>) -> Self {
+        let capacity = capacity.max(1) as f64;
+        Bucket {
+            capacity,
+            available: capacity,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: DateTime<Utc>) {
+        let elapsed = (now - self.updated_at).to_std().unwrap_or_default();
+        let refilled = self.capacity * elapsed.as_secs_f64() / WINDOW.as_secs_f64();
+        self.available = (self.available + refilled).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    /// How long until the given amount is available, or `None` if it already is.
+    ///
+    /// Amounts bigger than the capacity only wait for a full bucket.
+    fn wait_time(&mut self, amount: f64, now: DateTime<Utc>) -> Option<Duration> {
+        self.refill(now);
+        let missing = amount.min(self.capacity) - self.available;
+        (missing > 0.0).then(|| WINDOW.mul_f64(missing / self.capacity))
+    }
+
+    /// Takes the given amount, which may leave the bucket in debt.
+    fn take(&mut self, amount: f64, now: DateTime<Utc>) {
+        self.refill(now);
+        self.available -= amount;
+    }
+}
+
+/// One of the limits (requests or tokens), configured by the user or learned from
+/// the provider.
+#[derive(Debug, Default)]
+struct Limiter {
+    configured: Option<u64>,
+    bucket: Option<Bucket>,
+    /// Set when the provider reports the limit as exhausted.
+    blocked_until: Option<DateTime<Utc>>,
+}
+
+impl Limiter {
+    fn configure(&mut self, limit: Option<u64>, now: DateTime<Utc>) {
+        self.configured = limit;
+        self.bucket = limit.map(|limit| Bucket::new(limit, now));
+    }
+
+    fn wait_time(&mut self, amount: f64, now: DateTime<Utc>) -> Option<Duration> {
+        let blocked = self
+            .blocked_until
+            .and_then(|until| (until - now).to_std().ok())
+            .filter(|wait| !wait.is_zero());
+
+        let bucket = self
+            .bucket
+            .as_mut()
+            .and_then(|bucket| bucket.wait_time(amount, now));
+
+        blocked.max(bucket)
+    }
+
+    fn take(&mut self, amount: f64, now: DateTime<Utc>) {
+        if let Some(bucket) = self.bucket.as_mut() {
+            bucket.take(amount, now);
+        }
+    }
+
+    fn block_for(&mut self, duration: Duration, now: DateTime<Utc>) {
+        let until = now + duration;
+        self.blocked_until = Some(
+            self.blocked_until
+                .map_or(until, |current| current.max(until)),
+        );
+    }
+
+    /// Adjusts to what the provider reported.
+    fn learn(
+        &mut self,
+        limit: Option<u64>,
+        remaining: Option<u64>,
+        reset: Option<Duration>,
+        now: DateTime<Utc>,
+    ) {
+        // What the user configured always takes precedence.
+        if self.configured.is_none()
+            && let Some(limit) = limit
+            && self
+                .bucket
+                .as_ref()
+                .is_none_or(|b| b.capacity != limit as f64)
+        {
+            self.bucket = Some(Bucket::new(limit, now));
+        }
+
+        if let Some(remaining) = remaining {
+            if let Some(bucket) = self.bucket.as_mut() {
+                bucket.refill(now);
+                bucket.available = bucket.available.min(remaining as f64);
+            }
+
+            if remaining == 0 {
+                self.block_for(reset.unwrap_or(DEFAULT_RATE_LIMITED_WAIT), now);
+            }
+        }
+    }
+}
+
+/// Semaphore for the streams running at the same time.
+#[derive(Debug, Default)]
+struct Streams {
+    max: Option<usize>,
+    in_flight: usize,
+    waiters: VecDeque<futures::channel::oneshot::Sender<()>>,
+}
+
+impl Streams {
+    fn wake_next(&mut self) {
+        while let Some(waiter) = self.waiters.pop_front() {
+            // Waiters that gave up are skipped.
+            if waiter.send(()).is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+struct Inner<C: BotClient> {
+    client: C,
+    requests: Limiter,
+    tokens: Limiter,
+    streams: Streams,
+    learn_limits: bool,
+}
+
+impl<C: BotClient> Inner<C> {
+    fn learn_limits(&mut self) {
+        if !self.learn_limits {
+            return;
+        }
+
+        let Some(limits) = self.client.rate_limits() else {
+            return;
+        };
+
+        let now = Utc::now();
+        self.requests.learn(
+            limits.limit_requests,
+            limits.remaining_requests,
+            limits.reset_requests,
+            now,
+        );
+        self.tokens.learn(
+            limits.limit_tokens,
+            limits.remaining_tokens,
+            limits.reset_tokens,
+            now,
+        );
+    }
+}
+
+/// Releases its stream slot when dropped.
+struct StreamPermit<C: BotClient> {
+    inner: Arc<Mutex<Inner<C>>>,
+}
+
+impl<C: BotClient> Drop for StreamPermit<C> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.streams.in_flight -= 1;
+        inner.streams.wake_next();
+    }
+}
+
+async fn acquire_stream<C: BotClient>(inner: &Arc<Mutex<Inner<C>>>) -> StreamPermit<C> {
+    loop {
+        let waiter = {
+            let mut guard = inner.lock().unwrap();
+            let streams = &mut guard.streams;
+            if streams.max.is_none_or(|max| streams.in_flight < max) {
+                streams.in_flight += 1;
+                return StreamPermit {
+                    inner: Arc::clone(inner),
+                };
+            }
+
+            let (tx, rx) = futures::channel::oneshot::channel();
+            streams.waiters.push_back(tx);
+            rx
+        };
+
+        let _ = waiter.await;
+    }
+}
+
+/// Waits until a request of the given size fits in the limits, and takes it.
+async fn acquire_capacity<C: BotClient>(inner: &Arc<Mutex<Inner<C>>>, tokens: u64) {
+    loop {
+        let wait = {
+            let mut inner = inner.lock().unwrap();
+            let now = Utc::now();
+            let wait = inner
+                .requests
+                .wait_time(1.0, now)
+                .max(inner.tokens.wait_time(tokens as f64, now));
+
+            let Some(wait) = wait else {
+                inner.requests.take(1.0, now);
+                inner.tokens.take(tokens as f64, now);
+                return;
+            };
+
+            wait
+        };
+
+        log::debug!("Rate limit reached, waiting {wait:?} before sending");
+        sleep(wait.max(Duration::from_millis(1))).await;
+    }
+}
+
+/// Rough token count of some content, as they are not known before sending.
+///
+/// Uses the common approximation of 4 characters per token and ignores attachments.
+fn estimate_content_tokens(content: &MessageContent) -> u64 {
+    let tool_calls: usize = content
+        .tool_calls
+        .iter()
+        .map(|call| call.name.len() + serde_json::to_string(&call.arguments).map_or(0, |a| a.len()))
+        .sum();
+    let tool_results: usize = content.tool_results.iter().map(|r| r.content.len()).sum();
+
+    let chars = content.text.len() + content.reasoning.len() + tool_calls + tool_results;
+    chars.div_ceil(4) as u64
+}
+
+fn estimate_request_tokens(messages: &[Message], tools: &[Tool]) -> u64 {
+    let messages: u64 = messages
+        .iter()
+        .map(|m| estimate_content_tokens(&m.content))
+        .sum();
+    let tools = tools
+        .iter()
+        .map(|t| serde_json::to_string(t).map_or(0, |t| t.len()))
+        .sum::<usize>()
+        .div_ceil(4) as u64;
+
+    messages + tools
+}
+
+/// Utility wrapper client that keeps the underlying client within rate limits.
+///
+/// Sends are queued (not failed) until they fit in the configured requests per
+/// minute, tokens per minute and concurrent streams. Tokens are estimated from
+/// the text of the request, and the response is counted once it completes.
+///
+/// When the underlying client exposes [`BotClient::rate_limits`], the limits reported
+/// by the provider are learned and respected too. Sends rate limited by the provider
+/// anyway (status 429) are queued again before any content is streamed.
+///
+/// Clones share the same limits, so a single client (or its clones) can be given to
+/// many [`crate::controllers::chat::ChatController`]s using the same key.
+pub struct RateLimitedClient<C: BotClient> {
+    inner: Arc<Mutex<Inner<C>>>,
+}
+
+impl<C: BotClient> Clone for RateLimitedClient<C> {
+    fn clone(&self) -> Self {
+        RateLimitedClient {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<C: BotClient> RateLimitedClient<C> {
+    /// Wraps the client without limits of its own. Limits reported by the provider
+    /// are still learned.
+    pub fn new(client: C) -> Self {
+        RateLimitedClient {
+            inner: Arc::new(Mutex::new(Inner {
+                client,
+                requests: Limiter::default(),
+                tokens: Limiter::default(),
+                streams: Streams::default(),
+                learn_limits: true,
+            })),
+        }
+    }
+
+    /// Limits how many requests can be sent per minute.
+    pub fn set_requests_per_minute(&mut self, limit: Option<u64>) {
+        self.inner
+            .lock()
+            .unwrap()
+            .requests
+            .configure(limit, Utc::now());
+    }
+
+    pub fn requests_per_minute(&self) -> Option<u64> {
+        self.inner.lock().unwrap().requests.configured
+    }
+
+    /// Limits how many tokens (estimated) can be used per minute.
+    pub fn set_tokens_per_minute(&mut self, limit: Option<u64>) {
+        self.inner
+            .lock()
+            .unwrap()
+            .tokens
+            .configure(limit, Utc::now());
+    }
+
+    pub fn tokens_per_minute(&self) -> Option<u64> {
+        self.inner.lock().unwrap().tokens.configured
+    }
+
+    /// Limits how many responses can be streamed at the same time.
+    pub fn set_max_concurrent_streams(&mut self, max: Option<usize>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.streams.max = max;
+
+        // Let them check again, as there may be room now.
+        for waiter in inner.streams.waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+
+    pub fn max_concurrent_streams(&self) -> Option<usize> {
+        self.inner.lock().unwrap().streams.max
+    }
+
+    /// Whether to respect the limits reported by the provider. Enabled by default.
+    pub fn set_learn_limits(&mut self, enabled: bool) {
+        self.inner.lock().unwrap().learn_limits = enabled;
+    }
+
+    pub fn learn_limits(&self) -> bool {
+        self.inner.lock().unwrap().learn_limits
+    }
+}
+
+impl<C: BotClient> From<C> for RateLimitedClient<C> {
+    fn from(client: C) -> Self {
+        RateLimitedClient::new(client)
+    }
+}
+
+impl<C: BotClient + 'static> BotClient for RateLimitedClient<C> {
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        Box::new(self.clone())
+    }
+
+    fn rate_limits(&self) -> Option<RateLimits> {
+        self.inner.lock().unwrap().client.rate_limits()
+    }
+
+    /// Not limited, as listing bots is rare and usually not counted by providers.
+    fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        self.inner.lock().unwrap().client.bots()
+    }
+
+    fn send(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        let inner = self.inner.clone();
+        let bot_id = bot_id.clone();
+        let messages = messages.to_vec();
+        let tools = tools.to_vec();
+
+        let stream = async_stream::stream! {
+            let request_tokens = estimate_request_tokens(&messages, &tools);
+            let _permit = acquire_stream(&inner).await;
+
+            let mut attempt = 0;
+            'attempts: loop {
+                attempt += 1;
+                acquire_capacity(&inner, request_tokens).await;
+
+                let stream = inner.lock().unwrap().client.send(&bot_id, &messages, &tools);
+                let mut has_streamed = false;
+                let mut has_learned = false;
+                let mut last_content = None;
+
+                for await result in stream {
+                    // Headers are known as soon as the response starts.
+                    if !has_learned {
+                        inner.lock().unwrap().learn_limits();
+                        has_learned = true;
+                    }
+
+                    let has_content = result.value().is_some_and(|c| !c.is_empty());
+                    let is_rate_limited = result
+                        .errors()
+                        .iter()
+                        .any(|e| e.status_code() == Some(429));
+
+                    if !has_streamed
+                        && !has_content
+                        && is_rate_limited
+                        && attempt < MAX_RATE_LIMITED_ATTEMPTS
+                    {
+                        let wait = result
+                            .errors()
+                            .iter()
+                            .filter_map(|e| e.retry_after())
+                            .max()
+                            .unwrap_or(DEFAULT_RATE_LIMITED_WAIT);
+
+                        log::warn!("Send to {bot_id} was rate limited, queuing it again in {wait:?}");
+                        inner.lock().unwrap().requests.block_for(wait, Utc::now());
+                        continue 'attempts;
+                    }
+
+                    has_streamed |= has_content;
+                    last_content = result.value().cloned();
+                    yield result;
+                }
+
+                if let Some(content) = last_content {
+                    let response_tokens = estimate_content_tokens(&content) as f64;
+                    inner.lock().unwrap().tokens.take(response_tokens, Utc::now());
+                }
+
+                break;
+            }
+        };
+
+        Box::pin(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Client that takes a while to answer, after failing the given amount of
+    /// sends with a 429.
+    fn slow(rate_limited: usize) -> MockClient {
+        MockClient::responding(move |call| match call.number > rate_limited {
+            true => MockReply::text("done").with_delay(Duration::from_millis(10)),
+            false => MockReply::error(
+                ClientError::new(ClientErrorKind::Response, "slow down".into())
+                    .with_status_code(429)
+                    .with_retry_after(Duration::from_millis(5)),
+            ),
+        })
+    }
+
+    /// Runs the future in a runtime able to sleep.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    async fn send_text(client: &mut RateLimitedClient<MockClient>) -> String {
+        let stream = client.send(&BotId::new("bot"), &[], &[]);
+        let results: Vec<_> = futures::StreamExt::collect(stream).await;
+        let result = results.into_iter().last().unwrap();
+        assert!(!result.has_errors());
+        result.into_value().unwrap().text
+    }
+
+    #[test]
+    fn limits_concurrent_streams() {
+        let slow = slow(0);
+        let mut client = RateLimitedClient::new(slow.clone());
+        client.set_max_concurrent_streams(Some(2));
+
+        let mut clients = [client.clone(), client.clone(), client.clone(), client];
+        block_on(futures::future::join_all(clients.iter_mut().map(send_text)));
+
+        assert_eq!(slow.max_in_flight(), 2);
+    }
+
+    #[test]
+    fn queues_again_when_rate_limited() {
+        let slow = slow(2);
+        let mut client = RateLimitedClient::new(slow.clone());
+
+        assert_eq!(block_on(send_text(&mut client)), "done");
+        assert_eq!(slow.call_count(), 3);
+    }
+
+    #[test]
+    fn waits_for_reported_reset() {
+        let slow = slow(0);
+        slow.set_rate_limits(Some(RateLimits {
+            remaining_requests: Some(0),
+            reset_requests: Some(Duration::from_millis(50)),
+            ..Default::default()
+        }));
+        let mut client = RateLimitedClient::new(slow);
+
+        let started = std::time::Instant::now();
+        block_on(async {
+            send_text(&mut client).await;
+            send_text(&mut client).await;
+        });
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn buckets_refill_along_the_window() {
+        let now = Utc::now();
+        let mut bucket = Bucket::new(60, now);
+
+        assert_eq!(bucket.wait_time(60.0, now), None);
+        bucket.take(60.0, now);
+        assert_eq!(bucket.wait_time(1.0, now), Some(Duration::from_secs(1)));
+
+        let later = now + Duration::from_secs(30);
+        assert_eq!(bucket.wait_time(30.0, later), None);
+        // Bigger than the capacity only waits for a full bucket.
+        assert_eq!(
+            bucket.wait_time(1000.0, later),
+            Some(Duration::from_secs(30))
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use crate::protocol::*;
+use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream, sleep};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How to retry operations failing with a certain [`ClientErrorKind`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times to retry after the first attempt. `0` disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on each following one.
+    pub base_delay: Duration,
+    /// Upper bound for the delay between attempts.
+    ///
+    /// If the server asks to wait longer than this (with `Retry-After`), the
+    /// error is returned instead of retrying.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, base_delay: Duration) -> Self {
+        RetryPolicy {
+            max_retries,
+            base_delay,
+            max_delay: Duration::from_secs(30),
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn never() -> Self {
+        RetryPolicy::new(0, Duration::ZERO)
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Delay before the given retry (starting at 1), or `None` if it should not happen.
+    fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if retry > self.max_retries {
+            return None;
+        }
+
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_delay).then_some(retry_after);
+        }
+
+        let factor = 2u32.saturating_pow(retry - 1);
+        let backoff = self.base_delay.saturating_mul(factor).min(self.max_delay);
+
+        // "Equal jitter": keep half of the backoff and randomize the other half, so
+        // clients failing at the same time don't retry at the same time.
+        let half = backoff / 2;
+        let jitter = half.mul_f64(random_unit());
+        Some(half + jitter)
+    }
+}
+
+/// Random number in `[0, 1)`, good enough for jitter without pulling a dependency.
+fn random_unit() -> f64 {
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// If the errors of a failed attempt are worth retrying, returns the policy to use.
+fn retry_policy(
+    policies: &HashMap<ClientErrorKind, RetryPolicy>,
+    errors: &[ClientError],
+) -> Option<(RetryPolicy, Option<Duration>)> {
+    let error = errors.first()?;
+    let policy = *policies.get(&error.kind())?;
+
+    // Most HTTP errors are not going to be fixed by trying again.
+    if let Some(status_code) = error.status_code()
+        && !(status_code == 408 || status_code == 429 || status_code >= 500)
+    {
+        return None;
+    }
+
+    let retry_after = errors.iter().filter_map(|e| e.retry_after()).max();
+    Some((policy, retry_after))
+}
+
+struct Inner<C: BotClient> {
+    client: C,
+    policies: HashMap<ClientErrorKind, RetryPolicy>,
+}
+
+/// Utility wrapper client that retries failed operations of the underlying client.
+///
+/// Both `bots` and `send` are retried with exponential backoff and jitter, honoring
+/// the `Retry-After` hint from the server when available. In `send`, retrying only
+/// happens before any content has been streamed, so partial responses are never
+/// replaced.
+///
+/// By default, network errors and response errors with a transient status code
+/// (408, 429 and 5xx) are retried up to 3 times. Use [`RetryClient::set_policy`]
+/// to change this for each [`ClientErrorKind`].
+pub struct RetryClient<C: BotClient> {
+    inner: Arc<Mutex<Inner<C>>>,
+}
+
+impl<C: BotClient> Clone for RetryClient<C> {
+    fn clone(&self) -> Self {
+        RetryClient {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<C: BotClient> RetryClient<C> {
+    pub fn new(client: C) -> Self {
+        let default_policy = RetryPolicy::new(3, Duration::from_millis(500));
+
+        let mut policies = HashMap::new();
+        policies.insert(ClientErrorKind::Network, default_policy);
+        policies.insert(ClientErrorKind::Response, default_policy);
+
+        RetryClient {
+            inner: Arc::new(Mutex::new(Inner { client, policies })),
+        }
+    }
+
+    /// Sets how to retry operations failing with the given kind of error.
+    pub fn set_policy(&mut self, kind: ClientErrorKind, policy: RetryPolicy) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.policies.insert(kind, policy);
+    }
+
+    /// The policy used for the given kind of error. Never retries if not set.
+    pub fn policy(&self, kind: ClientErrorKind) -> RetryPolicy {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .policies
+            .get(&kind)
+            .copied()
+            .unwrap_or_else(RetryPolicy::never)
+    }
+}
+
+impl<C: BotClient> From<C> for RetryClient<C> {
+    fn from(client: C) -> Self {
+        RetryClient::new(client)
+    }
+}
+
+impl<C: BotClient + 'static> BotClient for RetryClient<C> {
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        Box::new(self.clone())
+    }
+
+    fn rate_limits(&self) -> Option<RateLimits> {
+        self.inner.lock().unwrap().client.rate_limits()
+    }
+
+    fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let mut retry = 0;
+            loop {
+                let future = inner.lock().unwrap().client.bots();
+                let result = future.await;
+
+                if result.has_value() || !result.has_errors() {
+                    return result;
+                }
+
+                retry += 1;
+                let delay = retry_policy(&inner.lock().unwrap().policies, result.errors())
+                    .and_then(|(policy, retry_after)| policy.delay(retry, retry_after));
+
+                let Some(delay) = delay else {
+                    return result;
+                };
+
+                log::warn!("Retrying bots request in {delay:?} (retry {retry})");
+                sleep(delay).await;
+            }
+        })
+    }
+
+    fn send(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        let inner = self.inner.clone();
+        let bot_id = bot_id.clone();
+        let messages = messages.to_vec();
+        let tools = tools.to_vec();
+
+        let stream = async_stream::stream! {
+            let mut retry = 0;
+            'attempts: loop {
+                let stream = inner.lock().unwrap().client.send(&bot_id, &messages, &tools);
+                let mut has_streamed = false;
+
+                for await result in stream {
+                    if !has_streamed && result.has_errors() {
+                        let has_content = result.value().is_some_and(|c| !c.is_empty());
+                        let delay = if has_content {
+                            None
+                        } else {
+                            retry += 1;
+                            retry_policy(&inner.lock().unwrap().policies, result.errors())
+                                .and_then(|(policy, retry_after)| policy.delay(retry, retry_after))
+                        };
+
+                        if let Some(delay) = delay {
+                            log::warn!("Retrying send to {bot_id} in {delay:?} (retry {retry})");
+                            sleep(delay).await;
+                            continue 'attempts;
+                        }
+                    }
+
+                    if result.value().is_some_and(|c| !c.is_empty()) {
+                        has_streamed = true;
+                    }
+
+                    yield result;
+                }
+
+                break;
+            }
+        };
+
+        Box::pin(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Client failing a given amount of times before answering.
+    fn flaky(failures: usize, error: ClientError) -> MockClient {
+        MockClient::responding(move |call| match call.number > failures {
+            true => MockReply::text("done"),
+            false => MockReply::error(error.clone()),
+        })
+    }
+
+    fn fast_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy::new(max_retries, Duration::from_millis(1))
+    }
+
+    /// Runs the future in a runtime able to sleep.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn collect_send(client: &mut RetryClient<MockClient>) -> Vec<ClientResult<MessageContent>> {
+        let stream = client.send(&BotId::new("bot"), &[], &[]);
+        block_on(futures::StreamExt::collect::<Vec<_>>(stream))
+    }
+
+    #[test]
+    fn retries_network_errors_until_success() {
+        let flaky = flaky(
+            2,
+            ClientError::new(ClientErrorKind::Network, "reset".into()),
+        );
+        let mut client = RetryClient::new(flaky.clone());
+        client.set_policy(ClientErrorKind::Network, fast_policy(3));
+
+        let results = collect_send(&mut client);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].value().unwrap().text, "done");
+        assert_eq!(flaky.call_count(), 3);
+
+        let bots = block_on(client.bots());
+        assert!(!bots.has_errors());
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let flaky = flaky(
+            10,
+            ClientError::new(ClientErrorKind::Network, "reset".into()),
+        );
+        let mut client = RetryClient::new(flaky.clone());
+        client.set_policy(ClientErrorKind::Network, fast_policy(2));
+
+        let results = collect_send(&mut client);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].has_errors());
+        assert_eq!(flaky.call_count(), 3);
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let flaky = flaky(
+            1,
+            ClientError::new(ClientErrorKind::Response, "bad request".into()).with_status_code(400),
+        );
+        let mut client = RetryClient::new(flaky.clone());
+        client.set_policy(ClientErrorKind::Response, fast_policy(3));
+
+        let results = collect_send(&mut client);
+        assert!(results[0].has_errors());
+        assert_eq!(flaky.call_count(), 1);
+    }
+
+    #[test]
+    fn retries_rate_limits_honoring_retry_after() {
+        let flaky = flaky(
+            1,
+            ClientError::new(ClientErrorKind::Response, "slow down".into())
+                .with_status_code(429)
+                .with_retry_after(Duration::from_millis(5)),
+        );
+        let mut client = RetryClient::new(flaky.clone());
+        client.set_policy(ClientErrorKind::Response, fast_policy(1));
+
+        let results = collect_send(&mut client);
+        assert_eq!(results[0].value().unwrap().text, "done");
+        assert_eq!(flaky.call_count(), 2);
+
+        // Waiting longer than allowed gives up instead.
+        let policy = fast_policy(1).with_max_delay(Duration::from_millis(1));
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(60))), None);
+    }
+
+    #[test]
+    fn does_not_retry_after_streaming_content() {
+        let flaky = MockClient::responding(|_| {
+            MockReply::text("partial")
+                .then_error(ClientError::new(ClientErrorKind::Network, "reset".into()))
+        });
+        let mut client = RetryClient::new(flaky.clone());
+        client.set_policy(ClientErrorKind::Network, fast_policy(3));
+
+        let results = collect_send(&mut client);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].value().unwrap().text, "partial");
+        assert!(results[1].has_errors());
+        assert_eq!(flaky.call_count(), 1);
+    }
+
+    #[test]
+    fn backoff_grows_within_jitter_bounds() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100));
+        for retry in 1..=5 {
+            let backoff = Duration::from_millis(100) * 2u32.pow(retry - 1);
+            let delay = policy.delay(retry, None).unwrap();
+            assert!(delay >= backoff / 2 && delay <= backoff);
+        }
+        assert_eq!(policy.delay(6, None), None);
+    }
+}
@@ -1,28 +1,54 @@
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use crate::protocol::*;
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
 
 #[derive(Clone)]
 struct Item {
     client: Box<dyn BotClient>,
     bots_result: Option<ClientResult<Vec<Bot>>>,
+    cached_at: Option<DateTime<Utc>>,
+    ttl: Option<Duration>,
+    enabled: bool,
+    refreshing: bool,
+}
+
+impl Item {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        let (Some(cached_at), Some(ttl)) = (self.cached_at, self.ttl) else {
+            return false;
+        };
+
+        chrono::Duration::from_std(ttl).is_ok_and(|ttl| cached_at + ttl <= now)
+    }
 }
 
 #[derive(Clone, Default)]
 struct Inner {
     items: HashMap<String, Item>,
+    subscribers: Vec<UnboundedSender<()>>,
+}
+
+impl Inner {
+    fn notify_bots_changed(&mut self) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(()).is_ok());
+    }
 }
 
 /// A client that can be composed from multiple subclients to interact with all of them as one.
 ///
 /// # Bot IDs
 ///
-/// [`BotId`]s are prefixed with the key used to insert the subclient. Take that into account when
-/// calling [`send`](BotClient::send) or when reading the list of bots from [`bots`](BotClient::bots).
+/// [`BotId`]s are prefixed with the key used to insert the subclient, as part of their
+/// [provider path](BotId::provider_path). Take that into account when calling
+/// [`send`](BotClient::send) or when reading the list of bots from [`bots`](BotClient::bots).
 ///
 /// If you are just forwarding an id that came from [`bots`](BotClient::bots), to the [`send`](BotClient::send)
 /// method, you don't need to worry.
@@ -37,9 +63,19 @@ struct Inner {
 /// the cached result contains errors.
 ///
 /// If a client is associated with a cached result, without errors, then its [`bots`](BotClient::bots) method
-/// will not be called again.
+/// will not be called again, unless a TTL was set for it with [`RouterClient::set_client_ttl`].
+/// Once expired, the stale result keeps being served while it's refreshed in the background
+/// (or awaited in place, without the `async-rt` feature).
+///
+/// You can also invalidate the cache explicitly to force a refresh.
+///
+/// Use [`RouterClient::bots_changes`] to know when the list of bots changed (e.g. to dispatch
+/// a `ChatTask::Load` so the chat controller picks up the new list).
 ///
-/// If you need to refresh the list of bots, you will need to invalidate the cache explicitly.
+/// # Disabling sub-clients
+///
+/// A sub-client can be disabled with [`RouterClient::set_client_enabled`] to hide its bots
+/// without removing it.
 #[derive(Clone, Default)]
 pub struct RouterClient {
     inner: Arc<Mutex<Inner>>,
@@ -56,7 +92,7 @@ impl BotClient for RouterClient {
             let mut value: Option<Vec<Bot>> = None;
             let mut errors = Vec::new();
 
-            for (key, item) in inner.items.iter() {
+            for (key, item) in inner.items.iter().filter(|(_, item)| item.enabled) {
                 if let Some(result) = &item.bots_result {
                     errors.extend(result.errors().iter().cloned());
 
@@ -109,8 +145,19 @@ impl BotClient for RouterClient {
 
                 me.cache_bots().await;
 
-                let mut client = match me.get_client_cloned(&key) {
-                    Some(c) => c,
+                let client = me.read_client(key, |c| c.clone_box());
+                let enabled = me.is_client_enabled(key);
+                let mut client = match client {
+                    Some(c) if enabled => c,
+                    Some(_) => {
+                        let err = ClientError::new(
+                            ClientErrorKind::Unknown,
+                            format!("The client for the given bot id is disabled: {:?}", bot_id),
+                        );
+                        let stream: BoxPlatformSendStream<_> =
+                            Box::pin(futures::stream::once(async move { err.into() }));
+                        return stream;
+                    }
                     None => {
                         let err = ClientError::new(
                             ClientErrorKind::Unknown,
@@ -165,14 +212,70 @@ impl RouterClient {
             Item {
                 client,
                 bots_result: None,
+                cached_at: None,
+                ttl: None,
+                enabled: true,
+                refreshing: false,
             },
         );
+        inner.notify_bots_changed();
     }
 
     /// Removes a client by the key used to insert it.
     pub fn remove_client(&self, key: impl AsRef<str>) {
         let mut inner = self.inner.lock().unwrap();
-        inner.items.remove(key.as_ref());
+        if inner.items.remove(key.as_ref()).is_some() {
+            inner.notify_bots_changed();
+        }
+    }
+
+    /// Sets for how long the bots of the client with the given key are cached.
+    ///
+    /// `None`, the default, caches them until invalidated.
+    pub fn set_client_ttl(&self, key: impl AsRef<str>, ttl: Option<Duration>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(item) = inner.items.get_mut(key.as_ref()) {
+            item.ttl = ttl;
+        }
+    }
+
+    /// The TTL of the bots cache for the client with the given key.
+    pub fn client_ttl(&self, key: impl AsRef<str>) -> Option<Duration> {
+        let inner = self.inner.lock().unwrap();
+        inner.items.get(key.as_ref()).and_then(|item| item.ttl)
+    }
+
+    /// Enables or disables the client with the given key.
+    ///
+    /// The bots of disabled clients are not listed nor fetched, and sending to them fails.
+    pub fn set_client_enabled(&self, key: impl AsRef<str>, enabled: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(item) = inner.items.get_mut(key.as_ref())
+            && item.enabled != enabled
+        {
+            item.enabled = enabled;
+            inner.notify_bots_changed();
+        }
+    }
+
+    /// If the client with the given key exists and is enabled.
+    pub fn is_client_enabled(&self, key: impl AsRef<str>) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .items
+            .get(key.as_ref())
+            .is_some_and(|item| item.enabled)
+    }
+
+    /// Returns a receiver notified each time the list of bots returned by this router
+    /// may have changed.
+    ///
+    /// This happens when clients are inserted, removed, enabled or disabled, and when
+    /// refreshing the cache gives a different list than before.
+    pub fn bots_changes(&self) -> UnboundedReceiver<()> {
+        let (sender, receiver) = unbounded();
+        self.inner.lock().unwrap().subscribers.push(sender);
+        receiver
     }
 
     /// Gets a client by the key used to insert it, cloning it.
@@ -209,51 +312,195 @@ impl RouterClient {
             .map(|item| f(&mut item.client))
     }
 
-    /// Caches the bots from all sub-clients that have not been cached yet, or that have errors.
+    /// Caches the bots from all enabled sub-clients that have not been cached yet, or
+    /// that have errors. Expired results are refreshed in the background.
     async fn cache_bots(&self) {
+        let now = Utc::now();
+
         // Collect entries quickly, before any async operation, to avoid retaining
         // the lock across await points.
-        // These entries are either uncached entries, or entries that contain errors.
-        let mut entries = self
-            .inner
-            .lock()
-            .unwrap()
-            .items
-            .iter()
-            .filter(|(_, item)| {
-                item.bots_result
-                    .as_ref()
-                    .map(|r| r.has_errors())
-                    .unwrap_or(true)
-            })
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect::<Vec<_>>();
+        let mut missing = Vec::new();
+        let mut expired = Vec::new();
+        for (key, item) in self.inner.lock().unwrap().items.iter_mut() {
+            if !item.enabled {
+                continue;
+            }
 
+            match &item.bots_result {
+                Some(result) if !result.has_errors() => {
+                    if item.is_expired(now) && !item.refreshing {
+                        item.refreshing = true;
+                        expired.push((key.clone(), item.client.clone_box()));
+                    }
+                }
+                _ => missing.push((key.clone(), item.client.clone_box())),
+            }
+        }
+
+        if !expired.is_empty() {
+            let me = self.clone();
+            let refresh = async move { me.fetch_bots(expired).await };
+
+            #[cfg(feature = "async-rt")]
+            crate::utils::asynchronous::spawn(refresh);
+
+            #[cfg(not(feature = "async-rt"))]
+            refresh.await;
+        }
+
+        self.fetch_bots(missing).await;
+    }
+
+    /// Fetches and caches the bots of the given entries, notifying if they changed.
+    async fn fetch_bots(&self, mut entries: Vec<(String, Box<dyn BotClient>)>) {
         if entries.is_empty() {
             return;
         }
 
-        let bots = entries.iter_mut().map(|(_, item)| item.client.bots());
+        let bots = entries.iter_mut().map(|(_, client)| client.bots());
         let bots = futures::future::join_all(bots).await;
 
         // Hold the lock to save the results.
         let mut inner = self.inner.lock().unwrap();
+        let mut changed = false;
         for ((key, _), result) in entries.iter().zip(bots.into_iter()) {
-            if let Some(entry) = inner.items.get_mut(key) {
-                entry.bots_result = Some(result);
+            let Some(item) = inner.items.get_mut(key) else {
+                continue;
+            };
+
+            item.cached_at = Some(Utc::now());
+            item.refreshing = false;
+
+            // A failed refresh keeps serving the stale bots until the next one.
+            if result.has_errors() && item.bots_result.as_ref().is_some_and(|r| !r.has_errors()) {
+                for error in result.errors() {
+                    log::warn!("Could not refresh the bots of {}: {}", key, error);
+                }
+                continue;
             }
+
+            let previous = item.bots_result.as_ref().and_then(|r| r.value());
+            changed |= previous != result.value();
+            item.bots_result = Some(result);
+        }
+
+        if changed {
+            inner.notify_bots_changed();
         }
     }
 
     /// Prefixes a bot id with the given key.
     pub fn prefix(key: &str, bot_id: &BotId) -> BotId {
-        BotId::new(format!("{}/{}", key, bot_id.as_str()))
+        bot_id.prefixed(key)
     }
 
     /// Unprefixes a bot id, returning the key and the original bot id.
+    ///
+    /// Ids without a provider path, like the ones persisted before [`BotId`] had one, are
+    /// split at their first `/` instead.
     pub fn unprefix(bot_id: &BotId) -> Option<(&str, BotId)> {
-        let s = bot_id.as_str();
-        let (key, id) = s.split_once('/')?;
+        if let Some(unprefixed) = bot_id.unprefixed() {
+            return Some(unprefixed);
+        }
+
+        let (key, id) = bot_id.id().split_once('/')?;
         Some((key, BotId::new(id)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Client listing the given bot ids, answering with the id it is sent to.
+    fn stub(ids: &[&str]) -> MockClient {
+        let client = MockClient::responding(|call| MockReply::text(call.bot_id.as_str()));
+        client.set_bot_ids(ids);
+        client
+    }
+
+    fn bot_ids(router: &mut RouterClient) -> Vec<String> {
+        let bots = futures::executor::block_on(router.bots())
+            .into_value()
+            .unwrap();
+        let mut ids: Vec<String> = bots.iter().map(|b| b.id.as_str().to_string()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn disabled_clients_are_hidden() {
+        let mut router = RouterClient::new();
+        router.insert_client("a", Box::new(stub(&["x"])));
+        router.insert_client("b", Box::new(stub(&["y"])));
+        assert_eq!(bot_ids(&mut router), ["a/x", "b/y"]);
+
+        let mut changes = router.bots_changes();
+        router.set_client_enabled("b", false);
+        assert!(changes.try_next().unwrap().is_some());
+        assert!(!router.is_client_enabled("b"));
+        assert_eq!(bot_ids(&mut router), ["a/x"]);
+
+        let stream = router.send(&BotId::new("b/y"), &[], &[]);
+        let results: Vec<_> = futures::executor::block_on(stream.collect());
+        assert!(results[0].has_errors());
+
+        router.set_client_enabled("b", true);
+        assert_eq!(bot_ids(&mut router), ["a/x", "b/y"]);
+    }
+
+    #[test]
+    fn nested_routers_keep_slashes_in_model_ids() {
+        let inner = RouterClient::new();
+        inner.insert_client("openrouter", Box::new(stub(&["openai/gpt-4o"])));
+        let mut outer = RouterClient::new();
+        outer.insert_client("remote", Box::new(inner));
+
+        let bots = futures::executor::block_on(outer.bots())
+            .into_value()
+            .unwrap();
+        let bot_id = &bots[0].id;
+        assert_eq!(bot_id.id(), "openai/gpt-4o");
+        assert_eq!(
+            bot_id.provider_path().collect::<Vec<_>>(),
+            ["remote", "openrouter"]
+        );
+
+        let stream = outer.send(bot_id, &[], &[]);
+        let results: Vec<_> = futures::executor::block_on(stream.collect());
+        assert_eq!(results[0].value().unwrap().text, "openai/gpt-4o");
+    }
+
+    #[test]
+    fn bots_are_cached_without_ttl() {
+        let mut router = RouterClient::new();
+        let client = stub(&["x"]);
+        router.insert_client("a", Box::new(client.clone()));
+        assert_eq!(bot_ids(&mut router), ["a/x"]);
+
+        client.set_bot_ids(["y"]);
+        assert_eq!(bot_ids(&mut router), ["a/x"]);
+
+        router.invalidate_bots_cache("a");
+        assert_eq!(bot_ids(&mut router), ["a/y"]);
+    }
+
+    #[cfg(feature = "async-rt")]
+    #[test]
+    fn expired_bots_are_refreshed_in_background() {
+        let mut router = RouterClient::new();
+        let client = stub(&["x"]);
+        router.insert_client("a", Box::new(client.clone()));
+        router.set_client_ttl("a", Some(Duration::ZERO));
+        assert_eq!(bot_ids(&mut router), ["a/x"]);
+
+        let mut changes = router.bots_changes();
+        client.set_bot_ids(["y"]);
+
+        // The stale list is served while refreshing.
+        assert_eq!(bot_ids(&mut router), ["a/x"]);
+        futures::executor::block_on(changes.next()).unwrap();
+        assert_eq!(bot_ids(&mut router), ["a/y"]);
+    }
+}
@@ -1 +1,3 @@
 pub mod chat;
+#[cfg(feature = "async-rt")]
+pub mod session;
@@ -9,19 +9,36 @@ use crate::{
         vec::VecMutation,
     },
 };
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex, Weak};
 
 use futures::StreamExt;
 
+#[cfg(feature = "mcp")]
+mod agent;
+mod context;
+mod history;
+mod instructions;
 mod plugin;
+mod projection;
+mod realtime;
 mod state;
 mod task;
 mod utils;
 
+#[cfg(feature = "mcp")]
+pub use agent::*;
+pub use context::*;
+pub use instructions::*;
 pub use plugin::*;
+pub use projection::*;
 pub use state::*;
 pub use task::*;
-use utils::amortize;
+use utils::{amortize, continued_content, tool_results_text};
+
+/// Sent after a partial answer to continue it, if the bot doesn't support prefill.
+const CONTINUE_PROMPT: &str =
+    "Continue your last message exactly where it was cut off, without repeating anything.";
 
 /// Private utility wrapper around a weak ref to a controller.
 ///
@@ -80,6 +97,28 @@ pub struct ChatController {
     #[cfg(feature = "mcp")]
     tool_manager: Option<McpManagerClient>,
     spawner: Option<Box<dyn ErasedSpawner>>,
+    realtime_client: Option<Box<dyn RealtimeClient>>,
+    realtime_abort_on_drop: Option<AbortOnDropHandle>,
+    /// Incremented for each bridged realtime session, to discard stale events.
+    realtime_generation: u64,
+    /// Messages being written by the realtime bridge, to not confuse them with others.
+    realtime_writing: realtime::RealtimeWriting,
+    /// If realtime sessions should be recorded into the messages list.
+    realtime_history_enabled: bool,
+    /// If tool calls requested during realtime sessions should be executed
+    /// through the tool manager.
+    #[cfg(feature = "mcp")]
+    realtime_tool_execution_enabled: bool,
+    #[cfg(feature = "mcp")]
+    realtime_session: Option<realtime::RealtimeSession>,
+    history: history::History,
+    context_strategy: Option<Arc<dyn ContextStrategy>>,
+    history_projection: HistoryProjection,
+    template_variables: HashMap<String, String>,
+    #[cfg(feature = "mcp")]
+    agent_loop: Option<AgentLoop>,
+    #[cfg(feature = "mcp")]
+    agent_run: agent::AgentRun,
 }
 
 impl ChatController {
@@ -103,6 +142,23 @@ impl ChatController {
                 #[cfg(feature = "mcp")]
                 tool_manager: None,
                 spawner: None,
+                realtime_client: None,
+                realtime_abort_on_drop: None,
+                realtime_generation: 0,
+                realtime_writing: Default::default(),
+                realtime_history_enabled: true,
+                #[cfg(feature = "mcp")]
+                realtime_tool_execution_enabled: false,
+                #[cfg(feature = "mcp")]
+                realtime_session: None,
+                history: history::History::default(),
+                context_strategy: None,
+                history_projection: HistoryProjection::default(),
+                template_variables: HashMap::new(),
+                #[cfg(feature = "mcp")]
+                agent_loop: None,
+                #[cfg(feature = "mcp")]
+                agent_run: agent::AgentRun::default(),
             })
         })
     }
@@ -128,6 +184,125 @@ impl ChatController {
         self.set_spawner(Some(crate::utils::asynchronous::BasicSpawner));
     }
 
+    /// Sets if transcripts and tool calls from realtime sessions should be recorded
+    /// as regular messages. Enabled by default.
+    pub fn set_realtime_history_enabled(&mut self, enabled: bool) {
+        self.realtime_history_enabled = enabled;
+    }
+
+    pub fn realtime_history_enabled(&self) -> bool {
+        self.realtime_history_enabled
+    }
+
+    /// Sets if tool calls requested during realtime sessions should be executed
+    /// automatically through the configured tool manager. Disabled by default,
+    /// leaving the calls to you.
+    ///
+    /// Results are sent back to the session on your behalf. If the tool manager
+    /// has dangerous mode enabled, calls run right away. Otherwise, they wait
+    /// until you set their permission status to approved (or dispatch them with
+    /// [`ChatTask::Execute`]) or denied.
+    ///
+    /// Does nothing unless realtime history is enabled too (see
+    /// [`ChatController::set_realtime_history_enabled`]), as the calls wait for
+    /// approval as messages of the history.
+    #[cfg(feature = "mcp")]
+    pub fn set_realtime_tool_execution_enabled(&mut self, enabled: bool) {
+        self.realtime_tool_execution_enabled = enabled;
+    }
+
+    #[cfg(feature = "mcp")]
+    pub fn realtime_tool_execution_enabled(&self) -> bool {
+        self.realtime_tool_execution_enabled
+    }
+
+    /// Sets if changes to messages and to the selected bot should be recorded to
+    /// be undone and redone. Disabled by default.
+    ///
+    /// Disabling it discards the recorded history.
+    pub fn set_history_enabled(&mut self, enabled: bool) {
+        self.history.set_enabled(enabled);
+    }
+
+    pub fn history_enabled(&self) -> bool {
+        self.history.is_enabled()
+    }
+
+    /// Sets how many steps can be undone. Older steps are forgotten. Defaults to 100.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.set_limit(limit);
+    }
+
+    pub fn history_limit(&self) -> usize {
+        self.history.limit()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Reverts the last recorded step, stopping any ongoing answer or tool execution
+    /// first, as they are part of the step that started them.
+    ///
+    /// Returns `false` if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        if !self.history.can_undo() {
+            return false;
+        }
+
+        self.clear_in_flight_artifacts();
+        self.replay(history::History::start_undo)
+    }
+
+    /// Applies again the last undone step.
+    ///
+    /// Returns `false` if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        if !self.history.can_redo() {
+            return false;
+        }
+
+        self.clear_in_flight_artifacts();
+        self.replay(history::History::start_redo)
+    }
+
+    fn replay(
+        &mut self,
+        start: fn(&mut history::History) -> Option<Vec<ChatStateMutation>>,
+    ) -> bool {
+        let Some(step) = start(&mut self.history) else {
+            return false;
+        };
+
+        self.dispatch_mutations(step);
+        self.history.finish_replay();
+        true
+    }
+
+    /// Forgets all the steps that could be undone or redone.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Starts merging all the mutations dispatched from now on into a single undo
+    /// step, until [`Self::end_history_group`] is called.
+    ///
+    /// Groups can be nested, in which case the outermost one makes the step. Tasks
+    /// are always grouped, and answers are grouped with the task that started them,
+    /// including everything dispatched while they are streamed.
+    pub fn begin_history_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    /// Ends a group started with [`Self::begin_history_group`].
+    pub fn end_history_group(&mut self) {
+        self.history.end_group();
+    }
+
     /// Registers a plugin to extend the controller behavior. Runs after all other plugins.
     pub fn append_plugin<P>(&mut self, plugin: P) -> ChatControllerPluginRegistrationId
     where
@@ -242,12 +417,21 @@ impl ChatController {
             for (_, plugin) in &mut self.plugins {
                 plugin.on_state_mutation(&mutation, &self.state);
             }
+            self.history.observe(&mutation, &self.state);
             mutation.apply(&mut self.state);
         }
 
+        self.history.commit();
+
         for (_, plugin) in &mut self.plugins {
             plugin.on_state_ready(&self.state, &mutations);
         }
+
+        #[cfg(feature = "mcp")]
+        {
+            self.resolve_realtime_tool_calls();
+            self.resolve_agent_tool_calls();
+        }
     }
 
     /// Shorthand for dispatching a single mutation.
@@ -288,18 +472,59 @@ impl ChatController {
             }
         }
 
+        self.history.begin_group();
+        self.handle_task(task);
+        self.history.end_group();
+    }
+
+    fn handle_task(&mut self, task: ChatTask) {
+        #[cfg(feature = "mcp")]
+        if matches!(
+            task,
+            ChatTask::Send
+                | ChatTask::Regenerate
+                | ChatTask::Continue
+                | ChatTask::EditAndResend(..)
+        ) {
+            self.start_agent_run();
+        }
+
         match task {
             ChatTask::Send => {
                 self.handle_send();
             }
             ChatTask::Stop => {
+                #[cfg(feature = "mcp")]
+                self.stop_agent_loop();
                 self.clear_streaming_artifacts();
             }
+            ChatTask::Regenerate => {
+                self.handle_regenerate();
+            }
+            ChatTask::Continue => {
+                self.handle_continue();
+            }
+            ChatTask::EditAndResend(index, content) => {
+                self.handle_edit_and_resend(index, content);
+            }
             ChatTask::Load => {
                 self.handle_load();
             }
+            ChatTask::StartRealtime => {
+                self.handle_start_realtime();
+            }
+            ChatTask::StopRealtime => {
+                self.handle_stop_realtime();
+            }
             ChatTask::Execute(tool_calls, bot_id) => {
-                self.handle_execute(tool_calls, bot_id);
+                #[cfg(feature = "mcp")]
+                let tool_calls = self.take_realtime_tool_calls(tool_calls);
+                #[cfg(feature = "mcp")]
+                self.take_agent_tool_calls(&tool_calls);
+
+                if !tool_calls.is_empty() {
+                    self.handle_execute(tool_calls, bot_id);
+                }
             }
         }
     }
@@ -307,30 +532,134 @@ impl ChatController {
     fn handle_send(&mut self) {
         // Clean previous streaming artifacts if any.
         self.clear_streaming_artifacts();
+        self.send_at(self.state.messages.len());
+    }
 
-        let Some(bot_id) = self.state.bot_id.clone() else {
-            self.dispatch_mutation(VecMutation::Push(Message::app_error("No bot selected")));
+    fn handle_regenerate(&mut self) {
+        self.clear_in_flight_artifacts();
 
+        let messages = &self.state.messages;
+        let Some(user_index) = messages.iter().rposition(|m| m.from == EntityId::User) else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(
+                "There is no message to regenerate an answer for",
+            )));
             return;
         };
 
-        let Some(mut spawner) = self.spawner.clone() else {
-            self.dispatch_mutation(VecMutation::Push(Message::app_error(
-                "No async spawner configured",
-            )));
+        // An answer made only of errors is not worth keeping as a branch.
+        let index = user_index + 1;
+        if index < messages.len() && messages[index..].iter().all(|m| m.from == EntityId::App) {
+            self.dispatch_mutation(VecMutation::<Message>::RemoveRange(index, messages.len()));
+        }
+
+        self.send_at(index);
+    }
+
+    fn handle_edit_and_resend(&mut self, index: usize, content: MessageContent) {
+        self.clear_in_flight_artifacts();
 
+        let Some(message) = self.state.messages.get(index) else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(format!(
+                "There is no message to edit at index {}",
+                index
+            ))));
             return;
         };
 
-        let Some(mut client) = self.client.clone() else {
+        if message.from != EntityId::User {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(format!(
+                "Only user messages can be edited and resent, but index {} is not one",
+                index
+            ))));
+            return;
+        }
+
+        let mut message = message.clone();
+        message.set_content(content);
+
+        if let Some(mutation) =
+            ChatStateMutation::create_branch(&self.state.messages, index, message)
+        {
+            self.dispatch_mutation(mutation);
+        }
+
+        self.send_at(self.state.messages.len());
+    }
+
+    fn handle_continue(&mut self) {
+        self.clear_in_flight_artifacts();
+
+        // Errors from the interrupted attempt are not part of the answer.
+        let messages = &self.state.messages;
+        let trailing_errors = messages
+            .iter()
+            .rev()
+            .take_while(|m| m.from == EntityId::App)
+            .count();
+        if trailing_errors > 0 {
+            let len = messages.len();
+            self.dispatch_mutation(VecMutation::<Message>::RemoveRange(
+                len - trailing_errors,
+                len,
+            ));
+        }
+
+        let Some(Message {
+            from: EntityId::Bot(bot_id),
+            content: prefix,
+            ..
+        }) = self.state.messages.last().cloned()
+        else {
             self.dispatch_mutation(VecMutation::Push(Message::app_error(
-                "No bot client configured",
+                "There is no answer to continue",
             )));
+            return;
+        };
 
+        let Some((spawner, client)) = self.send_prerequisites() else {
             return;
         };
 
-        self.dispatch_mutation(VecMutation::Push(Message {
+        let mut messages_context = self.messages_context();
+        let supports_prefill = self
+            .state
+            .get_bot(&bot_id)
+            .is_some_and(|b| b.capabilities.has_capability(&BotCapability::Prefill));
+        if !supports_prefill {
+            messages_context.push(Message {
+                from: EntityId::User,
+                content: MessageContent {
+                    text: CONTINUE_PROMPT.into(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+        }
+
+        self.dispatch_mutation(VecMutation::update_last_with(&self.state.messages, |m| {
+            m.metadata.is_writing = true
+        }));
+        self.dispatch_mutation(ChatStateMutation::SetIsStreaming(true));
+
+        self.stream_answer(spawner, client, bot_id, messages_context, Some(prefix));
+    }
+
+    /// Streams a new answer from the selected bot into a message inserted at `index`,
+    /// sending the messages before it.
+    ///
+    /// Messages from `index` onwards are kept as an alternative branch.
+    fn send_at(&mut self, index: usize) {
+        let Some(bot_id) = self.state.bot_id.clone() else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error("No bot selected")));
+
+            return;
+        };
+
+        let Some((spawner, client)) = self.send_prerequisites() else {
+            return;
+        };
+
+        let message = Message {
             from: EntityId::Bot(bot_id.clone()),
             content: MessageContent::default(),
             metadata: MessageMetadata {
@@ -338,27 +667,136 @@ impl ChatController {
                 ..Default::default()
             },
             ..Default::default()
-        }));
+        };
+
+        match ChatStateMutation::create_branch(&self.state.messages, index, message.clone()) {
+            Some(mutation) => self.dispatch_mutation(mutation),
+            None => self.dispatch_mutation(VecMutation::Push(message)),
+        }
 
         self.dispatch_mutation(ChatStateMutation::SetIsStreaming(true));
 
-        let messages_context = self
+        let messages_context = self.messages_context();
+        self.stream_answer(spawner, client, bot_id, messages_context, None);
+    }
+
+    /// Gets what's needed to send messages, reporting anything missing as an error.
+    fn send_prerequisites(&mut self) -> Option<(Box<dyn ErasedSpawner>, Box<dyn BotClient>)> {
+        let Some(spawner) = self.spawner.clone() else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(
+                "No async spawner configured",
+            )));
+
+            return None;
+        };
+
+        let Some(client) = self.client.clone() else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(
+                "No bot client configured",
+            )));
+
+            return None;
+        };
+
+        Some((spawner, client))
+    }
+
+    /// The messages to send as context, from the active branch, after applying
+    /// the history projection.
+    fn messages_context(&self) -> Vec<Message> {
+        let messages = self
             .state
             .messages
             .iter()
-            .filter(|m| m.from != EntityId::App && !m.metadata.is_writing)
-            .cloned()
-            .collect::<Vec<_>>();
+            .filter(|m| {
+                m.from != EntityId::App && !m.metadata.is_writing && !m.metadata.hidden_from_model
+            })
+            .map(|m| {
+                let mut m = m.clone();
+                m.metadata.branches = None;
+                m
+            })
+            .collect();
+
+        let mut messages = self.history_projection.project(messages);
+        if let Some(instructions) = self.system_instructions_message() {
+            messages.insert(0, instructions);
+        }
+        messages
+    }
+
+    /// The system instructions for the selected bot, with their variables resolved.
+    fn system_instructions_message(&self) -> Option<Message> {
+        let bot_id = self.state.bot_id.as_ref();
+        let text = self
+            .state
+            .system_instructions
+            .render(bot_id, |name| self.template_variable(name, bot_id))?;
+
+        Some(Message {
+            from: EntityId::System,
+            content: MessageContent {
+                text,
+                ..Default::default()
+            },
+            metadata: MessageMetadata {
+                pinned: true,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn template_variable(&self, name: &str, bot_id: Option<&BotId>) -> Option<String> {
+        if let Some(value) = self.template_variables.get(name) {
+            return Some(value.clone());
+        }
+
+        let now = chrono::Local::now();
+        match name {
+            "date" => Some(now.format("%Y-%m-%d").to_string()),
+            "time" => Some(now.format("%H:%M").to_string()),
+            "bot" => bot_id.map(|id| id.id().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Streams the answer to the given messages into the last message.
+    ///
+    /// If a `prefix` is given, the answer continues it.
+    fn stream_answer(
+        &mut self,
+        mut spawner: Box<dyn ErasedSpawner>,
+        mut client: Box<dyn BotClient>,
+        bot_id: BotId,
+        messages_context: Vec<Message>,
+        prefix: Option<MessageContent>,
+    ) {
+        self.history.set_streaming(true);
 
         let controller = self.accessor.clone();
+        let context_strategy = self.context_strategy.clone();
         self.send_abort_on_drop = Some(spawner.spawn_abort_on_drop(async move {
             let Some(tools) = controller.lock_with(|c| c.get_all_namespaced_tools()) else {
                 return;
             };
 
+            let messages_context = match context_strategy {
+                Some(strategy) => strategy.apply(messages_context).await,
+                None => messages_context,
+            };
+
             let message_stream = amortize(client.send(&bot_id, &messages_context, &tools));
             let mut message_stream = std::pin::pin!(message_stream);
             while let Some(result) = message_stream.next().await {
+                let result = match &prefix {
+                    Some(prefix) => {
+                        let (content, errors) = result.into_value_and_errors();
+                        let content = content.map(|c| continued_content(prefix, c));
+                        ClientResult::new_unchecked(content, errors)
+                    }
+                    None => result,
+                };
+
                 let should_break = controller
                     .lock_with(|c| c.handle_message_content(result, &bot_id))
                     .unwrap_or(true);
@@ -367,10 +805,29 @@ impl ChatController {
                     break;
                 }
             }
-            controller.lock_with(|c| c.clear_streaming_artifacts());
+            controller.lock_with(|c| {
+                c.clear_streaming_artifacts();
+                #[cfg(feature = "mcp")]
+                c.continue_agent_loop(bot_id);
+            });
         }));
     }
 
+    /// Like [`Self::clear_streaming_artifacts`], but also for ongoing tool executions.
+    fn clear_in_flight_artifacts(&mut self) {
+        self.clear_streaming_artifacts();
+
+        #[cfg(feature = "mcp")]
+        if self.execute_tools_abort_on_drop.take().is_some() {
+            self.dispatch_mutation(ChatStateMutation::SetIsStreaming(false));
+            self.dispatch_mutation(VecMutation::remove_many_with_retain(
+                &self.state.messages,
+                |_, m| !(m.metadata.is_writing && m.from == EntityId::Tool),
+            ));
+            self.history.set_streaming(false);
+        }
+    }
+
     /// Aborts current streaming operation and cleans up artifacts.
     fn clear_streaming_artifacts(&mut self) {
         if self.send_abort_on_drop.is_none() {
@@ -402,7 +859,29 @@ impl ChatController {
         }
 
         self.dispatch_mutations(updates_to_dispatch);
+
+        // Removing an empty answer holding alternatives would lose them, so the
+        // previous alternative is restored instead, keeping any errors after it.
+        if let Some(position) = indices_to_remove
+            .iter()
+            .position(|&i| self.state.messages[i].metadata.branches.is_some())
+        {
+            let index = indices_to_remove[position];
+            indices_to_remove.truncate(position);
+
+            let errors: Vec<_> = self.state.messages[index..]
+                .iter()
+                .filter(|m| m.from == EntityId::App)
+                .cloned()
+                .collect();
+            if let Some(mutation) = ChatStateMutation::remove_branch(&self.state.messages, index) {
+                self.dispatch_mutation(mutation);
+                self.dispatch_mutation(VecMutation::Extend(errors));
+            }
+        }
+
         self.dispatch_mutation(VecMutation::RemoveMany::<Message>(indices_to_remove.into()));
+        self.history.set_streaming(false);
     }
 
     /// Changes the client used by this controller when sending messages and laoding bots.
@@ -414,6 +893,49 @@ impl ChatController {
         self.dispatch_mutation(ChatStateMutation::SetLoadStatus(Status::Idle));
     }
 
+    /// Sets how the messages sent as context are trimmed (or rewritten) before
+    /// sending, like to fit the context window of the bot. See [`ContextStrategy`].
+    ///
+    /// By default, all the messages are sent.
+    pub fn set_context_strategy<S>(&mut self, strategy: Option<S>)
+    where
+        S: ContextStrategy,
+    {
+        self.context_strategy = strategy.map(|s| Arc::new(s) as Arc<dyn ContextStrategy>);
+    }
+
+    pub fn context_strategy(&self) -> Option<&dyn ContextStrategy> {
+        self.context_strategy.as_deref()
+    }
+
+    /// Sets a variable for the templates of the system instructions, used as
+    /// `{{name}}`. `None` removes it.
+    ///
+    /// Apps are expected to set `user_name` and `locale`. The current `date`
+    /// (`2025-01-31`), `time` (`13:45`) and `bot` (the id of the selected bot) are
+    /// available unless set here.
+    pub fn set_template_variable(&mut self, name: impl Into<String>, value: Option<String>) {
+        let name = name.into();
+        match value {
+            Some(value) => self.template_variables.insert(name, value),
+            None => self.template_variables.remove(&name),
+        };
+    }
+
+    pub fn template_variables(&self) -> &HashMap<String, String> {
+        &self.template_variables
+    }
+
+    /// Sets the rules rewriting past messages before sending them again, like to
+    /// strip old reasoning. See [`HistoryProjection`].
+    pub fn set_history_projection(&mut self, projection: HistoryProjection) {
+        self.history_projection = projection;
+    }
+
+    pub fn history_projection(&self) -> HistoryProjection {
+        self.history_projection
+    }
+
     fn handle_load(&mut self) {
         self.dispatch_mutation(ChatStateMutation::SetLoadStatus(Status::Working));
 
@@ -448,10 +970,16 @@ impl ChatController {
 
                 c.dispatch_mutation(VecMutation::Set(bots.unwrap_or_default()));
 
-                let messages: Vec<_> = errors
-                    .into_iter()
-                    .map(Message::from_client_error)
-                    .collect();
+                // Upgrades ids saved before routed ids had a provider path.
+                if let Some(bot_id) = &c.state.bot_id
+                    && let Some(bot) = c.state.get_bot(bot_id)
+                    && &bot.id != bot_id
+                {
+                    let bot_id = bot.id.clone();
+                    c.dispatch_mutation(ChatStateMutation::SetBotId(Some(bot_id)));
+                }
+
+                let messages: Vec<_> = errors.into_iter().map(Message::from_client_error).collect();
                 c.dispatch_mutation(VecMutation::Extend(messages));
             });
         }));
@@ -482,7 +1010,13 @@ impl ChatController {
                 // Take any pending upgrade from the client and abort if any.
                 match content.upgrade.take() {
                     Some(upgrade) => {
-                        let mut upgrade = Some(upgrade);
+                        let mut upgrade = Some(match upgrade {
+                            Upgrade::Realtime(channel) => {
+                                // Only one session at a time.
+                                self.handle_stop_realtime();
+                                Upgrade::Realtime(self.expose_realtime_channel(channel, bot_id))
+                            }
+                        });
                         for (_, plugin) in &mut self.plugins {
                             upgrade = plugin.on_upgrade(upgrade.unwrap(), bot_id);
                             if upgrade.is_none() {
@@ -497,22 +1031,25 @@ impl ChatController {
                 if self.state.messages.is_empty() {
                     return true;
                 }
+
+                let answered_by = content.answered_by.take();
                 self.dispatch_mutation(VecMutation::update_last_with(
                     &self.state.messages,
                     |message| {
                         message.update_content(|c| {
                             *c = content.clone();
                         });
+
+                        if answered_by.is_some() {
+                            message.metadata.answered_by = answered_by.clone();
+                        }
                     },
                 ));
 
                 false
             }
             Err(errors) => {
-                let messages: Vec<_> = errors
-                    .into_iter()
-                    .map(Message::from_client_error)
-                    .collect();
+                let messages: Vec<_> = errors.into_iter().map(Message::from_client_error).collect();
                 self.dispatch_mutation(VecMutation::Extend(messages));
 
                 true
@@ -520,6 +1057,15 @@ impl ChatController {
         }
     }
 
+    /// Changes the client used by this controller to start realtime sessions.
+    pub fn set_realtime_client(&mut self, client: Option<Box<dyn RealtimeClient>>) {
+        self.realtime_client = client;
+    }
+
+    pub fn realtime_client(&self) -> Option<&dyn RealtimeClient> {
+        self.realtime_client.as_deref()
+    }
+
     pub fn bot_client(&self) -> Option<&dyn BotClient> {
         self.client.as_deref()
     }
@@ -552,6 +1098,34 @@ impl ChatController {
 
     #[cfg(feature = "mcp")]
     fn handle_execute(&mut self, tool_calls: Vec<ToolCall>, bot_id: Option<BotId>) {
+        self.execute_tool_calls(tool_calls, Vec::new(), bot_id);
+    }
+
+    /// Executes the approved tool calls and answers the denied ones, then sends
+    /// all the results to the bot, if given.
+    #[cfg(feature = "mcp")]
+    fn execute_tool_calls(
+        &mut self,
+        tool_calls: Vec<ToolCall>,
+        denied: Vec<ToolCall>,
+        bot_id: Option<BotId>,
+    ) {
+        let denied_results: Vec<ToolResult> = denied
+            .iter()
+            .map(|tc| ToolResult {
+                tool_call_id: tc.id.clone(),
+                content: realtime::DENIED_TOOL_CALL_OUTPUT.to_string(),
+                is_error: true,
+            })
+            .collect();
+
+        if tool_calls.is_empty() {
+            if !denied.is_empty() {
+                self.push_tool_results(&denied, denied_results, bot_id);
+            }
+            return;
+        }
+
         let Some(mut spawner) = self.spawner.clone() else {
             self.dispatch_mutation(VecMutation::Push(Message::app_error(
                 "Tool execution failed: No async spawner configured",
@@ -591,76 +1165,56 @@ impl ChatController {
 
         self.dispatch_mutation(VecMutation::Push(loading_message));
         self.dispatch_mutation(ChatStateMutation::SetIsStreaming(true));
+        self.history.set_streaming(true);
+        self.agent_run.executing = tool_calls.clone();
 
         self.execute_tools_abort_on_drop = Some(spawner.spawn_abort_on_drop(async move {
             // Execute tool calls using MCP manager
-            let tool_results = tool_manager.execute_tool_calls(tool_calls.clone()).await;
-
-            // Create formatted text for tool results
-            let results_text = if tool_results.len() == 1 {
-                let result = &tool_results[0];
-                let tool_name = tool_calls
-                    .iter()
-                    .find(|tc| tc.id == result.tool_call_id)
-                    .map(|tc| tc.name.as_str())
-                    .unwrap_or("unknown");
-
-                let display_name = display_name_from_namespaced(tool_name);
-                if result.is_error {
-                    format!("🔧 Tool '{}' failed:\n{}", display_name, result.content)
-                } else {
-                    let summary =
-                        crate::utils::tool::create_tool_output_summary(tool_name, &result.content);
-                    format!(
-                        "🔧 Tool '{}' executed successfully:\n`{}`",
-                        display_name, summary
-                    )
-                }
-            } else {
-                let mut text = format!("🔧 Executed {} tools:\n\n", tool_results.len());
-                for result in &tool_results {
-                    let tool_name = tool_calls
-                        .iter()
-                        .find(|tc| tc.id == result.tool_call_id)
-                        .map(|tc| tc.name.as_str())
-                        .unwrap_or("unknown");
-
-                    let display_name = display_name_from_namespaced(tool_name);
-                    if result.is_error {
-                        text.push_str(&format!("**{}** ❌: {}\n\n", display_name, result.content));
-                    } else {
-                        let summary = crate::utils::tool::create_tool_output_summary(
-                            tool_name,
-                            &result.content,
-                        );
-                        text.push_str(&format!("**{}** ✅: `{}`\n\n", display_name, summary));
-                    }
-                }
-                text
-            };
+            let mut tool_results = tool_manager.execute_tool_calls(tool_calls.clone()).await;
+            tool_results.extend(denied_results);
+
+            let mut tool_calls = tool_calls;
+            tool_calls.extend(denied);
 
             controller.lock_with(|c| {
+                c.execute_tools_abort_on_drop = None;
+                c.agent_run.executing.clear();
                 c.dispatch_mutation(ChatStateMutation::SetIsStreaming(false));
                 c.dispatch_mutation(VecMutation::remove_many_with_retain(
                     &c.state.messages,
                     |_, m| !(m.metadata.is_writing && m.from == EntityId::Tool),
                 ));
-                c.dispatch_mutation(VecMutation::Push(Message {
-                    from: EntityId::Tool, // Tool results use the tool role
-                    content: MessageContent {
-                        text: results_text,
-                        tool_results: tool_results,
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                }));
-
-                if bot_id.is_some() {
-                    c.dispatch_task(ChatTask::Send);
-                }
+                c.history.set_streaming(false);
+                c.push_tool_results(&tool_calls, tool_results, bot_id);
             });
         }));
     }
+
+    /// Records the results of tool calls, and sends them to the bot, if given,
+    /// continuing the agent loop.
+    #[cfg(feature = "mcp")]
+    fn push_tool_results(
+        &mut self,
+        tool_calls: &[ToolCall],
+        tool_results: Vec<ToolResult>,
+        bot_id: Option<BotId>,
+    ) {
+        self.dispatch_mutation(VecMutation::Push(Message {
+            from: EntityId::Tool, // Tool results use the tool role
+            content: MessageContent {
+                text: tool_results_text(tool_calls, &tool_results),
+                tool_results,
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+
+        if bot_id.is_some() {
+            self.agent_run.continuing = true;
+            self.dispatch_task(ChatTask::Send);
+            self.agent_run.continuing = false;
+        }
+    }
 }
 
 pub struct ChatControllerBuilder(Arc<Mutex<ChatController>>);
@@ -696,6 +1250,17 @@ impl ChatControllerBuilder {
         self
     }
 
+    pub fn with_realtime_client<C>(self, client: C) -> Self
+    where
+        C: RealtimeClient + 'static,
+    {
+        self.0
+            .lock()
+            .unwrap()
+            .set_realtime_client(Some(Box::new(client)));
+        self
+    }
+
     pub fn with_plugin_append<P>(self, plugin: P) -> Self
     where
         P: ChatControllerPlugin + 'static,
@@ -712,6 +1277,68 @@ impl ChatControllerBuilder {
         self
     }
 
+    /// See [`ChatController::set_agent_loop`].
+    #[cfg(feature = "mcp")]
+    pub fn with_agent_loop(self, agent_loop: AgentLoop) -> Self {
+        self.0.lock().unwrap().set_agent_loop(Some(agent_loop));
+        self
+    }
+
+    /// See [`ChatController::set_context_strategy`].
+    pub fn with_context_strategy<S>(self, strategy: S) -> Self
+    where
+        S: ContextStrategy,
+    {
+        self.0.lock().unwrap().set_context_strategy(Some(strategy));
+        self
+    }
+
+    /// Sets the initial system instructions of the chat.
+    pub fn with_system_instructions(self, instructions: SystemInstructions) -> Self {
+        self.0
+            .lock()
+            .unwrap()
+            .dispatch_mutation(ChatStateMutation::SetSystemInstructions(instructions));
+        self
+    }
+
+    /// See [`ChatController::set_template_variable`].
+    pub fn with_template_variable(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.0
+            .lock()
+            .unwrap()
+            .set_template_variable(name, Some(value.into()));
+        self
+    }
+
+    /// See [`ChatController::set_history_projection`].
+    pub fn with_history_projection(self, projection: HistoryProjection) -> Self {
+        self.0.lock().unwrap().set_history_projection(projection);
+        self
+    }
+
+    /// See [`ChatController::set_history_enabled`].
+    pub fn with_history(self, enabled: bool) -> Self {
+        self.0.lock().unwrap().set_history_enabled(enabled);
+        self
+    }
+
+    /// See [`ChatController::set_realtime_history_enabled`].
+    pub fn with_realtime_history(self, enabled: bool) -> Self {
+        self.0.lock().unwrap().set_realtime_history_enabled(enabled);
+        self
+    }
+
+    /// See [`ChatController::set_realtime_tool_execution_enabled`].
+    #[cfg(feature = "mcp")]
+    pub fn with_realtime_tool_execution(self, enabled: bool) -> Self {
+        self.0
+            .lock()
+            .unwrap()
+            .set_realtime_tool_execution_enabled(enabled);
+        self
+    }
+
     #[cfg(feature = "mcp")]
     pub fn with_tool_manager(self, tool_manager: McpManagerClient) -> Self {
         self.0.lock().unwrap().set_tool_manager(Some(tool_manager));
@@ -722,3 +1349,475 @@ impl ChatControllerBuilder {
         self.0
     }
 }
+
+#[cfg(all(test, feature = "async-rt"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use std::time::Duration;
+
+    /// Client numbering its answers. It continues its own messages when prefilled,
+    /// and otherwise echoes the last message.
+    fn counting() -> MockClient {
+        MockClient::responding(|call| {
+            let last = call.messages.last().unwrap();
+            match last.from {
+                EntityId::Bot(_) => MockReply::text(" and more"),
+                _ => MockReply::text(format!("{}: {}", call.number, last.content.text)),
+            }
+        })
+    }
+
+    fn user_message(text: &str) -> Message {
+        Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn controller_with(messages: Vec<Message>) -> Arc<Mutex<ChatController>> {
+        let controller = ChatController::builder()
+            .with_basic_spawner()
+            .with_client(counting())
+            .build_arc();
+
+        let mut c = controller.lock().unwrap();
+        c.dispatch_mutation(ChatStateMutation::SetBotId(Some(BotId::new("bot"))));
+        c.dispatch_mutation(VecMutation::Set(messages));
+        drop(c);
+
+        controller
+    }
+
+    fn run(controller: &Arc<Mutex<ChatController>>, task: ChatTask) -> Vec<String> {
+        controller.lock().unwrap().dispatch_task(task);
+
+        for _ in 0..500 {
+            std::thread::sleep(Duration::from_millis(5));
+            if !controller.lock().unwrap().state().is_streaming {
+                break;
+            }
+        }
+
+        let c = controller.lock().unwrap();
+        c.state()
+            .messages
+            .iter()
+            .map(|m| m.content.text.clone())
+            .collect()
+    }
+
+    #[test]
+    fn regenerate_keeps_previous_answer_as_branch() {
+        let controller = controller_with(vec![user_message("hi")]);
+        assert_eq!(run(&controller, ChatTask::Send), ["hi", "1: hi"]);
+
+        controller
+            .lock()
+            .unwrap()
+            .dispatch_mutation(VecMutation::Push(Message::app_error("oops")));
+        assert_eq!(run(&controller, ChatTask::Regenerate), ["hi", "2: hi"]);
+
+        let c = controller.lock().unwrap();
+        let answer = &c.state().messages[1];
+        assert_eq!(answer.branch_position(), (1, 2));
+        let previous = &answer.metadata.branches.as_ref().unwrap().before[0];
+        assert_eq!(previous[0].content.text, "1: hi");
+    }
+
+    #[test]
+    fn regenerate_discards_answers_made_of_errors() {
+        let controller = controller_with(vec![user_message("hi"), Message::app_error("oops")]);
+        assert_eq!(run(&controller, ChatTask::Regenerate), ["hi", "1: hi"]);
+
+        let c = controller.lock().unwrap();
+        assert_eq!(c.state().messages[1].branch_position(), (0, 1));
+    }
+
+    #[test]
+    fn edit_and_resend_branches_from_edited_message() {
+        let controller = controller_with(vec![user_message("hi")]);
+        run(&controller, ChatTask::Send);
+
+        let content = MessageContent {
+            text: "hello".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&controller, ChatTask::EditAndResend(0, content)),
+            ["hello", "2: hello"]
+        );
+
+        let c = controller.lock().unwrap();
+        assert_eq!(c.state().messages[0].branch_position(), (1, 2));
+    }
+
+    #[test]
+    fn edit_and_resend_rejects_non_user_messages() {
+        let controller = controller_with(vec![user_message("hi")]);
+        run(&controller, ChatTask::Send);
+
+        let content = MessageContent {
+            text: "edited".into(),
+            ..Default::default()
+        };
+        run(&controller, ChatTask::EditAndResend(1, content));
+
+        let c = controller.lock().unwrap();
+        let messages = &c.state().messages;
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].content.text, "1: hi");
+        assert_eq!(messages[1].branch_position(), (0, 1));
+        assert_eq!(messages[2].from, EntityId::App);
+    }
+
+    #[test]
+    fn legacy_router_bot_ids_are_upgraded_on_load() {
+        let client = MockClient::responding(|call| MockReply::text(call.bot_id.as_str()));
+        client.set_bot_ids(["model"]);
+        let router = crate::clients::router::RouterClient::new();
+        router.insert_client("key", Box::new(client));
+
+        let controller = ChatController::builder()
+            .with_basic_spawner()
+            .with_client(router)
+            .build_arc();
+
+        // Saved before routed ids had a provider path.
+        let saved: BotId = serde_json::from_str(r#""key/model""#).unwrap();
+        let mut c = controller.lock().unwrap();
+        c.dispatch_mutation(ChatStateMutation::SetBotId(Some(saved)));
+        c.dispatch_mutation(VecMutation::Push(user_message("hi")));
+        c.dispatch_task(ChatTask::Load);
+        drop(c);
+
+        for _ in 0..500 {
+            std::thread::sleep(Duration::from_millis(5));
+            if controller.lock().unwrap().state().load_status == Status::Success {
+                break;
+            }
+        }
+
+        let bot_id = controller.lock().unwrap().state().bot_id.clone().unwrap();
+        assert_eq!(bot_id, BotId::new("model").prefixed("key"));
+        let json = serde_json::to_string(&bot_id).unwrap();
+        assert_eq!(serde_json::from_str::<BotId>(&json).unwrap(), bot_id);
+
+        assert_eq!(run(&controller, ChatTask::Send), ["hi", "model"]);
+    }
+
+    /// Bridges a realtime session to the controller, giving what the client would
+    /// use to send events and receive commands.
+    fn start_realtime(
+        controller: &Arc<Mutex<ChatController>>,
+    ) -> (
+        futures::channel::mpsc::UnboundedSender<RealtimeEvent>,
+        futures::channel::mpsc::UnboundedReceiver<RealtimeCommand>,
+    ) {
+        let (event_sender, event_receiver) = futures::channel::mpsc::unbounded();
+        let (command_sender, command_receiver) = futures::channel::mpsc::unbounded();
+        let channel = RealtimeChannel {
+            event_sender: event_sender.clone(),
+            event_receiver: Arc::new(Mutex::new(Some(event_receiver))),
+            command_sender,
+        };
+        controller
+            .lock()
+            .unwrap()
+            .expose_realtime_channel(channel, &BotId::new("bot"));
+        (event_sender, command_receiver)
+    }
+
+    #[test]
+    fn realtime_transcripts_leave_other_writing_messages_alone() {
+        let mut answer = user_message("partial");
+        answer.from = EntityId::Bot(BotId::new("bot"));
+        answer.metadata.is_writing = true;
+        let controller = controller_with(vec![user_message("hi"), answer.clone()]);
+
+        let (event_sender, _command_receiver) = start_realtime(&controller);
+
+        for event in [
+            RealtimeEvent::AudioTranscript("Hel".into()),
+            RealtimeEvent::AudioTranscript("lo".into()),
+            RealtimeEvent::SessionClosed,
+        ] {
+            event_sender.unbounded_send(event).unwrap();
+        }
+
+        for _ in 0..500 {
+            std::thread::sleep(Duration::from_millis(5));
+            if controller
+                .lock()
+                .unwrap()
+                .state()
+                .realtime_channel
+                .is_none()
+            {
+                break;
+            }
+        }
+
+        let c = controller.lock().unwrap();
+        let messages = &c.state().messages;
+        assert_eq!(messages[1], answer);
+        assert_eq!(messages[2].content.text, "Hello");
+        assert!(!messages[2].metadata.is_writing);
+        assert_eq!(messages[2].metadata.item_id, None);
+    }
+
+    #[test]
+    fn undo_reverts_whole_answers() {
+        let controller = controller_with(vec![user_message("hi")]);
+        controller.lock().unwrap().set_history_enabled(true);
+        run(&controller, ChatTask::Send);
+        run(&controller, ChatTask::Regenerate);
+
+        let mut c = controller.lock().unwrap();
+        assert!(c.undo());
+        let texts: Vec<_> = c.state().messages.iter().map(|m| &m.content.text).collect();
+        assert_eq!(texts, ["hi", "1: hi"]);
+        assert_eq!(c.state().messages[1].branch_position(), (0, 1));
+
+        assert!(c.undo());
+        assert_eq!(c.state().messages.len(), 1);
+        assert!(!c.undo());
+
+        assert!(c.redo());
+        assert!(c.redo());
+        assert_eq!(c.state().messages[1].content.text, "2: hi");
+        assert!(!c.state().messages[1].metadata.is_writing);
+        assert!(!c.can_redo());
+    }
+
+    #[test]
+    fn continue_appends_to_last_answer() {
+        let controller = controller_with(vec![user_message("hi")]);
+        run(&controller, ChatTask::Send);
+
+        // Without prefill, the bot is asked to continue with an extra message.
+        controller
+            .lock()
+            .unwrap()
+            .dispatch_mutation(VecMutation::Push(Message::app_error("cut off")));
+        let texts = run(&controller, ChatTask::Continue);
+        assert_eq!(texts.len(), 2);
+        assert_eq!(texts[1], format!("1: hi2: {}", CONTINUE_PROMPT));
+
+        controller
+            .lock()
+            .unwrap()
+            .dispatch_mutation(VecMutation::Set(vec![Bot {
+                id: BotId::new("bot"),
+                name: "bot".into(),
+                avatar: EntityAvatar::Text("B".into()),
+                capabilities: BotCapabilities::new().with_capability(BotCapability::Prefill),
+            }]));
+        let texts = run(&controller, ChatTask::Continue);
+        assert!(texts[1].ends_with(" and more"));
+    }
+
+    #[test]
+    fn system_instructions_lead_the_context() {
+        let controller = ChatController::builder()
+            .with_system_instructions(
+                SystemInstructions::new("Help {{user_name}}.")
+                    .with_override(BotId::new("coder"), "Help {{user_name}} code with {{bot}}."),
+            )
+            .with_template_variable("user_name", "Ana")
+            .build_arc();
+
+        let mut c = controller.lock().unwrap();
+        c.dispatch_mutation(VecMutation::Push(Message {
+            from: EntityId::System,
+            content: MessageContent {
+                text: "Old prompt".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+
+        let texts = |c: &ChatController| -> Vec<String> {
+            c.messages_context()
+                .into_iter()
+                .map(|m| m.content.text)
+                .collect()
+        };
+        assert_eq!(texts(&c), ["Help Ana.", "Old prompt"]);
+
+        c.dispatch_mutation(ChatStateMutation::SetBotId(Some(BotId::new("coder"))));
+        assert_eq!(texts(&c)[0], "Help Ana code with coder.");
+    }
+
+    /// Client always asking to call a tool, with arguments changing on each call
+    /// unless `repeat` is set.
+    #[cfg(feature = "mcp")]
+    fn tool_calling(repeat: bool) -> MockClient {
+        MockClient::responding(move |call| {
+            let argument = if repeat { 0 } else { call.number };
+            MockReply::content(MessageContent {
+                tool_calls: vec![ToolCall {
+                    id: format!("call-{}", call.number),
+                    name: "server__search".into(),
+                    arguments: serde_json::json!({ "page": argument })
+                        .as_object()
+                        .unwrap()
+                        .clone(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+        })
+    }
+
+    /// Runs the agent loop until it stops with an error, giving who sent each message.
+    #[cfg(feature = "mcp")]
+    fn run_agent(client: MockClient, agent_loop: AgentLoop) -> Arc<Mutex<ChatController>> {
+        let tool_manager = McpManagerClient::new();
+        tool_manager.set_dangerous_mode_enabled(true);
+
+        let controller = ChatController::builder()
+            .with_basic_spawner()
+            .with_client(client)
+            .with_tool_manager(tool_manager)
+            .with_agent_loop(agent_loop)
+            .build_arc();
+
+        let mut c = controller.lock().unwrap();
+        c.dispatch_mutation(ChatStateMutation::SetBotId(Some(BotId::new("bot"))));
+        c.dispatch_mutation(VecMutation::Push(user_message("search")));
+        c.dispatch_task(ChatTask::Send);
+        drop(c);
+
+        for _ in 0..500 {
+            std::thread::sleep(Duration::from_millis(5));
+            let c = controller.lock().unwrap();
+            if c.state().messages.last().unwrap().from == EntityId::App {
+                break;
+            }
+        }
+
+        controller
+    }
+
+    #[cfg(feature = "mcp")]
+    fn senders(controller: &Arc<Mutex<ChatController>>) -> Vec<EntityId> {
+        let c = controller.lock().unwrap();
+        c.state().messages.iter().map(|m| m.from.clone()).collect()
+    }
+
+    #[cfg(feature = "mcp")]
+    #[test]
+    fn agent_loop_stops_at_iteration_cap_and_repeated_calls() {
+        let bot = EntityId::Bot(BotId::new("bot"));
+
+        let controller = run_agent(tool_calling(false), AgentLoop::new().with_max_iterations(2));
+        assert_eq!(
+            senders(&controller),
+            [
+                EntityId::User,
+                bot.clone(),
+                EntityId::Tool,
+                bot.clone(),
+                EntityId::Tool,
+                bot.clone(),
+                EntityId::Tool,
+                EntityId::App,
+            ]
+        );
+
+        let controller = run_agent(
+            tool_calling(true),
+            AgentLoop::new().with_max_repeated_calls(1),
+        );
+        assert_eq!(
+            senders(&controller),
+            [
+                EntityId::User,
+                bot.clone(),
+                EntityId::Tool,
+                bot,
+                EntityId::Tool,
+                EntityId::App,
+            ]
+        );
+    }
+
+    #[cfg(feature = "mcp")]
+    #[test]
+    fn agent_loop_caps_leave_no_call_without_result() {
+        let client = tool_calling(false);
+        let controller = run_agent(client.clone(), AgentLoop::new().with_max_iterations(1));
+
+        client.push_reply(MockReply::text("done"));
+        controller
+            .lock()
+            .unwrap()
+            .dispatch_mutation(VecMutation::Push(user_message("go on")));
+        assert_eq!(run(&controller, ChatTask::Send).last().unwrap(), "done");
+
+        let sent = client.calls().last().unwrap().messages.clone();
+        let calls: Vec<_> = sent.iter().flat_map(|m| &m.content.tool_calls).collect();
+        let results: Vec<_> = sent.iter().flat_map(|m| &m.content.tool_results).collect();
+        assert_eq!(calls.len(), 2);
+        for call in calls {
+            assert!(results.iter().any(|r| r.tool_call_id == call.id));
+        }
+        assert!(results.last().unwrap().is_error);
+    }
+
+    #[cfg(feature = "mcp")]
+    #[test]
+    fn realtime_tool_calls_are_answered_through_the_session_once_enabled() {
+        let controller = controller_with(vec![]);
+        let mut c = controller.lock().unwrap();
+        assert!(!c.realtime_tool_execution_enabled());
+        c.set_tool_manager(Some(McpManagerClient::new()));
+        c.set_realtime_tool_execution_enabled(true);
+        drop(c);
+
+        let (event_sender, mut command_receiver) = start_realtime(&controller);
+        event_sender
+            .unbounded_send(RealtimeEvent::FunctionCallRequest {
+                name: "server__search".into(),
+                call_id: "call-1".into(),
+                arguments: "{}".into(),
+            })
+            .unwrap();
+
+        let mut index = None;
+        for _ in 0..500 {
+            std::thread::sleep(Duration::from_millis(5));
+            index = controller.lock().unwrap().find_tool_call("call-1");
+            if index.is_some() {
+                break;
+            }
+        }
+
+        // Waits for approval, as dangerous mode is disabled.
+        let (index, position) = index.unwrap();
+        assert!(command_receiver.try_next().is_err());
+
+        let mut c = controller.lock().unwrap();
+        let deny = VecMutation::update_with(&c.state().messages, index, |m| {
+            m.content.tool_calls[position].permission_status = ToolCallPermissionStatus::Denied;
+        });
+        c.dispatch_mutation(deny);
+
+        match command_receiver.try_next() {
+            Ok(Some(RealtimeCommand::SendFunctionCallResult { call_id, output })) => {
+                assert_eq!(call_id, "call-1");
+                assert_eq!(output, realtime::DENIED_TOOL_CALL_OUTPUT);
+            }
+            other => panic!("expected a function call result, got {other:?}"),
+        }
+
+        let result = &c.state().messages.last().unwrap().content.tool_results[0];
+        assert_eq!(result.tool_call_id, "call-1");
+        assert!(result.is_error);
+    }
+}
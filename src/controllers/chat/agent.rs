@@ -0,0 +1,229 @@
+//! Tool loop run by the controller, so bots can keep calling tools until they answer.
+//!
+//! When an answer ends with tool calls, they are executed once approved (right away
+//! if the tool manager has dangerous mode enabled) and their results are sent back
+//! to the bot, until it stops calling tools, the loop hits its limits, or the user
+//! stops it with [`ChatTask::Stop`]. Calls left unexecuted either way get an error
+//! result, so the conversation can go on.
+
+use super::*;
+use std::collections::HashMap;
+
+/// Result given to the bot for a tool call whose execution was stopped.
+const CANCELLED_TOOL_CALL_OUTPUT: &str = "The user stopped the execution of this tool.";
+
+/// Result given to the bot for a tool call left unexecuted as the loop hit its limits.
+const SKIPPED_TOOL_CALL_OUTPUT: &str =
+    "This tool was not executed, as the limit of tool calls was reached.";
+
+/// Limits of the tool loop run by the controller. See [`ChatController::set_agent_loop`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AgentLoop {
+    /// How many rounds of tool calls are executed before stopping, for a single
+    /// message from the user.
+    pub max_iterations: usize,
+    /// How many times a tool can be called with the same arguments before stopping,
+    /// as the bot is likely stuck.
+    pub max_repeated_calls: usize,
+}
+
+impl Default for AgentLoop {
+    fn default() -> Self {
+        Self {
+            max_iterations: 10,
+            max_repeated_calls: 2,
+        }
+    }
+}
+
+impl AgentLoop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn with_max_repeated_calls(mut self, max_repeated_calls: usize) -> Self {
+        self.max_repeated_calls = max_repeated_calls;
+        self
+    }
+}
+
+/// Progress of the tool loop started by the last message from the user.
+#[derive(Debug, Default)]
+pub(super) struct AgentRun {
+    iterations: usize,
+    /// Times each tool was called, by name and arguments.
+    calls: HashMap<(String, String), usize>,
+    /// If the next send continues this run instead of starting a new one.
+    pub(super) continuing: bool,
+    /// Calls waiting for the user to approve or deny them, and the bot asking.
+    pending: Option<(BotId, Vec<String>)>,
+    /// Calls being executed, to give them a result if stopped.
+    pub(super) executing: Vec<ToolCall>,
+}
+
+impl ChatController {
+    /// Makes the controller execute the tools requested by bots, and send their
+    /// results back, by itself. Disabled by default, leaving it to you through
+    /// [`ChatTask::Execute`].
+    ///
+    /// Calls run once their permission status is set to approved (or denied), or
+    /// right away if the tool manager has dangerous mode enabled.
+    pub fn set_agent_loop(&mut self, agent_loop: Option<AgentLoop>) {
+        self.agent_loop = agent_loop;
+    }
+
+    pub fn agent_loop(&self) -> Option<AgentLoop> {
+        self.agent_loop
+    }
+
+    /// Starts counting from zero, unless the send is the loop continuing itself.
+    pub(super) fn start_agent_run(&mut self) {
+        if !self.agent_run.continuing {
+            self.agent_run = AgentRun::default();
+        }
+    }
+
+    /// Runs the tools requested by the answer that just finished, if any.
+    pub(super) fn continue_agent_loop(&mut self, bot_id: BotId) {
+        let Some(agent_loop) = self.agent_loop else {
+            return;
+        };
+        let Some(tool_manager) = &self.tool_manager else {
+            return;
+        };
+        let Some(message) = self.state.messages.last() else {
+            return;
+        };
+        if !matches!(message.from, EntityId::Bot(_)) || message.content.tool_calls.is_empty() {
+            return;
+        }
+
+        let tool_calls = message.content.tool_calls.clone();
+        let dangerous = tool_manager.get_dangerous_mode_enabled();
+
+        if self.agent_run.iterations >= agent_loop.max_iterations {
+            self.push_error_tool_results(&tool_calls, SKIPPED_TOOL_CALL_OUTPUT);
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(format!(
+                "Stopped after {} rounds of tool calls",
+                agent_loop.max_iterations
+            ))));
+            return;
+        }
+
+        for tool_call in &tool_calls {
+            let arguments = serde_json::Value::Object(tool_call.arguments.clone()).to_string();
+            let count = self
+                .agent_run
+                .calls
+                .entry((tool_call.name.clone(), arguments))
+                .or_default();
+            *count += 1;
+
+            if *count > agent_loop.max_repeated_calls {
+                self.push_error_tool_results(&tool_calls, SKIPPED_TOOL_CALL_OUTPUT);
+                self.dispatch_mutation(VecMutation::Push(Message::app_error(format!(
+                    "Stopped because '{}' was called again with the same arguments",
+                    display_name_from_namespaced(&tool_call.name)
+                ))));
+                return;
+            }
+        }
+
+        self.agent_run.iterations += 1;
+
+        if dangerous {
+            self.dispatch_mutation(VecMutation::update_last_with(&self.state.messages, |m| {
+                for tool_call in &mut m.content.tool_calls {
+                    if tool_call.permission_status == ToolCallPermissionStatus::Pending {
+                        tool_call.permission_status = ToolCallPermissionStatus::Approved;
+                    }
+                }
+            }));
+        }
+
+        let ids = tool_calls.into_iter().map(|tc| tc.id).collect();
+        self.agent_run.pending = Some((bot_id, ids));
+        self.resolve_agent_tool_calls();
+    }
+
+    /// Executes the pending calls of the loop once all of them are approved or
+    /// denied, giving the denied ones a result saying so.
+    pub(super) fn resolve_agent_tool_calls(&mut self) {
+        let Some((_, ids)) = &self.agent_run.pending else {
+            return;
+        };
+
+        let mut approved = Vec::new();
+        let mut denied = Vec::new();
+        for id in ids {
+            let Some((index, position)) = self.find_tool_call(id) else {
+                continue;
+            };
+
+            let tool_call = &self.state.messages[index].content.tool_calls[position];
+            match tool_call.permission_status {
+                ToolCallPermissionStatus::Approved => approved.push(tool_call.clone()),
+                ToolCallPermissionStatus::Denied => denied.push(tool_call.clone()),
+                ToolCallPermissionStatus::Pending => return,
+            }
+        }
+
+        let Some((bot_id, _)) = self.agent_run.pending.take() else {
+            return;
+        };
+        self.execute_tool_calls(approved, denied, Some(bot_id));
+    }
+
+    /// Forgets the pending calls of the loop if you execute any of them yourself.
+    pub(super) fn take_agent_tool_calls(&mut self, tool_calls: &[ToolCall]) {
+        if let Some((_, ids)) = &self.agent_run.pending
+            && tool_calls.iter().any(|tc| ids.contains(&tc.id))
+        {
+            self.agent_run.pending = None;
+        }
+    }
+
+    /// Ends the loop, stopping ongoing tool executions. Stopped calls get a result
+    /// saying so.
+    pub(super) fn stop_agent_loop(&mut self) {
+        let executing = std::mem::take(&mut self.agent_run.executing);
+        self.agent_run = AgentRun::default();
+
+        if self.execute_tools_abort_on_drop.is_none() {
+            return;
+        }
+
+        self.clear_in_flight_artifacts();
+
+        self.push_error_tool_results(&executing, CANCELLED_TOOL_CALL_OUTPUT);
+    }
+
+    /// Gives the calls an error result, as bots reject calls without results.
+    fn push_error_tool_results(&mut self, tool_calls: &[ToolCall], output: &str) {
+        let tool_results: Vec<_> = tool_calls
+            .iter()
+            .map(|tc| ToolResult {
+                tool_call_id: tc.id.clone(),
+                content: output.to_string(),
+                is_error: true,
+            })
+            .collect();
+
+        if !tool_results.is_empty() {
+            self.dispatch_mutation(VecMutation::Push(Message {
+                from: EntityId::Tool,
+                content: MessageContent {
+                    text: tool_results_text(tool_calls, &tool_results),
+                    tool_results,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }));
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use crate::protocol::*;
+use crate::utils::asynchronous::BoxPlatformSendFuture;
+use crate::utils::hash::KeyHasher;
+use futures::StreamExt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+/// Rough number of tokens taken by an attachment, as its real size depends on
+/// the provider and the kind of file.
+const ATTACHMENT_TOKENS: usize = 1000;
+
+/// Tokens taken by the role and framing of each message.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Instruction sent after the messages to summarize.
+const DEFAULT_SUMMARY_PROMPT: &str = "Summarize the conversation above in a few short paragraphs, \
+keeping any facts, decisions, names and open questions needed to continue it. \
+Answer only with the summary.";
+
+/// Decides which messages are sent as context to the bot, right before sending.
+///
+/// Messages are given oldest first, as they would be sent. Implementations can
+/// drop, merge or add messages, like [`TruncateContext`] and [`SummarizeContext`] do.
+pub trait ContextStrategy: Send + Sync + 'static {
+    fn apply(&self, messages: Vec<Message>) -> BoxPlatformSendFuture<'static, Vec<Message>>;
+}
+
+impl<S: ContextStrategy + ?Sized> ContextStrategy for Arc<S> {
+    fn apply(&self, messages: Vec<Message>) -> BoxPlatformSendFuture<'static, Vec<Message>> {
+        (**self).apply(messages)
+    }
+}
+
+/// Estimates the tokens of a message, at roughly 4 characters per token.
+///
+/// Good enough to stay under a limit with some margin, without tokenizing.
+pub fn estimate_tokens(message: &Message) -> usize {
+    let content = &message.content;
+    let chars = content.text.len()
+        + content.reasoning.len()
+        + content
+            .tool_calls
+            .iter()
+            .map(|c| {
+                c.name.len()
+                    + serde_json::Value::Object(c.arguments.clone())
+                        .to_string()
+                        .len()
+            })
+            .sum::<usize>()
+        + content
+            .tool_results
+            .iter()
+            .map(|r| r.content.len())
+            .sum::<usize>();
+
+    chars.div_ceil(4) + content.attachments.len() * ATTACHMENT_TOKENS + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Drops the oldest messages that don't fit in a token budget.
+///
+/// System messages (if preserved), pinned messages and the last message are always
+/// kept. A message with tool calls and the tool results following it are kept or
+/// dropped together, as providers reject results without their calls.
+#[derive(Clone, Debug)]
+pub struct TruncateContext {
+    max_tokens: usize,
+    preserve_system: bool,
+    estimator: fn(&Message) -> usize,
+}
+
+impl TruncateContext {
+    /// Keeps the context under `max_tokens`, as estimated by [`estimate_tokens`].
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            preserve_system: true,
+            estimator: estimate_tokens,
+        }
+    }
+
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    /// If system messages should always be kept. Defaults to `true`.
+    pub fn set_preserve_system(&mut self, preserve: bool) {
+        self.preserve_system = preserve;
+    }
+
+    pub fn preserve_system(&self) -> bool {
+        self.preserve_system
+    }
+
+    /// Replaces how tokens are counted, like with a real tokenizer.
+    pub fn set_estimator(&mut self, estimator: fn(&Message) -> usize) {
+        self.estimator = estimator;
+    }
+
+    /// Splits the messages into the dropped ones and the kept ones, both in their
+    /// original order.
+    fn split(&self, messages: Vec<Message>) -> (Vec<Message>, Vec<Message>) {
+        let units = tool_units(&messages);
+        let last = units.len().saturating_sub(1);
+
+        let forced: Vec<bool> = units
+            .iter()
+            .enumerate()
+            .map(|(i, unit)| {
+                let unit = &messages[unit.clone()];
+                i == last
+                    || unit.iter().any(|m| m.metadata.pinned)
+                    || (self.preserve_system && unit.iter().all(|m| m.from == EntityId::System))
+            })
+            .collect();
+
+        let tokens: Vec<usize> = units
+            .iter()
+            .map(|unit| messages[unit.clone()].iter().map(self.estimator).sum())
+            .collect();
+
+        let mut budget = self.max_tokens.saturating_sub(
+            tokens
+                .iter()
+                .zip(&forced)
+                .filter(|(_, forced)| **forced)
+                .map(|(tokens, _)| tokens)
+                .sum(),
+        );
+
+        // Newest first, stopping at the first that doesn't fit, so the kept ones
+        // are the most recent turns without gaps.
+        let mut keep = forced.clone();
+        for i in (0..units.len()).rev().filter(|i| !forced[*i]) {
+            if tokens[i] > budget {
+                break;
+            }
+            budget -= tokens[i];
+            keep[i] = true;
+        }
+
+        let mut dropped = Vec::new();
+        let mut kept = Vec::new();
+        let mut messages = messages.into_iter();
+        for (unit, keep) in units.iter().zip(keep) {
+            let target = if keep { &mut kept } else { &mut dropped };
+            target.extend(messages.by_ref().take(unit.len()));
+        }
+
+        (dropped, kept)
+    }
+}
+
+impl ContextStrategy for TruncateContext {
+    fn apply(&self, messages: Vec<Message>) -> BoxPlatformSendFuture<'static, Vec<Message>> {
+        let (_, kept) = self.split(messages);
+        Box::pin(async move { kept })
+    }
+}
+
+/// Groups messages into the smallest ranges that can be dropped alone, which are
+/// single messages except for tool calls followed by their results.
+fn tool_units(messages: &[Message]) -> Vec<Range<usize>> {
+    let mut units: Vec<Range<usize>> = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        let follows_calls = message.from == EntityId::Tool
+            && units.last().is_some_and(|unit| {
+                unit.end == i && !messages[unit.start].content.tool_calls.is_empty()
+            });
+
+        match units.last_mut() {
+            Some(unit) if follows_calls => unit.end = i + 1,
+            _ => units.push(i..i + 1),
+        }
+    }
+    units
+}
+
+/// Summary of the oldest messages, reused while they stay the same.
+struct CachedSummary {
+    /// Hash of the summarized messages.
+    key: String,
+    /// How many messages it covers.
+    len: usize,
+    text: String,
+}
+
+/// Like [`TruncateContext`], but replaces the dropped messages with a summary of
+/// them, asked to a bot and sent as a system message.
+///
+/// Summaries are reused, and extended when more messages are dropped, so the bot
+/// is only asked when the dropped messages change. If it fails, the messages are
+/// just dropped.
+///
+/// Leave some room in the budget of the truncation for the summary itself.
+pub struct SummarizeContext {
+    truncate: TruncateContext,
+    client: Mutex<Box<dyn BotClient>>,
+    bot_id: BotId,
+    prompt: String,
+    cache: Arc<Mutex<Option<CachedSummary>>>,
+}
+
+impl SummarizeContext {
+    /// Summarizes what `truncate` would drop, asking `bot_id` through `client`.
+    pub fn new(truncate: TruncateContext, client: Box<dyn BotClient>, bot_id: BotId) -> Self {
+        Self {
+            truncate,
+            client: Mutex::new(client),
+            bot_id,
+            prompt: DEFAULT_SUMMARY_PROMPT.to_string(),
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Replaces the instruction sent after the messages to summarize them.
+    pub fn set_prompt(&mut self, prompt: impl Into<String>) {
+        self.prompt = prompt.into();
+    }
+
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    pub fn truncate(&self) -> &TruncateContext {
+        &self.truncate
+    }
+}
+
+impl ContextStrategy for SummarizeContext {
+    fn apply(&self, messages: Vec<Message>) -> BoxPlatformSendFuture<'static, Vec<Message>> {
+        let (dropped, mut kept) = self.truncate.split(messages);
+        let mut client = self.client.lock().unwrap().clone();
+        let bot_id = self.bot_id.clone();
+        let prompt = self.prompt.clone();
+        let cache = self.cache.clone();
+
+        Box::pin(async move {
+            if dropped.is_empty() {
+                return kept;
+            }
+
+            let key = summary_key(&dropped);
+            let previous = cache.lock().unwrap().take();
+            let (previous_text, new) = match previous {
+                Some(cached) if cached.key == key => {
+                    let text = cached.text.clone();
+                    *cache.lock().unwrap() = Some(cached);
+                    kept.insert(summary_position(&kept), summary_message(&text));
+                    return kept;
+                }
+                Some(cached)
+                    if cached.len < dropped.len()
+                        && summary_key(&dropped[..cached.len]) == cached.key =>
+                {
+                    (Some(cached.text), &dropped[cached.len..])
+                }
+                _ => (None, &dropped[..]),
+            };
+
+            let mut request: Vec<Message> = previous_text
+                .iter()
+                .map(|text| summary_message(text))
+                .chain(new.iter().map(plain_text))
+                .collect();
+            request.push(Message {
+                from: EntityId::User,
+                content: MessageContent {
+                    text: prompt,
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+
+            match summarize(&mut client, &bot_id, &request).await {
+                Ok(text) => {
+                    kept.insert(summary_position(&kept), summary_message(&text));
+                    *cache.lock().unwrap() = Some(CachedSummary {
+                        key,
+                        len: dropped.len(),
+                        text,
+                    });
+                }
+                Err(error) => log::warn!("Failed to summarize the context: {error}"),
+            }
+
+            kept
+        })
+    }
+}
+
+async fn summarize(
+    client: &mut Box<dyn BotClient>,
+    bot_id: &BotId,
+    messages: &[Message],
+) -> Result<String, ClientError> {
+    let mut stream = client.send(bot_id, messages, &[]);
+    let mut content = None;
+    while let Some(result) = stream.next().await {
+        let (value, errors) = result.into_value_and_errors();
+        if let Some(error) = errors.into_iter().next() {
+            return Err(error);
+        }
+        content = value.or(content);
+    }
+
+    content
+        .map(|c| c.text.trim().to_string())
+        .filter(|text| !text.is_empty())
+        .ok_or_else(|| ClientError::new(ClientErrorKind::Response, "Empty summary".into()))
+}
+
+fn summary_key(messages: &[Message]) -> String {
+    let mut hasher = KeyHasher::new();
+    for message in messages {
+        hasher.update_json(&message.from);
+        hasher.update_json(&message.content.text);
+        hasher.update_json(&message.content.tool_calls);
+        hasher.update_json(&message.content.tool_results);
+    }
+    hasher.finish()
+}
+
+/// Right after the leading system messages.
+fn summary_position(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .take_while(|m| m.from == EntityId::System)
+        .count()
+}
+
+fn summary_message(text: &str) -> Message {
+    Message {
+        from: EntityId::System,
+        content: MessageContent {
+            text: format!("Summary of the earlier conversation:\n\n{text}"),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// The message as plain text, as tool calls without their definitions (and
+/// attachments) can't be sent to a bot that is just summarizing.
+fn plain_text(message: &Message) -> Message {
+    let content = &message.content;
+    let mut text = content.text.clone();
+    for call in &content.tool_calls {
+        let arguments = serde_json::Value::Object(call.arguments.clone());
+        text.push_str(&format!("\n[Called tool {} with {arguments}]", call.name));
+    }
+    for result in &content.tool_results {
+        text.push_str(&format!("\n[Tool result: {}]", result.content));
+    }
+
+    let from = match &message.from {
+        EntityId::Tool => EntityId::User,
+        from => from.clone(),
+    };
+
+    Message {
+        from,
+        content: MessageContent {
+            text,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use futures::executor::block_on;
+
+    fn message(from: EntityId, text: &str) -> Message {
+        Message {
+            from,
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn bot() -> EntityId {
+        EntityId::Bot(BotId::new("bot"))
+    }
+
+    fn texts(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.text.as_str()).collect()
+    }
+
+    /// Every message takes 10 tokens.
+    fn truncate(max_tokens: usize) -> TruncateContext {
+        let mut truncate = TruncateContext::new(max_tokens);
+        truncate.set_estimator(|_| 10);
+        truncate
+    }
+
+    #[test]
+    fn truncation_keeps_system_pinned_and_tool_pairs() {
+        let mut pinned = message(EntityId::User, "pinned");
+        pinned.metadata.pinned = true;
+        let mut call = message(bot(), "call");
+        call.content.tool_calls.push(ToolCall {
+            id: "1".into(),
+            name: "tool".into(),
+            ..Default::default()
+        });
+
+        let messages = vec![
+            message(EntityId::System, "system"),
+            pinned,
+            message(EntityId::User, "old"),
+            call,
+            message(EntityId::Tool, "result"),
+            message(EntityId::User, "question"),
+        ];
+
+        // Fits the forced messages, and one more, but not the pair.
+        let kept = block_on(truncate(40).apply(messages.clone()));
+        assert_eq!(texts(&kept), ["system", "pinned", "question"]);
+
+        let kept = block_on(truncate(50).apply(messages.clone()));
+        assert_eq!(
+            texts(&kept),
+            ["system", "pinned", "call", "result", "question"]
+        );
+
+        // The last message is kept even if over the budget.
+        let kept = block_on(truncate(0).apply(messages));
+        assert_eq!(texts(&kept), ["system", "pinned", "question"]);
+    }
+
+    #[test]
+    fn summaries_replace_dropped_messages_and_are_reused() {
+        // Summarizes as the texts sent, without the prompt.
+        let client = MockClient::responding(|call| {
+            let sent = &call.messages[..call.messages.len() - 1];
+            MockReply::text(
+                sent.iter()
+                    .map(|m| m.content.text.clone())
+                    .collect::<Vec<_>>()
+                    .join("+"),
+            )
+        });
+        let strategy =
+            SummarizeContext::new(truncate(20), Box::new(client.clone()), BotId::new("small"));
+
+        let mut messages = vec![
+            message(EntityId::System, "system"),
+            message(EntityId::User, "a"),
+            message(bot(), "b"),
+            message(EntityId::User, "c"),
+        ];
+
+        let context = block_on(strategy.apply(messages.clone()));
+        assert_eq!(context.len(), 3);
+        assert_eq!(
+            context[1].content.text,
+            "Summary of the earlier conversation:\n\na+b"
+        );
+
+        block_on(strategy.apply(messages.clone()));
+        assert_eq!(client.call_count(), 1);
+
+        // Only the newly dropped message is summarized, with the previous summary.
+        messages.push(message(bot(), "d"));
+        let context = block_on(strategy.apply(messages));
+        assert_eq!(
+            context[1].content.text,
+            "Summary of the earlier conversation:\n\n\
+            Summary of the earlier conversation:\n\na+b+c"
+        );
+        assert_eq!(client.call_count(), 2);
+    }
+}
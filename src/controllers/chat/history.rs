@@ -0,0 +1,376 @@
+use super::state::*;
+use crate::utils::vec::*;
+
+/// Default maximum number of steps that can be undone.
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+enum Replay {
+    #[default]
+    None,
+    Undo,
+    Redo,
+}
+
+/// Undo and redo stacks for the state of a [`super::ChatController`].
+///
+/// Each step holds the mutations reverting one or more dispatched batches, in the
+/// order they must be applied. Only messages and the selected bot are recorded, as
+/// the rest of the state reflects external resources or ongoing work.
+#[derive(Debug)]
+pub(super) struct History {
+    enabled: bool,
+    limit: usize,
+    undo: Vec<Vec<ChatStateMutation>>,
+    redo: Vec<Vec<ChatStateMutation>>,
+    /// Inverse of the batch being dispatched, built one mutation at a time.
+    pending: Vec<ChatStateMutation>,
+    /// If the batch being dispatched only touches messages that are being written.
+    pending_is_chunk: bool,
+    group_depth: usize,
+    /// If the outermost open group already pushed its step.
+    group_started: bool,
+    /// If an answer (or tool execution) is in progress, so everything recorded
+    /// belongs to the step that started it.
+    streaming: bool,
+    replay: Replay,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            limit: DEFAULT_LIMIT,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            pending: Vec::new(),
+            pending_is_chunk: true,
+            group_depth: 0,
+            group_started: false,
+            streaming: false,
+            replay: Replay::None,
+        }
+    }
+}
+
+impl History {
+    pub(super) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    pub(super) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub(super) fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        let excess = self.undo.len().saturating_sub(limit);
+        self.undo.drain(..excess);
+        self.redo.truncate(limit);
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub(super) fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub(super) fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub(super) fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group_started = false;
+        }
+        self.group_depth += 1;
+    }
+
+    pub(super) fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+    }
+
+    pub(super) fn set_streaming(&mut self, streaming: bool) {
+        self.streaming = streaming;
+    }
+
+    /// Records the inverse of a mutation about to be applied to the given state.
+    ///
+    /// Call [`Self::commit`] once the whole batch has been applied.
+    pub(super) fn observe(&mut self, mutation: &ChatStateMutation, state: &ChatState) {
+        if !self.enabled {
+            return;
+        }
+
+        match mutation {
+            ChatStateMutation::MutateMessages(mutation) => {
+                let is_chunk = mutation
+                    .effects(&state.messages)
+                    .all(|effect| match effect {
+                        VecEffect::Insert(..) => false,
+                        VecEffect::Update(_, previous, _) => previous.metadata.is_writing,
+                        VecEffect::Remove(_, _, previous) => {
+                            previous.iter().all(|m| m.metadata.is_writing)
+                        }
+                    });
+                self.pending_is_chunk &= is_chunk;
+
+                let inverse = mutation
+                    .inverse(&state.messages)
+                    .into_iter()
+                    .map(ChatStateMutation::MutateMessages);
+                self.pending.splice(0..0, inverse);
+            }
+            ChatStateMutation::SetBotId(bot_id) if *bot_id != state.bot_id => {
+                self.pending_is_chunk = false;
+                self.pending
+                    .insert(0, ChatStateMutation::SetBotId(state.bot_id.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Turns the mutations observed since the last commit into an undo step.
+    ///
+    /// Batches only changing messages being written (streaming chunks), batches
+    /// dispatched while streaming and batches inside an open group are merged
+    /// into the previous step instead.
+    pub(super) fn commit(&mut self) {
+        let step = std::mem::take(&mut self.pending);
+        let is_chunk = std::mem::replace(&mut self.pending_is_chunk, true);
+        if step.is_empty() {
+            return;
+        }
+
+        match self.replay {
+            Replay::Undo => return self.redo.push(step),
+            Replay::Redo => return self.push_undo(step),
+            Replay::None => {}
+        }
+
+        self.redo.clear();
+
+        let amend = is_chunk || self.streaming || (self.group_depth > 0 && self.group_started);
+        match self.undo.last_mut() {
+            Some(last) if amend => prepend(last, step),
+            _ => {
+                self.push_undo(step);
+                self.group_started = self.group_depth > 0;
+            }
+        }
+    }
+
+    /// Takes the step to dispatch for undoing, recording its inverse for redoing.
+    ///
+    /// Call [`Self::finish_replay`] after dispatching it.
+    pub(super) fn start_undo(&mut self) -> Option<Vec<ChatStateMutation>> {
+        let step = self.undo.pop()?;
+        self.replay = Replay::Undo;
+        Some(step)
+    }
+
+    /// Takes the step to dispatch for redoing, recording its inverse for undoing.
+    ///
+    /// Call [`Self::finish_replay`] after dispatching it.
+    pub(super) fn start_redo(&mut self) -> Option<Vec<ChatStateMutation>> {
+        let step = self.redo.pop()?;
+        self.replay = Replay::Redo;
+        Some(step)
+    }
+
+    pub(super) fn finish_replay(&mut self) {
+        self.replay = Replay::None;
+    }
+
+    fn push_undo(&mut self, step: Vec<ChatStateMutation>) {
+        self.undo.push(step);
+        if self.undo.len() > self.limit {
+            self.undo.remove(0);
+        }
+    }
+}
+
+/// Puts `mutations` before the ones in `step`, so they are undone first.
+///
+/// Consecutive updates to the same message are reverted by the last one alone,
+/// so this keeps a single update per message when streaming into it.
+fn prepend(step: &mut Vec<ChatStateMutation>, mutations: Vec<ChatStateMutation>) {
+    for mutation in mutations.into_iter().rev() {
+        if let (
+            ChatStateMutation::MutateMessages(VecMutation::Update(index, _)),
+            Some(ChatStateMutation::MutateMessages(VecMutation::Update(first, _))),
+        ) = (&mutation, step.first())
+            && index == first
+        {
+            continue;
+        }
+
+        step.insert(0, mutation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::*;
+
+    fn text(text: &str, is_writing: bool) -> Message {
+        Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            metadata: MessageMetadata {
+                is_writing,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn dispatch(history: &mut History, state: &mut ChatState, mutations: Vec<ChatStateMutation>) {
+        for mutation in mutations {
+            history.observe(&mutation, state);
+            mutation.apply(state);
+        }
+        history.commit();
+    }
+
+    fn undo(history: &mut History, state: &mut ChatState) {
+        let step = history.start_undo().unwrap();
+        dispatch(history, state, step);
+        history.finish_replay();
+    }
+
+    fn redo(history: &mut History, state: &mut ChatState) {
+        let step = history.start_redo().unwrap();
+        dispatch(history, state, step);
+        history.finish_replay();
+    }
+
+    fn texts(state: &ChatState) -> Vec<&str> {
+        state
+            .messages
+            .iter()
+            .map(|m| m.content.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_undo_and_redo_batches() {
+        let mut history = History::default();
+        history.set_enabled(true);
+        let mut state = ChatState::default();
+
+        dispatch(
+            &mut history,
+            &mut state,
+            vec![
+                VecMutation::Push(text("a", false)).into(),
+                ChatStateMutation::SetBotId(Some(BotId::new("bot"))),
+            ],
+        );
+        dispatch(
+            &mut history,
+            &mut state,
+            vec![VecMutation::Update(0, text("b", false)).into()],
+        );
+
+        undo(&mut history, &mut state);
+        assert_eq!(texts(&state), ["a"]);
+        undo(&mut history, &mut state);
+        assert!(state.messages.is_empty());
+        assert_eq!(state.bot_id, None);
+        assert!(!history.can_undo());
+
+        redo(&mut history, &mut state);
+        redo(&mut history, &mut state);
+        assert_eq!(texts(&state), ["b"]);
+        assert_eq!(state.bot_id, Some(BotId::new("bot")));
+        assert!(!history.can_redo());
+
+        // Recording something new forgets what could be redone.
+        undo(&mut history, &mut state);
+        dispatch(
+            &mut history,
+            &mut state,
+            vec![VecMutation::Push(text("c", false)).into()],
+        );
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_streaming_chunks_are_one_step() {
+        let mut history = History::default();
+        history.set_enabled(true);
+        let mut state = ChatState::default();
+
+        dispatch(
+            &mut history,
+            &mut state,
+            vec![VecMutation::Push(text("", true)).into()],
+        );
+        for chunk in ["h", "he", "hello"] {
+            dispatch(
+                &mut history,
+                &mut state,
+                vec![VecMutation::Update(0, text(chunk, true)).into()],
+            );
+        }
+        dispatch(
+            &mut history,
+            &mut state,
+            vec![VecMutation::Update(0, text("hello", false)).into()],
+        );
+
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(history.undo[0].len(), 2);
+
+        undo(&mut history, &mut state);
+        assert!(state.messages.is_empty());
+        redo(&mut history, &mut state);
+        assert_eq!(texts(&state), ["hello"]);
+        assert!(!state.messages[0].metadata.is_writing);
+    }
+
+    #[test]
+    fn test_groups_and_limit() {
+        let mut history = History::default();
+        history.set_enabled(true);
+        history.set_limit(2);
+        let mut state = ChatState::default();
+
+        history.begin_group();
+        for t in ["a", "b"] {
+            dispatch(
+                &mut history,
+                &mut state,
+                vec![VecMutation::Push(text(t, false)).into()],
+            );
+        }
+        history.end_group();
+
+        for t in ["c", "d"] {
+            dispatch(
+                &mut history,
+                &mut state,
+                vec![VecMutation::Push(text(t, false)).into()],
+            );
+        }
+
+        undo(&mut history, &mut state);
+        undo(&mut history, &mut state);
+        assert_eq!(texts(&state), ["a", "b"]);
+        assert!(!history.can_undo());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::protocol::*;
+use serde::{Deserialize, Serialize};
+
+/// Instructions sent to the bot as a leading system message, kept apart from the
+/// messages so they can't be deleted or reordered by accident.
+///
+/// Templates can include variables like `{{date}}`, resolved when sending. See
+/// [`super::ChatController::set_template_variable`] for the available ones.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct SystemInstructions {
+    /// Template used for bots without an override.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Templates replacing the default one for specific bots.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<(BotId, String)>,
+}
+
+impl SystemInstructions {
+    /// Instructions using the same template for every bot.
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            default: Some(template.into()),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Uses a different template for the given bot, replacing any previous one.
+    pub fn with_override(mut self, bot_id: BotId, template: impl Into<String>) -> Self {
+        self.set_override(bot_id, Some(template.into()));
+        self
+    }
+
+    /// Sets or removes the template used for the given bot.
+    pub fn set_override(&mut self, bot_id: BotId, template: Option<String>) {
+        self.overrides.retain(|(id, _)| *id != bot_id);
+        if let Some(template) = template {
+            self.overrides.push((bot_id, template));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.overrides.is_empty()
+    }
+
+    /// The template used for the given bot, if any.
+    pub fn template_for(&self, bot_id: Option<&BotId>) -> Option<&str> {
+        self.overrides
+            .iter()
+            .find(|(id, _)| Some(id) == bot_id)
+            .map(|(_, template)| template.as_str())
+            .or(self.default.as_deref())
+    }
+
+    /// The instructions for the given bot, with its variables resolved by `variable`.
+    ///
+    /// Unknown variables are left as they are. Gives `None` if there is nothing to send.
+    pub fn render(
+        &self,
+        bot_id: Option<&BotId>,
+        variable: impl Fn(&str) -> Option<String>,
+    ) -> Option<String> {
+        let text = render_template(self.template_for(bot_id)?, variable);
+        (!text.trim().is_empty()).then_some(text)
+    }
+}
+
+/// Replaces the `{{name}}` placeholders of the template.
+fn render_template(template: &str, variable: impl Fn(&str) -> Option<String>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+
+        text.push_str(&rest[..start]);
+        match variable(rest[start + 2..end].trim()) {
+            Some(value) => text.push_str(&value),
+            None => text.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+
+    text.push_str(rest);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_and_variables() {
+        let instructions = SystemInstructions::new("Talk to {{ user_name }} in {{locale}}.")
+            .with_override(BotId::new("coder"), "Write code. {{unknown}}");
+
+        let variable = |name: &str| (name == "user_name").then(|| "Ana".to_string());
+
+        assert_eq!(
+            instructions.render(None, variable).as_deref(),
+            Some("Talk to Ana in {{locale}}.")
+        );
+        assert_eq!(
+            instructions
+                .render(Some(&BotId::new("coder")), variable)
+                .as_deref(),
+            Some("Write code. {{unknown}}")
+        );
+        assert_eq!(SystemInstructions::default().render(None, variable), None);
+    }
+}
@@ -53,6 +53,10 @@ pub trait ChatControllerPlugin: Send {
     /// > Note: Mutations are the focus of this method, so they are given as the first parameter.
     fn on_state_mutation(&mut self, _mutation: &ChatStateMutation, _state: &ChatState) {}
 
+    /// Called when a [`BotClient`] answers with an [`Upgrade`] instead of a message.
+    ///
+    /// Prefer [`ChatTask::StartRealtime`] and `state.realtime_channel` for realtime
+    /// sessions. This remains for clients that still upgrade from a send.
     // TODO: Remove this very specific method later.
     fn on_upgrade(&mut self, upgrade: Upgrade, _bot_id: &BotId) -> Option<Upgrade> {
         Some(upgrade)
@@ -0,0 +1,176 @@
+use crate::protocol::*;
+use crate::utils::tool::create_tool_output_summary;
+
+/// Rules rewriting past messages before sending them again as context.
+///
+/// Each rule applies to messages at least the given number of turns old, where a
+/// turn starts with each user message. The last user message and everything after
+/// it are `0` turns old, so `0` applies a rule to every message.
+///
+/// Nothing is changed by default. Messages with `hidden_from_model` set in their
+/// metadata are never sent, regardless of these rules.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HistoryProjection {
+    /// Removes the reasoning of bot messages, which some providers reject.
+    pub strip_reasoning_after: Option<usize>,
+    /// Replaces attachments with a note naming them.
+    pub drop_attachments_after: Option<usize>,
+    /// Replaces tool results with their summaries.
+    pub collapse_tool_results_after: Option<usize>,
+}
+
+impl HistoryProjection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_strip_reasoning_after(mut self, turns: usize) -> Self {
+        self.strip_reasoning_after = Some(turns);
+        self
+    }
+
+    pub fn with_drop_attachments_after(mut self, turns: usize) -> Self {
+        self.drop_attachments_after = Some(turns);
+        self
+    }
+
+    pub fn with_collapse_tool_results_after(mut self, turns: usize) -> Self {
+        self.collapse_tool_results_after = Some(turns);
+        self
+    }
+
+    /// Applies the rules to the given messages, oldest first.
+    pub fn project(&self, mut messages: Vec<Message>) -> Vec<Message> {
+        if *self == Self::default() {
+            return messages;
+        }
+
+        let applies = |rule: Option<usize>, age: usize| rule.is_some_and(|turns| age >= turns);
+
+        let mut age = 0;
+        for index in (0..messages.len()).rev() {
+            if applies(self.collapse_tool_results_after, age) {
+                collapse_tool_results(&mut messages, index);
+            }
+
+            let message = &mut messages[index];
+
+            if applies(self.strip_reasoning_after, age) && matches!(message.from, EntityId::Bot(_))
+            {
+                message.content.reasoning.clear();
+            }
+
+            if applies(self.drop_attachments_after, age) {
+                for attachment in std::mem::take(&mut message.content.attachments) {
+                    if !message.content.text.is_empty() {
+                        message.content.text.push_str("\n\n");
+                    }
+                    message
+                        .content
+                        .text
+                        .push_str(&format!("[Attachment omitted: {}]", attachment.name));
+                }
+            }
+
+            if message.from == EntityId::User {
+                age += 1;
+            }
+        }
+
+        messages
+    }
+}
+
+/// Replaces the results in the message at `index` with their summaries, looking
+/// for the calls that requested them in the messages before.
+fn collapse_tool_results(messages: &mut [Message], index: usize) {
+    let (previous, rest) = messages.split_at_mut(index);
+    for result in &mut rest[0].content.tool_results {
+        let name = previous
+            .iter()
+            .rev()
+            .flat_map(|m| &m.content.tool_calls)
+            .find(|call| call.id == result.tool_call_id)
+            .map(|call| call.name.as_str())
+            .unwrap_or_default();
+
+        let summary = create_tool_output_summary(name, &result.content);
+        if summary.len() < result.content.len() {
+            result.content = summary;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: EntityId, text: &str) -> Message {
+        Message {
+            from,
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules_apply_to_old_turns() {
+        let bot = EntityId::Bot(BotId::new("bot"));
+
+        let mut question = message(EntityId::User, "look");
+        question
+            .content
+            .attachments
+            .push(Attachment::from_bytes("a.png".into(), None, b"png"));
+
+        let mut call = message(bot.clone(), "");
+        call.content.reasoning = "thinking".into();
+        call.content.tool_calls.push(ToolCall {
+            id: "1".into(),
+            name: "search".into(),
+            ..Default::default()
+        });
+
+        let mut result = message(EntityId::Tool, "");
+        result.content.tool_results.push(ToolResult {
+            tool_call_id: "1".into(),
+            content: r#"{"summary": "3 results", "items": [1, 2, 3]}"#.into(),
+            is_error: false,
+        });
+
+        let mut answer = message(bot, "done");
+        answer.content.reasoning = "recent".into();
+
+        let messages = vec![
+            question,
+            call,
+            result,
+            message(EntityId::User, "thanks"),
+            answer,
+        ];
+
+        let projected = HistoryProjection::new()
+            .with_strip_reasoning_after(1)
+            .with_drop_attachments_after(1)
+            .with_collapse_tool_results_after(1)
+            .project(messages.clone());
+
+        assert_eq!(
+            projected[0].content.text,
+            "look\n\n[Attachment omitted: a.png]"
+        );
+        assert!(projected[0].content.attachments.is_empty());
+        assert!(projected[1].content.reasoning.is_empty());
+        assert_eq!(projected[2].content.tool_results[0].content, "3 results");
+        // The current turn is untouched.
+        assert_eq!(projected[3..], messages[3..]);
+
+        assert_eq!(
+            HistoryProjection::default().project(messages.clone()),
+            messages
+        );
+    }
+}
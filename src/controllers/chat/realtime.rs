@@ -0,0 +1,709 @@
+//! Realtime sessions handled by the controller.
+//!
+//! Sessions are started with [`ChatTask::StartRealtime`] through a [`RealtimeClient`],
+//! or handed over by a [`BotClient`] as an [`Upgrade`]. Before the channel is exposed,
+//! the controller wraps both directions of it, so the session lifecycle is reflected
+//! in the state, and transcripts and tool calls are recorded as regular messages,
+//! while the session events and commands keep flowing unchanged.
+
+use super::*;
+use crate::utils::tool::parse_tool_arguments;
+
+/// Bookkeeping of the realtime session currently bridged by the controller.
+#[cfg(feature = "mcp")]
+pub(super) struct RealtimeSession {
+    /// Sender connected directly to the realtime client, skipping the bridge.
+    command_sender: futures::channel::mpsc::UnboundedSender<RealtimeCommand>,
+    /// Ids of tool calls waiting for the user to approve or deny them.
+    pending_tool_calls: Vec<String>,
+}
+
+/// Messages pushed by the bridge that are still being written.
+///
+/// They are tagged with placeholder item ids until their transcripts complete, so
+/// other messages being written at the same time, like a text answer, are left alone.
+#[derive(Default)]
+pub(super) struct RealtimeWriting {
+    next: u64,
+    item_ids: Vec<String>,
+}
+
+impl RealtimeWriting {
+    fn add(&mut self) -> String {
+        self.next += 1;
+        let item_id = format!("realtime-writing-{}", self.next);
+        self.item_ids.push(item_id.clone());
+        item_id
+    }
+
+    fn contains(&self, message: &Message) -> bool {
+        message
+            .metadata
+            .item_id
+            .as_ref()
+            .is_some_and(|id| self.item_ids.contains(id))
+    }
+
+    fn remove(&mut self, message: &Message) {
+        self.item_ids
+            .retain(|id| message.metadata.item_id.as_ref() != Some(id));
+    }
+}
+
+/// Output sent back to the bot when the user denies a tool call.
+#[cfg(feature = "mcp")]
+pub(super) const DENIED_TOOL_CALL_OUTPUT: &str = "The user denied the execution of this tool.";
+
+impl ChatController {
+    pub(super) fn handle_start_realtime(&mut self) {
+        // Only one session at a time.
+        self.handle_stop_realtime();
+
+        let Some(bot_id) = self.state.bot_id.clone() else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error("No bot selected")));
+            return;
+        };
+
+        if let Some(bot) = self.state.get_bot(&bot_id)
+            && !bot.supports_realtime()
+        {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(format!(
+                "{} does not support realtime sessions",
+                bot.name
+            ))));
+            return;
+        }
+
+        let Some(mut spawner) = self.spawner.clone() else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(
+                "No async spawner configured",
+            )));
+            return;
+        };
+
+        let Some(mut client) = self.realtime_client.clone() else {
+            self.dispatch_mutation(VecMutation::Push(Message::app_error(
+                "No realtime client configured",
+            )));
+            return;
+        };
+
+        self.dispatch_mutation(ChatStateMutation::SetRealtimeStatus(Status::Working));
+
+        let messages_context = self
+            .state
+            .messages
+            .iter()
+            .filter(|m| m.from != EntityId::App && !m.metadata.is_writing)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let controller = self.accessor.clone();
+        self.realtime_abort_on_drop = Some(spawner.spawn_abort_on_drop(async move {
+            let Some(tools) = controller.lock_with(|c| c.get_all_namespaced_tools()) else {
+                return;
+            };
+
+            let result = client.start(&bot_id, &messages_context, &tools).await;
+            controller.lock_with(|c| match result.into_result() {
+                Ok(channel) => {
+                    c.expose_realtime_channel(channel, &bot_id);
+                }
+                Err(errors) => {
+                    c.dispatch_mutation(ChatStateMutation::SetRealtimeStatus(Status::Error));
+                    let messages: Vec<_> =
+                        errors.into_iter().map(Message::from_client_error).collect();
+                    c.dispatch_mutation(VecMutation::Extend(messages));
+                }
+            });
+        }));
+    }
+
+    pub(super) fn handle_stop_realtime(&mut self) {
+        // Cancels any session still connecting.
+        self.realtime_abort_on_drop = None;
+
+        if let Some(channel) = self.state.realtime_channel.clone() {
+            match &mut self.realtime_client {
+                Some(client) => client.stop(&channel),
+                None => {
+                    let _ = channel
+                        .command_sender
+                        .unbounded_send(RealtimeCommand::StopSession);
+                }
+            }
+        }
+
+        self.end_realtime_session();
+    }
+
+    /// Bridges the given channel and publishes it in the state as the ongoing session.
+    ///
+    /// Returns the bridged channel.
+    pub(super) fn expose_realtime_channel(
+        &mut self,
+        channel: RealtimeChannel,
+        bot_id: &BotId,
+    ) -> RealtimeChannel {
+        let channel = self.bridge_realtime_channel(channel, bot_id);
+        self.dispatch_mutations(vec![
+            ChatStateMutation::SetRealtimeChannel(Some(channel.clone())),
+            ChatStateMutation::SetRealtimeStatus(Status::Success),
+        ]);
+        channel
+    }
+
+    /// Clears everything related to the ongoing realtime session, if any.
+    fn end_realtime_session(&mut self) {
+        #[cfg(feature = "mcp")]
+        {
+            self.realtime_session = None;
+        }
+
+        if self.state.realtime_channel.is_none() && self.state.realtime_status.is_idle() {
+            return;
+        }
+
+        self.finish_realtime_messages();
+        self.dispatch_mutations(vec![
+            ChatStateMutation::SetRealtimeChannel(None),
+            ChatStateMutation::SetRealtimeStatus(Status::Idle),
+        ]);
+    }
+
+    /// Interposes between the realtime client and whoever consumes the channel.
+    ///
+    /// Returns the channel that should be exposed instead of the original one.
+    fn bridge_realtime_channel(
+        &mut self,
+        channel: RealtimeChannel,
+        bot_id: &BotId,
+    ) -> RealtimeChannel {
+        let Some(mut spawner) = self.spawner.clone() else {
+            return channel;
+        };
+
+        let Some(mut client_events) = channel.event_receiver.lock().unwrap().take() else {
+            // Someone already took the receiver, so there is nothing we can observe.
+            return channel;
+        };
+
+        let (event_sender, event_receiver) = futures::channel::mpsc::unbounded();
+        let (command_sender, mut command_receiver) = futures::channel::mpsc::unbounded();
+
+        // Events from a previous session must not end the new one.
+        self.realtime_generation += 1;
+        let generation = self.realtime_generation;
+
+        let controller = self.accessor.clone();
+        let bot_id = bot_id.clone();
+        spawner.spawn(async move {
+            while let Some(event) = client_events.next().await {
+                controller.lock_with(|c| {
+                    if c.realtime_generation == generation {
+                        c.record_realtime_event(&event, &bot_id);
+                    }
+                });
+                // The consumer may be gone, but the session may still be alive.
+                let _ = event_sender.unbounded_send(event);
+            }
+
+            controller.lock_with(|c| {
+                if c.realtime_generation == generation {
+                    c.end_realtime_session();
+                }
+            });
+        });
+
+        #[cfg(feature = "mcp")]
+        {
+            self.realtime_session = Some(RealtimeSession {
+                command_sender: channel.command_sender.clone(),
+                pending_tool_calls: Vec::new(),
+            });
+        }
+
+        let controller = self.accessor.clone();
+        let client_commands = channel.command_sender.clone();
+        spawner.spawn(async move {
+            while let Some(command) = command_receiver.next().await {
+                controller.lock_with(|c| {
+                    if c.realtime_generation == generation {
+                        c.record_realtime_command(&command);
+                    }
+                });
+                if client_commands.unbounded_send(command).is_err() {
+                    break;
+                }
+            }
+        });
+
+        RealtimeChannel {
+            event_sender: channel.event_sender,
+            event_receiver: Arc::new(Mutex::new(Some(event_receiver))),
+            command_sender,
+        }
+    }
+
+    fn record_realtime_event(&mut self, event: &RealtimeEvent, bot_id: &BotId) {
+        match event {
+            RealtimeEvent::SessionClosed => {
+                self.end_realtime_session();
+                return;
+            }
+            RealtimeEvent::Reconnecting { .. } => {
+                // Whatever was in progress is lost with the connection.
+                self.finish_realtime_messages();
+                if !self.state.realtime_status.is_working() {
+                    self.dispatch_mutation(ChatStateMutation::SetRealtimeStatus(Status::Working));
+                }
+                return;
+            }
+            RealtimeEvent::Reconnected => {
+                self.dispatch_mutation(ChatStateMutation::SetRealtimeStatus(Status::Success));
+                return;
+            }
+            _ => {}
+        }
+
+        if !self.realtime_history_enabled {
+            return;
+        }
+
+        match event {
+            RealtimeEvent::SpeechStopped => {
+                // The transcription of the user audio arrives asynchronously, normally
+                // after the bot started answering, so keep the place of the user message.
+                let item_id = self.realtime_writing.add();
+                self.dispatch_mutation(VecMutation::Push(Message {
+                    from: EntityId::User,
+                    metadata: MessageMetadata {
+                        is_writing: true,
+                        item_id: Some(item_id),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }));
+            }
+            RealtimeEvent::UserTranscriptCompleted(transcript, item_id) => {
+                let placeholder =
+                    self.state.messages.iter().position(|m| {
+                        m.from == EntityId::User && self.realtime_writing.contains(m)
+                    });
+
+                let mutation = match placeholder {
+                    Some(index) => VecMutation::update_with(&self.state.messages, index, |m| {
+                        self.realtime_writing.remove(m);
+                        m.update_content(|c| c.text = transcript.clone());
+                        m.metadata.is_writing = false;
+                        m.metadata.item_id = Some(item_id.clone());
+                    }),
+                    None => VecMutation::Push(Message {
+                        from: EntityId::User,
+                        content: MessageContent {
+                            text: transcript.clone(),
+                            ..Default::default()
+                        },
+                        metadata: MessageMetadata {
+                            item_id: Some(item_id.clone()),
+                            ..Default::default()
+                        },
+                    }),
+                };
+
+                self.dispatch_mutation(mutation);
+            }
+            RealtimeEvent::AudioTranscript(delta) => {
+                let mutation = match self.writing_realtime_bot_message(bot_id) {
+                    Some(index) => VecMutation::update_with(&self.state.messages, index, |m| {
+                        m.update_content(|c| c.text.push_str(delta));
+                    }),
+                    None => VecMutation::Push(Message {
+                        from: EntityId::Bot(bot_id.clone()),
+                        content: MessageContent {
+                            text: delta.clone(),
+                            ..Default::default()
+                        },
+                        metadata: MessageMetadata {
+                            is_writing: true,
+                            item_id: Some(self.realtime_writing.add()),
+                            ..Default::default()
+                        },
+                    }),
+                };
+
+                self.dispatch_mutation(mutation);
+            }
+            RealtimeEvent::AudioTranscriptCompleted(transcript, item_id) => {
+                let mutation = match self.writing_realtime_bot_message(bot_id) {
+                    Some(index) => VecMutation::update_with(&self.state.messages, index, |m| {
+                        self.realtime_writing.remove(m);
+                        m.update_content(|c| c.text = transcript.clone());
+                        m.metadata.is_writing = false;
+                        m.metadata.item_id = Some(item_id.clone());
+                    }),
+                    None => VecMutation::Push(Message {
+                        from: EntityId::Bot(bot_id.clone()),
+                        content: MessageContent {
+                            text: transcript.clone(),
+                            ..Default::default()
+                        },
+                        metadata: MessageMetadata {
+                            item_id: Some(item_id.clone()),
+                            ..Default::default()
+                        },
+                    }),
+                };
+
+                self.dispatch_mutation(mutation);
+            }
+            RealtimeEvent::ResponseCompleted => {
+                // Interrupted responses may never get their transcript completed.
+                if let Some(index) = self.writing_realtime_bot_message(bot_id) {
+                    self.realtime_writing.remove(&self.state.messages[index]);
+                    self.dispatch_mutation(VecMutation::update_with(
+                        &self.state.messages,
+                        index,
+                        |m| {
+                            m.metadata.is_writing = false;
+                            m.metadata.item_id = None;
+                        },
+                    ));
+                }
+            }
+            RealtimeEvent::FunctionCallRequest {
+                name,
+                call_id,
+                arguments,
+            } => {
+                // Clients may report the same call more than once (e.g. when the
+                // arguments are done, and again when the whole response is done).
+                if self.find_tool_call(call_id).is_some() {
+                    return;
+                }
+
+                let arguments = parse_tool_arguments(arguments).unwrap_or_else(|error| {
+                    ::log::warn!("Invalid arguments for realtime tool call {name}: {error}");
+                    Default::default()
+                });
+
+                let tool_call = ToolCall {
+                    id: call_id.clone(),
+                    name: name.clone(),
+                    arguments,
+                    ..Default::default()
+                };
+
+                self.dispatch_mutation(VecMutation::Push(Message {
+                    from: EntityId::Bot(bot_id.clone()),
+                    content: MessageContent {
+                        tool_calls: vec![tool_call.clone()],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }));
+
+                #[cfg(feature = "mcp")]
+                self.handle_realtime_tool_call(tool_call);
+            }
+            _ => {}
+        }
+    }
+
+    fn record_realtime_command(&mut self, command: &RealtimeCommand) {
+        if let RealtimeCommand::StopSession = command {
+            self.end_realtime_session();
+            return;
+        }
+
+        if !self.realtime_history_enabled {
+            return;
+        }
+
+        match command {
+            RealtimeCommand::SendText(text) => {
+                self.dispatch_mutation(VecMutation::Push(Message {
+                    from: EntityId::User,
+                    content: MessageContent {
+                        text: text.clone(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }));
+            }
+            RealtimeCommand::SendFunctionCallResult { call_id, output } => {
+                // The app answered this call by itself, so it's not pending anymore.
+                #[cfg(feature = "mcp")]
+                if let Some(session) = &mut self.realtime_session {
+                    session.pending_tool_calls.retain(|id| id != call_id);
+                }
+
+                self.record_realtime_tool_result(
+                    ToolResult {
+                        tool_call_id: call_id.clone(),
+                        content: output.clone(),
+                        is_error: false,
+                    },
+                    ToolCallPermissionStatus::Approved,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Pushes a tool message with the given result, settling the permission status
+    /// of the call it belongs to.
+    fn record_realtime_tool_result(
+        &mut self,
+        tool_result: ToolResult,
+        permission_status: ToolCallPermissionStatus,
+    ) {
+        let mut mutations: Vec<ChatStateMutation> = Vec::new();
+        let mut tool_calls = Vec::new();
+
+        if let Some((index, position)) = self.find_tool_call(&tool_result.tool_call_id) {
+            let tool_call = &self.state.messages[index].content.tool_calls[position];
+            tool_calls.push(tool_call.clone());
+
+            if tool_call.permission_status != permission_status {
+                mutations.push(
+                    VecMutation::update_with(&self.state.messages, index, |m| {
+                        m.content.tool_calls[position].permission_status = permission_status;
+                    })
+                    .into(),
+                );
+            }
+        }
+
+        let tool_results = vec![tool_result];
+        mutations.push(
+            VecMutation::Push(Message {
+                from: EntityId::Tool,
+                content: MessageContent {
+                    text: tool_results_text(&tool_calls, &tool_results),
+                    tool_results,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .into(),
+        );
+
+        self.dispatch_mutations(mutations);
+    }
+
+    /// Decides what to do with a tool call requested during a realtime session.
+    ///
+    /// Without a tool manager, or with automatic execution disabled, calls are
+    /// left for the app to handle.
+    #[cfg(feature = "mcp")]
+    fn handle_realtime_tool_call(&mut self, tool_call: ToolCall) {
+        if !self.realtime_tool_execution_enabled || self.realtime_session.is_none() {
+            return;
+        }
+
+        let Some(tool_manager) = &self.tool_manager else {
+            return;
+        };
+
+        if tool_manager.get_dangerous_mode_enabled() {
+            self.execute_realtime_tool_call(tool_call);
+        } else if let Some(session) = &mut self.realtime_session {
+            // Wait for the user to approve or deny it.
+            session.pending_tool_calls.push(tool_call.id);
+        }
+    }
+
+    /// Reacts to the user approving or denying pending realtime tool calls by
+    /// changing their permission status in the state.
+    #[cfg(feature = "mcp")]
+    pub(super) fn resolve_realtime_tool_calls(&mut self) {
+        let Some(session) = &self.realtime_session else {
+            return;
+        };
+
+        if session.pending_tool_calls.is_empty() {
+            return;
+        }
+
+        let mut approved = Vec::new();
+        let mut denied = Vec::new();
+        for call_id in &session.pending_tool_calls {
+            let Some((index, position)) = self.find_tool_call(call_id) else {
+                continue;
+            };
+
+            let tool_call = &self.state.messages[index].content.tool_calls[position];
+            match tool_call.permission_status {
+                ToolCallPermissionStatus::Approved => approved.push(tool_call.clone()),
+                ToolCallPermissionStatus::Denied => denied.push(tool_call.clone()),
+                ToolCallPermissionStatus::Pending => {}
+            }
+        }
+
+        if approved.is_empty() && denied.is_empty() {
+            return;
+        }
+
+        if let Some(session) = &mut self.realtime_session {
+            session
+                .pending_tool_calls
+                .retain(|id| !approved.iter().chain(denied.iter()).any(|tc| &tc.id == id));
+        }
+
+        for tool_call in approved {
+            self.execute_realtime_tool_call(tool_call);
+        }
+
+        for tool_call in denied {
+            self.deny_realtime_tool_call(tool_call);
+        }
+    }
+
+    /// Takes the tool calls that belong to the current realtime session out of
+    /// an [`ChatTask::Execute`] request and executes them through the session.
+    ///
+    /// Returns the tool calls that must follow the regular execution path.
+    #[cfg(feature = "mcp")]
+    pub(super) fn take_realtime_tool_calls(&mut self, tool_calls: Vec<ToolCall>) -> Vec<ToolCall> {
+        let Some(session) = &mut self.realtime_session else {
+            return tool_calls;
+        };
+
+        let (realtime, regular): (Vec<_>, Vec<_>) = tool_calls
+            .into_iter()
+            .partition(|tc| session.pending_tool_calls.contains(&tc.id));
+
+        session
+            .pending_tool_calls
+            .retain(|id| !realtime.iter().any(|tc| &tc.id == id));
+
+        for tool_call in realtime {
+            self.execute_realtime_tool_call(tool_call);
+        }
+
+        regular
+    }
+
+    #[cfg(feature = "mcp")]
+    fn execute_realtime_tool_call(&mut self, tool_call: ToolCall) {
+        let Some(session) = &self.realtime_session else {
+            return;
+        };
+
+        let command_sender = session.command_sender.clone();
+
+        let (Some(mut spawner), Some(tool_manager)) =
+            (self.spawner.clone(), self.tool_manager.clone())
+        else {
+            return;
+        };
+
+        if let Some((index, position)) = self.find_tool_call(&tool_call.id)
+            && self.state.messages[index].content.tool_calls[position].permission_status
+                != ToolCallPermissionStatus::Approved
+        {
+            self.dispatch_mutation(VecMutation::update_with(&self.state.messages, index, |m| {
+                m.content.tool_calls[position].permission_status =
+                    ToolCallPermissionStatus::Approved;
+            }));
+        }
+
+        let controller = self.accessor.clone();
+        spawner.spawn(async move {
+            let tool_result = tool_manager
+                .execute_tool_call(&tool_call.name, &tool_call.id, tool_call.arguments)
+                .await;
+
+            // Answer the session first, so the bot is not kept waiting on the UI.
+            let _ = command_sender.unbounded_send(RealtimeCommand::SendFunctionCallResult {
+                call_id: tool_result.tool_call_id.clone(),
+                output: tool_result.content.clone(),
+            });
+
+            controller.lock_with(|c| {
+                c.record_realtime_tool_result(tool_result, ToolCallPermissionStatus::Approved)
+            });
+        });
+    }
+
+    #[cfg(feature = "mcp")]
+    fn deny_realtime_tool_call(&mut self, tool_call: ToolCall) {
+        let Some(session) = &self.realtime_session else {
+            return;
+        };
+
+        let _ = session
+            .command_sender
+            .unbounded_send(RealtimeCommand::SendFunctionCallResult {
+                call_id: tool_call.id.clone(),
+                output: DENIED_TOOL_CALL_OUTPUT.to_string(),
+            });
+
+        self.record_realtime_tool_result(
+            ToolResult {
+                tool_call_id: tool_call.id,
+                content: DENIED_TOOL_CALL_OUTPUT.to_string(),
+                is_error: true,
+            },
+            ToolCallPermissionStatus::Denied,
+        );
+    }
+
+    /// Settles messages left incomplete when a realtime session ends.
+    fn finish_realtime_messages(&mut self) {
+        let writing = std::mem::take(&mut self.realtime_writing.item_ids);
+        let is_realtime_leftover = |m: &Message| {
+            m.metadata
+                .item_id
+                .as_ref()
+                .is_some_and(|id| writing.contains(id))
+        };
+
+        let mut mutations: Vec<ChatStateMutation> = Vec::new();
+        for (index, message) in self.state.messages.iter().enumerate() {
+            if is_realtime_leftover(message) && !message.content.is_empty() {
+                mutations.push(
+                    VecMutation::update_with(&self.state.messages, index, |m| {
+                        m.metadata.is_writing = false;
+                        m.metadata.item_id = None;
+                    })
+                    .into(),
+                );
+            }
+        }
+
+        mutations.push(
+            VecMutation::remove_many_with_retain(&self.state.messages, |_, m| {
+                !(is_realtime_leftover(m) && m.content.is_empty())
+            })
+            .into(),
+        );
+
+        self.dispatch_mutations(mutations);
+    }
+
+    /// Index of the bot message currently receiving a realtime transcript, if any.
+    fn writing_realtime_bot_message(&self, bot_id: &BotId) -> Option<usize> {
+        self.state.messages.iter().rposition(|m| {
+            m.from == EntityId::Bot(bot_id.clone()) && self.realtime_writing.contains(m)
+        })
+    }
+
+    /// Finds a tool call by id, returning the message index and its position in it.
+    pub(super) fn find_tool_call(&self, call_id: &str) -> Option<(usize, usize)> {
+        self.state
+            .messages
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, message)| {
+                message
+                    .content
+                    .tool_calls
+                    .iter()
+                    .position(|tc| tc.id == call_id)
+                    .map(|position| (index, position))
+            })
+    }
+}
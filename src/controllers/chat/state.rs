@@ -1,3 +1,4 @@
+use super::SystemInstructions;
 use crate::{protocol::*, utils::vec::*};
 
 /// Represents a generic status in which an operation can be.
@@ -32,6 +33,10 @@ impl Status {
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct ChatState {
     /// The chat history sent as context to LLMs.
+    ///
+    /// This is the active branch of the conversation. Alternative branches are kept
+    /// in [`MessageMetadata::branches`] and can be activated with
+    /// [`ChatStateMutation::switch_branch`].
     pub messages: Vec<Message>,
     /// Indicates that the LLM is still streaming the response ("writing").
     // TODO: Make this the source of truth and remove the message metadata field.
@@ -42,21 +47,50 @@ pub struct ChatState {
     pub load_status: Status,
     /// The currently selected bot for this chat.
     pub bot_id: Option<BotId>,
+    /// Sent before the messages, as a system message.
+    pub system_instructions: SystemInstructions,
+    /// Status of the realtime session started with [`super::ChatTask::StartRealtime`].
+    ///
+    /// `Working` while connecting, `Success` while the session is alive and `Idle`
+    /// once it ends.
+    pub realtime_status: Status,
+    /// Channel of the ongoing realtime session, if any.
+    ///
+    /// Take its event receiver to consume the session events.
+    pub realtime_channel: Option<RealtimeChannel>,
 }
 
 impl ChatState {
+    /// Finds a bot by id.
+    ///
+    /// Ids without a provider path also match bots whose id looks the same once joined,
+    /// as ids of routed bots were plain `key/id` strings before having a provider path.
     pub fn get_bot(&self, bot_id: &BotId) -> Option<&Bot> {
-        self.bots.iter().find(|b| &b.id == bot_id)
+        self.bots.iter().find(|b| &b.id == bot_id).or_else(|| {
+            if bot_id.provider_path().len() > 0 {
+                return None;
+            }
+
+            self.bots
+                .iter()
+                .find(|b| b.id.provider_path().len() > 0 && b.id.as_str() == bot_id.as_str())
+        })
     }
 }
 
+// Message mutations are the most common ones, so boxing them to shrink the others
+// would just add allocations.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum ChatStateMutation {
     SetIsStreaming(bool),
     SetLoadStatus(Status),
     SetBotId(Option<BotId>),
+    SetSystemInstructions(SystemInstructions),
     MutateMessages(VecMutation<Message>),
     MutateBots(VecMutation<Bot>),
+    SetRealtimeStatus(Status),
+    SetRealtimeChannel(Option<RealtimeChannel>),
 }
 
 impl ChatStateMutation {
@@ -71,16 +105,104 @@ impl ChatStateMutation {
             ChatStateMutation::SetBotId(bot_id) => {
                 state.bot_id = bot_id;
             }
+            ChatStateMutation::SetSystemInstructions(instructions) => {
+                state.system_instructions = instructions;
+            }
             ChatStateMutation::MutateMessages(mutation) => {
                 mutation.apply(&mut state.messages);
             }
             ChatStateMutation::MutateBots(mutation) => {
                 mutation.apply(&mut state.bots);
             }
+            ChatStateMutation::SetRealtimeStatus(status) => {
+                state.realtime_status = status;
+            }
+            ChatStateMutation::SetRealtimeChannel(channel) => {
+                state.realtime_channel = channel;
+            }
         }
     }
 }
 
+impl ChatStateMutation {
+    /// Moves the messages from `index` onwards into an inactive branch, and starts a
+    /// new active branch there with the given message, ordered after all the others.
+    ///
+    /// Useful to edit a message, or regenerate an answer, keeping the previous version.
+    pub fn create_branch(
+        messages: &[Message],
+        index: usize,
+        mut message: Message,
+    ) -> Option<ChatStateMutation> {
+        let mut previous = messages.get(index..)?.to_vec();
+        let first = previous.first_mut()?;
+        let mut branches = first
+            .metadata
+            .branches
+            .take()
+            .map(|b| *b)
+            .unwrap_or_default();
+
+        let after = std::mem::take(&mut branches.after);
+        branches.before.push(previous);
+        branches.before.extend(after);
+        message.metadata.branches = Some(Box::new(branches));
+
+        Some(VecMutation::Splice(index, messages.len(), vec![message]).into())
+    }
+
+    /// Activates the branch at position `branch` among the alternatives of the message
+    /// at `index`, replacing the active messages from there onwards.
+    ///
+    /// Gives `None` if there is no such branch, or if it's already active.
+    pub fn switch_branch(
+        messages: &[Message],
+        index: usize,
+        branch: usize,
+    ) -> Option<ChatStateMutation> {
+        let branches = messages.get(index)?.metadata.branches.as_deref()?;
+        if branch >= branches.count() || branch == branches.active_index() {
+            return None;
+        }
+
+        let mut active = messages[index..].to_vec();
+        let MessageBranches { before, after } = *active[0].metadata.branches.take()?;
+
+        let mut all = before;
+        all.push(active);
+        all.extend(after);
+
+        let mut target = all.remove(branch);
+        let after = all.split_off(branch);
+        target.first_mut()?.metadata.branches =
+            Some(Box::new(MessageBranches { before: all, after }));
+
+        Some(VecMutation::Splice(index, messages.len(), target).into())
+    }
+
+    /// Discards the active branch starting at `index`, activating the alternative
+    /// before it (or after it, if it was the first one).
+    ///
+    /// Gives `None` if the message at `index` has no alternatives.
+    pub fn remove_branch(messages: &[Message], index: usize) -> Option<ChatStateMutation> {
+        let mut branches = *messages.get(index)?.metadata.branches.clone()?;
+
+        let mut target = match branches.before.pop() {
+            Some(target) => target,
+            None if !branches.after.is_empty() => branches.after.remove(0),
+            None => return None,
+        };
+
+        if branches.before.is_empty() && branches.after.is_empty() {
+            target.first_mut()?.metadata.branches = None;
+        } else {
+            target.first_mut()?.metadata.branches = Some(Box::new(branches));
+        }
+
+        Some(VecMutation::Splice(index, messages.len(), target).into())
+    }
+}
+
 impl From<VecMutation<Message>> for ChatStateMutation {
     fn from(mutation: VecMutation<Message>) -> Self {
         ChatStateMutation::MutateMessages(mutation)
@@ -92,3 +214,103 @@ impl From<VecMutation<Bot>> for ChatStateMutation {
         ChatStateMutation::MutateBots(mutation)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> Message {
+        Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn texts(state: &ChatState) -> Vec<&str> {
+        state
+            .messages
+            .iter()
+            .map(|m| m.content.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn branches_keep_alternative_paths() {
+        let mut state = ChatState {
+            messages: vec![message("hi"), message("a1"), message("a2")],
+            ..Default::default()
+        };
+
+        ChatStateMutation::create_branch(&state.messages, 1, message("b1"))
+            .unwrap()
+            .apply(&mut state);
+        ChatStateMutation::create_branch(&state.messages, 1, message("c1"))
+            .unwrap()
+            .apply(&mut state);
+        assert_eq!(texts(&state), ["hi", "c1"]);
+        assert_eq!(state.messages[1].branch_position(), (2, 3));
+
+        ChatStateMutation::switch_branch(&state.messages, 1, 0)
+            .unwrap()
+            .apply(&mut state);
+        assert_eq!(texts(&state), ["hi", "a1", "a2"]);
+        assert_eq!(state.messages[1].branch_position(), (0, 3));
+
+        ChatStateMutation::switch_branch(&state.messages, 1, 1)
+            .unwrap()
+            .apply(&mut state);
+        assert_eq!(texts(&state), ["hi", "b1"]);
+        assert_eq!(state.messages[1].branch_position(), (1, 3));
+
+        assert!(ChatStateMutation::switch_branch(&state.messages, 1, 1).is_none());
+        assert!(ChatStateMutation::switch_branch(&state.messages, 1, 3).is_none());
+        assert!(ChatStateMutation::switch_branch(&state.messages, 0, 0).is_none());
+    }
+
+    #[test]
+    fn branches_are_serialized() {
+        let mut state = ChatState {
+            messages: vec![message("a")],
+            ..Default::default()
+        };
+        ChatStateMutation::create_branch(&state.messages, 0, message("b"))
+            .unwrap()
+            .apply(&mut state);
+
+        let json = serde_json::to_string(&state.messages).unwrap();
+        let messages: Vec<Message> = serde_json::from_str(&json).unwrap();
+        let branches = messages[0].metadata.branches.as_ref().unwrap();
+        assert_eq!(branches.before[0][0].content.text, "a");
+    }
+
+    #[test]
+    fn legacy_router_ids_find_their_bot() {
+        let bot = |id: BotId| Bot {
+            id,
+            name: "bot".into(),
+            avatar: EntityAvatar::Text("B".into()),
+            capabilities: BotCapabilities::new(),
+        };
+        let routed = BotId::new("gpt-4o").prefixed("openai");
+        let state = ChatState {
+            bots: vec![bot(BotId::new("openai/gpt-4o-mini")), bot(routed.clone())],
+            ..Default::default()
+        };
+
+        let legacy: BotId = serde_json::from_str(r#""openai/gpt-4o""#).unwrap();
+        assert_eq!(state.get_bot(&legacy).unwrap().id, routed);
+        assert_eq!(
+            state.get_bot(&BotId::new("openai/gpt-4o-mini")).unwrap().id,
+            BotId::new("openai/gpt-4o-mini")
+        );
+        assert!(
+            state
+                .get_bot(&BotId::new("gpt-4o").prefixed("other"))
+                .is_none()
+        );
+    }
+}
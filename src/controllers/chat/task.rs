@@ -12,8 +12,33 @@ pub enum ChatTask {
     Execute(Vec<ToolCall>, Option<BotId>),
     /// Interrupts the streaming started by `Send`.
     Stop,
+    /// Sends again the messages up to the last user message, replacing everything
+    /// after it (answers, tool calls and errors) with a new answer from the currently
+    /// selected bot.
+    ///
+    /// The previous answer is kept as an alternative branch.
+    Regenerate,
+    /// Asks the bot that wrote the last message to keep writing it, like after
+    /// being interrupted or running out of output tokens.
+    ///
+    /// Uses assistant prefill if the bot has [`BotCapability::Prefill`], or asks
+    /// the bot to continue with an extra (not recorded) user message otherwise.
+    Continue,
+    /// Replaces the content of the user message at the given index, and sends the
+    /// conversation up to it again.
+    ///
+    /// The previous version of the message, and the ones after it, are kept as an
+    /// alternative branch.
+    EditAndResend(usize, MessageContent),
     /// Should be triggered to start fetching async data (e.g. bots).
     ///
     /// Eventually, the state will contain the list of bots or errors as messages.
     Load,
+    /// Starts a realtime session with the currently selected bot (from state.bot_id)
+    /// using the configured realtime client.
+    ///
+    /// The session channel will be available at `state.realtime_channel`.
+    StartRealtime,
+    /// Ends the realtime session started by `StartRealtime`.
+    StopRealtime,
 }
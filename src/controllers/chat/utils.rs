@@ -53,3 +53,66 @@ pub(super) fn amortize(
         }
     }
 }
+
+/// Formats a human readable summary of executed tool calls, to be used as the
+/// text of the tool message holding the results.
+pub(super) fn tool_results_text(tool_calls: &[ToolCall], tool_results: &[ToolResult]) -> String {
+    use crate::utils::tool::{create_tool_output_summary, display_name_from_namespaced};
+
+    let tool_name = |result: &ToolResult| {
+        tool_calls
+            .iter()
+            .find(|tc| tc.id == result.tool_call_id)
+            .map(|tc| tc.name.as_str())
+            .unwrap_or("unknown")
+    };
+
+    if tool_results.len() == 1 {
+        let result = &tool_results[0];
+        let tool_name = tool_name(result);
+
+        let display_name = display_name_from_namespaced(tool_name);
+        if result.is_error {
+            format!("🔧 Tool '{}' failed:\n{}", display_name, result.content)
+        } else {
+            let summary = create_tool_output_summary(tool_name, &result.content);
+            format!(
+                "🔧 Tool '{}' executed successfully:\n`{}`",
+                display_name, summary
+            )
+        }
+    } else {
+        let mut text = format!("🔧 Executed {} tools:\n\n", tool_results.len());
+        for result in tool_results {
+            let tool_name = tool_name(result);
+
+            let display_name = display_name_from_namespaced(tool_name);
+            if result.is_error {
+                text.push_str(&format!("**{}** ❌: {}\n\n", display_name, result.content));
+            } else {
+                let summary = create_tool_output_summary(tool_name, &result.content);
+                text.push_str(&format!("**{}** ✅: `{}`\n\n", display_name, summary));
+            }
+        }
+        text
+    }
+}
+
+/// Joins a partial answer with the content generated to continue it.
+pub(super) fn continued_content(
+    prefix: &MessageContent,
+    content: MessageContent,
+) -> MessageContent {
+    fn concat<T: Clone>(prefix: &[T], items: Vec<T>) -> Vec<T> {
+        prefix.iter().cloned().chain(items).collect()
+    }
+
+    MessageContent {
+        text: format!("{}{}", prefix.text, content.text),
+        reasoning: format!("{}{}", prefix.reasoning, content.reasoning),
+        citations: concat(&prefix.citations, content.citations),
+        attachments: concat(&prefix.attachments, content.attachments),
+        tool_calls: concat(&prefix.tool_calls, content.tool_calls),
+        ..content
+    }
+}
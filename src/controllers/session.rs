@@ -0,0 +1,644 @@
+//! Management of many conversations at once, like the ones listed in a sidebar.
+
+use crate::clients::rate_limit::RateLimitedClient;
+use crate::controllers::chat::*;
+#[cfg(feature = "mcp")]
+use crate::mcp::mcp_manager::McpManagerClient;
+use crate::protocol::*;
+use crate::store::*;
+use crate::utils::asynchronous::{BasicSpawner, ErasedSpawner};
+use crate::utils::vec::VecMutation;
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Snapshot of a conversation owned by a [`SessionManager`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub metadata: ConversationMetadata,
+    /// If the conversation has a live [`ChatController`], instead of being only
+    /// in the store.
+    pub is_loaded: bool,
+}
+
+struct Entry {
+    /// Shared with the autosave plugin of the controller, which keeps it updated.
+    metadata: Arc<Mutex<ConversationMetadata>>,
+    controller: Option<Arc<Mutex<ChatController>>>,
+    /// Stops the autosave of the controller, once unloaded or deleted.
+    autosave: Option<AutosaveCancel>,
+    /// Conversation being saved while unloading, to reopen it without waiting.
+    unloading: Option<Conversation>,
+    last_used: DateTime<Utc>,
+}
+
+impl Entry {
+    fn id(&self) -> String {
+        self.metadata.lock().unwrap().id.clone()
+    }
+}
+
+/// Owns many conversations, each one with its own [`ChatController`], and shares
+/// a single client, tool manager and spawner among them.
+///
+/// Conversations are saved to the configured [`ConversationStore`] as they change
+/// (see [`AutosavePlugin`]), and the ones not used for a while can be unloaded,
+/// dropping their controllers until they are opened again.
+///
+/// Operations that may need the store are async, and take the manager behind its
+/// mutex, which is never locked while waiting.
+pub struct SessionManager {
+    entries: Vec<Entry>,
+    client: Option<RateLimitedClient<Box<dyn BotClient>>>,
+    max_concurrent_streams: Option<usize>,
+    #[cfg(feature = "mcp")]
+    tool_manager: Option<McpManagerClient>,
+    spawner: Box<dyn ErasedSpawner>,
+    store: Arc<dyn ConversationStore>,
+    max_loaded: Option<usize>,
+    autosave_delay: Duration,
+    title_bot_id: Option<BotId>,
+    /// Used to make ids unique when created in the same millisecond.
+    next_id: u64,
+}
+
+impl SessionManager {
+    /// Creates a new reference-counted `SessionManager`, without conversations.
+    ///
+    /// Conversations are kept in memory until a store is configured with
+    /// [`Self::set_store`].
+    pub fn new_arc() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            entries: Vec::new(),
+            client: None,
+            max_concurrent_streams: None,
+            #[cfg(feature = "mcp")]
+            tool_manager: None,
+            spawner: Box::new(BasicSpawner),
+            store: Arc::new(MemoryConversationStore::new()),
+            max_loaded: None,
+            autosave_delay: Duration::from_secs(1),
+            title_bot_id: None,
+            next_id: 0,
+        }))
+    }
+
+    /// Changes the client shared by all the conversations, and reloads their bots.
+    pub fn set_client(&mut self, client: Option<Box<dyn BotClient>>) {
+        self.client = client.map(|client| {
+            let mut client = RateLimitedClient::new(client);
+            client.set_learn_limits(false);
+            client.set_max_concurrent_streams(self.max_concurrent_streams);
+            client
+        });
+
+        for controller in self.entries.iter().filter_map(|e| e.controller.as_ref()) {
+            Self::apply_client(&mut controller.lock().unwrap(), &self.client);
+        }
+    }
+
+    fn apply_client(
+        controller: &mut ChatController,
+        client: &Option<RateLimitedClient<Box<dyn BotClient>>>,
+    ) {
+        controller.set_client(client.clone().map(|c| Box::new(c) as Box<dyn BotClient>));
+        if client.is_some() {
+            controller.dispatch_task(ChatTask::Load);
+        }
+    }
+
+    /// Limits how many answers can be streamed at the same time, among all the
+    /// conversations. Sends over the limit wait for their turn.
+    pub fn set_max_concurrent_streams(&mut self, max: Option<usize>) {
+        self.max_concurrent_streams = max;
+        if let Some(client) = &mut self.client {
+            client.set_max_concurrent_streams(max);
+        }
+    }
+
+    pub fn max_concurrent_streams(&self) -> Option<usize> {
+        self.max_concurrent_streams
+    }
+
+    /// Changes the tool manager shared by all the conversations.
+    #[cfg(feature = "mcp")]
+    pub fn set_tool_manager(&mut self, tool_manager: Option<McpManagerClient>) {
+        self.tool_manager = tool_manager;
+        for controller in self.entries.iter().filter_map(|e| e.controller.as_ref()) {
+            controller
+                .lock()
+                .unwrap()
+                .set_tool_manager(self.tool_manager.clone());
+        }
+    }
+
+    #[cfg(feature = "mcp")]
+    pub fn tool_manager(&self) -> Option<&McpManagerClient> {
+        self.tool_manager.as_ref()
+    }
+
+    /// Changes the spawner shared by all the conversations. Defaults to [`BasicSpawner`].
+    pub fn set_spawner<S>(&mut self, spawner: S)
+    where
+        S: ErasedSpawner + 'static,
+    {
+        self.spawner = Box::new(spawner);
+        for controller in self.entries.iter().filter_map(|e| e.controller.as_ref()) {
+            controller
+                .lock()
+                .unwrap()
+                .set_spawner(Some(self.spawner.clone()));
+        }
+    }
+
+    /// Changes where conversations are saved. Defaults to a [`MemoryConversationStore`].
+    ///
+    /// Loaded conversations keep saving to the previous store, so set this before
+    /// creating or restoring any.
+    pub fn set_store(&mut self, store: impl ConversationStore) {
+        self.store = Arc::new(store);
+    }
+
+    pub fn store(&self) -> &Arc<dyn ConversationStore> {
+        &self.store
+    }
+
+    /// Limits how many conversations can be loaded at the same time. When creating
+    /// or opening one over the limit, the least recently used ones are unloaded.
+    ///
+    /// Conversations that are streaming, or whose controller is still referenced
+    /// outside of the manager, are never unloaded.
+    pub fn set_max_loaded(&mut self, max: Option<usize>) {
+        self.max_loaded = max;
+    }
+
+    pub fn max_loaded(&self) -> Option<usize> {
+        self.max_loaded
+    }
+
+    /// Sets how long to wait for more changes before saving a conversation. Defaults
+    /// to 1 second. Only affects conversations loaded afterwards.
+    pub fn set_autosave_delay(&mut self, delay: Duration) {
+        self.autosave_delay = delay;
+    }
+
+    /// Gives titles to untitled conversations after their first answer, asking the
+    /// given bot through the shared client (see [`TitlePlugin`]).
+    ///
+    /// Only affects conversations loaded afterwards, which keep the client they
+    /// were loaded with for this.
+    pub fn set_title_bot_id(&mut self, bot_id: Option<BotId>) {
+        self.title_bot_id = bot_id;
+    }
+
+    pub fn title_bot_id(&self) -> Option<&BotId> {
+        self.title_bot_id.as_ref()
+    }
+
+    /// All the conversations, most recently updated first.
+    pub fn sessions(&self) -> Vec<Session> {
+        let mut sessions: Vec<_> = self.entries.iter().map(Self::session_of).collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.metadata.updated_at));
+        sessions
+    }
+
+    pub fn session(&self, id: &str) -> Option<Session> {
+        self.position(id)
+            .map(|i| Self::session_of(&self.entries[i]))
+    }
+
+    fn session_of(entry: &Entry) -> Session {
+        Session {
+            metadata: entry.metadata.lock().unwrap().clone(),
+            is_loaded: entry.controller.is_some(),
+        }
+    }
+
+    fn position(&self, id: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| e.metadata.lock().unwrap().id == id)
+    }
+
+    /// Starts a new empty conversation, giving its id and its controller.
+    ///
+    /// It's saved once it has messages. Other conversations may be unloaded to stay
+    /// under [`Self::max_loaded`].
+    pub async fn create(manager: &Arc<Mutex<Self>>) -> (String, Arc<Mutex<ChatController>>) {
+        let (id, controller) = {
+            let mut m = manager.lock().unwrap();
+            let id = format!("{}-{}", Utc::now().format("%Y%m%d%H%M%S%3f"), m.next_id);
+            m.next_id += 1;
+            let controller = m.insert_loaded(Conversation::new(id.clone()));
+            (id, controller)
+        };
+
+        Self::unload_excess(manager).await;
+        (id, controller)
+    }
+
+    /// Adds the given conversation with a new controller, or gives the existing
+    /// controller if it was loaded meanwhile.
+    fn insert_loaded(&mut self, conversation: Conversation) -> Arc<Mutex<ChatController>> {
+        let index = match self.position(&conversation.metadata.id) {
+            Some(index) => index,
+            None => {
+                self.entries.push(Entry {
+                    metadata: Arc::new(Mutex::new(conversation.metadata.clone())),
+                    controller: None,
+                    autosave: None,
+                    unloading: None,
+                    last_used: Utc::now(),
+                });
+                self.entries.len() - 1
+            }
+        };
+
+        let entry = &self.entries[index];
+        if let Some(controller) = &entry.controller {
+            let controller = controller.clone();
+            self.entries[index].last_used = Utc::now();
+            return controller;
+        }
+
+        let (controller, autosave) = self.new_controller(&entry.metadata, conversation.messages);
+
+        let entry = &mut self.entries[index];
+        entry.controller = Some(controller.clone());
+        entry.autosave = Some(autosave);
+        entry.unloading = None;
+        entry.last_used = Utc::now();
+        controller
+    }
+
+    fn new_controller(
+        &self,
+        metadata: &Arc<Mutex<ConversationMetadata>>,
+        messages: Vec<Message>,
+    ) -> (Arc<Mutex<ChatController>>, AutosaveCancel) {
+        let (bot_id, instructions) = {
+            let metadata = metadata.lock().unwrap();
+            (
+                metadata.bot_id.clone(),
+                metadata.system_instructions.clone(),
+            )
+        };
+
+        let controller = ChatController::new_arc();
+        let mut c = controller.lock().unwrap();
+        c.set_spawner(Some(self.spawner.clone()));
+        #[cfg(feature = "mcp")]
+        c.set_tool_manager(self.tool_manager.clone());
+
+        // Restored before autosaving starts, as it's already saved.
+        c.dispatch_mutation(ChatStateMutation::SetBotId(bot_id));
+        c.dispatch_mutation(ChatStateMutation::SetSystemInstructions(instructions));
+        c.dispatch_mutation(VecMutation::Set(messages));
+
+        let mut autosave = AutosavePlugin::new_shared(self.store.clone(), metadata.clone());
+        autosave.set_spawner(self.spawner.clone());
+        autosave.set_delay(self.autosave_delay);
+        let autosave_cancel = autosave.cancel_handle();
+        c.append_plugin(autosave);
+
+        if let (Some(client), Some(bot_id)) = (&self.client, &self.title_bot_id) {
+            let mut title =
+                TitlePlugin::new(Box::new(client.clone()), bot_id.clone(), metadata.clone());
+            title.set_store(Some(self.store.clone()));
+            title.set_spawner(self.spawner.clone());
+            c.append_plugin(title);
+        }
+
+        Self::apply_client(&mut c, &self.client);
+        drop(c);
+
+        (controller, autosave_cancel)
+    }
+
+    /// Takes the controller out of the conversation at `index`, if it can be
+    /// unloaded, giving the conversation to save.
+    fn take_unloadable(&mut self, index: usize) -> Option<Conversation> {
+        let entry = &mut self.entries[index];
+        let controller = entry.controller.as_ref()?;
+        if Arc::strong_count(controller) > 1 {
+            return None;
+        }
+
+        let messages = {
+            let c = controller.lock().unwrap();
+            if c.state().is_streaming {
+                return None;
+            }
+            c.state().messages.clone()
+        };
+
+        let conversation = Conversation {
+            metadata: entry.metadata.lock().unwrap().clone(),
+            messages,
+        };
+
+        // Saved below instead, with the latest messages.
+        if let Some(autosave) = entry.autosave.take() {
+            autosave.cancel();
+        }
+
+        entry.controller = None;
+        entry.unloading = Some(conversation.clone());
+        Some(conversation)
+    }
+
+    /// Adds the conversations in the store that are not known yet, unloaded.
+    pub async fn restore(manager: &Arc<Mutex<Self>>) -> std::io::Result<()> {
+        let store = manager.lock().unwrap().store.clone();
+        let list = store.list().await?;
+
+        let mut manager = manager.lock().unwrap();
+        for metadata in list {
+            if manager.position(&metadata.id).is_none() {
+                manager.entries.push(Entry {
+                    metadata: Arc::new(Mutex::new(metadata)),
+                    controller: None,
+                    autosave: None,
+                    unloading: None,
+                    last_used: DateTime::UNIX_EPOCH,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gives the controller of a conversation, loading it from the store if needed.
+    ///
+    /// Conversations in the store are loaded even if they weren't restored.
+    pub async fn open(
+        manager: &Arc<Mutex<Self>>,
+        id: &str,
+    ) -> std::io::Result<Arc<Mutex<ChatController>>> {
+        let store = {
+            let mut m = manager.lock().unwrap();
+            let index = m.position(id);
+            let entry = index.map(|i| &mut m.entries[i]);
+
+            match entry {
+                Some(Entry {
+                    controller: Some(controller),
+                    last_used,
+                    ..
+                }) => {
+                    *last_used = Utc::now();
+                    return Ok(controller.clone());
+                }
+                Some(Entry {
+                    unloading: Some(conversation),
+                    ..
+                }) => {
+                    let conversation = conversation.clone();
+                    return Ok(m.insert_loaded(conversation));
+                }
+                _ => m.store.clone(),
+            }
+        };
+
+        let conversation = store.load(id).await?;
+        let controller = manager.lock().unwrap().insert_loaded(conversation);
+        Self::unload_excess(manager).await;
+        Ok(controller)
+    }
+
+    /// Unloads the least recently used conversations over [`Self::max_loaded`].
+    async fn unload_excess(manager: &Arc<Mutex<Self>>) {
+        let ids: Vec<String> = {
+            let m = manager.lock().unwrap();
+            let Some(max) = m.max_loaded else {
+                return;
+            };
+
+            let mut loaded: Vec<_> = m
+                .entries
+                .iter()
+                .filter(|e| e.controller.is_some())
+                .collect();
+            loaded.sort_by_key(|e| std::cmp::Reverse(e.last_used));
+            loaded.iter().skip(max).map(|e| e.id()).collect()
+        };
+
+        for id in ids {
+            if let Err(error) = Self::unload(manager, &id).await {
+                log::warn!("Failed to unload conversation '{id}': {error}");
+            }
+        }
+    }
+
+    /// Unloads the conversations not opened for the given time.
+    pub async fn unload_idle(
+        manager: &Arc<Mutex<Self>>,
+        idle_for: Duration,
+    ) -> std::io::Result<()> {
+        let ids: Vec<String> = {
+            let m = manager.lock().unwrap();
+            let now = Utc::now();
+            m.entries
+                .iter()
+                .filter(|e| e.controller.is_some())
+                .filter(|e| (now - e.last_used).to_std().unwrap_or_default() >= idle_for)
+                .map(Entry::id)
+                .collect()
+        };
+
+        for id in ids {
+            Self::unload(manager, &id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves a conversation and drops its controller, unless it's streaming or
+    /// referenced outside of the manager.
+    pub async fn unload(manager: &Arc<Mutex<Self>>, id: &str) -> std::io::Result<()> {
+        let (store, conversation) = {
+            let mut m = manager.lock().unwrap();
+            let Some(index) = m.position(id) else {
+                return Ok(());
+            };
+            let Some(conversation) = m.take_unloadable(index) else {
+                return Ok(());
+            };
+            (m.store.clone(), conversation)
+        };
+
+        // Never changed, so there is nothing to save, and it stays in memory to
+        // be opened again.
+        let metadata = &conversation.metadata;
+        if conversation.messages.is_empty() && metadata.updated_at == metadata.created_at {
+            return Ok(());
+        }
+
+        let result = store.save(&conversation).await;
+
+        let mut m = manager.lock().unwrap();
+        if let Some(index) = m.position(id) {
+            let entry = &mut m.entries[index];
+            if result.is_ok() && entry.unloading.as_ref() == Some(&conversation) {
+                entry.unloading = None;
+            }
+        }
+
+        result
+    }
+
+    /// Removes a conversation from the manager and from the store.
+    pub async fn delete(manager: &Arc<Mutex<Self>>, id: &str) -> std::io::Result<()> {
+        let store = {
+            let mut m = manager.lock().unwrap();
+            if let Some(index) = m.position(id) {
+                let entry = m.entries.remove(index);
+                if let Some(autosave) = entry.autosave {
+                    autosave.cancel();
+                }
+            }
+            m.store.clone()
+        };
+
+        match store.delete(id).await {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Changes the title of a conversation, in the manager and in the store.
+    pub async fn rename(
+        manager: &Arc<Mutex<Self>>,
+        id: &str,
+        title: Option<String>,
+    ) -> std::io::Result<()> {
+        let store = {
+            let m = manager.lock().unwrap();
+            if let Some(index) = m.position(id) {
+                m.entries[index].metadata.lock().unwrap().title = title.clone();
+            }
+            m.store.clone()
+        };
+
+        // Not saved yet, but the title will be included when it is.
+        match store.rename(id, title).await {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn push_message(controller: &Arc<Mutex<ChatController>>, text: &str) {
+        controller
+            .lock()
+            .unwrap()
+            .dispatch_mutation(VecMutation::Push(Message {
+                from: EntityId::User,
+                content: MessageContent {
+                    text: text.into(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }));
+    }
+
+    #[test]
+    fn least_recently_used_sessions_are_unloaded() {
+        let store = MemoryConversationStore::new();
+        let manager = SessionManager::new_arc();
+        {
+            let mut m = manager.lock().unwrap();
+            m.set_store(store.clone());
+            m.set_max_loaded(Some(1));
+        }
+
+        let (first, controller) = block_on(SessionManager::create(&manager));
+        push_message(&controller, "first");
+        drop(controller);
+
+        let (second, controller) = block_on(SessionManager::create(&manager));
+        push_message(&controller, "second");
+        assert!(!manager.lock().unwrap().session(&first).unwrap().is_loaded);
+
+        // Held outside of the manager, so it stays loaded.
+        block_on(SessionManager::open(&manager, &first)).unwrap();
+        assert!(manager.lock().unwrap().session(&second).unwrap().is_loaded);
+        drop(controller);
+
+        block_on(SessionManager::unload_idle(&manager, Duration::ZERO)).unwrap();
+        let m = manager.lock().unwrap();
+        assert!(m.sessions().iter().all(|s| !s.is_loaded));
+        assert_eq!(store.len(), 2);
+        drop(m);
+
+        let controller = block_on(SessionManager::open(&manager, &second)).unwrap();
+        assert_eq!(
+            controller.lock().unwrap().state().messages[0].content.text,
+            "second"
+        );
+    }
+
+    #[test]
+    fn empty_sessions_are_not_saved_and_deleted_ones_stay_deleted() {
+        let store = MemoryConversationStore::new();
+        let manager = SessionManager::new_arc();
+        {
+            let mut m = manager.lock().unwrap();
+            m.set_store(store.clone());
+            m.set_autosave_delay(Duration::from_millis(50));
+        }
+
+        let (empty, controller) = block_on(SessionManager::create(&manager));
+        drop(controller);
+        block_on(SessionManager::unload(&manager, &empty)).unwrap();
+        assert!(store.is_empty());
+        assert!(block_on(SessionManager::open(&manager, &empty)).is_ok());
+
+        let (id, controller) = block_on(SessionManager::create(&manager));
+        push_message(&controller, "soon deleted");
+        block_on(SessionManager::delete(&manager, &id)).unwrap();
+
+        // The save pending when deleting is dropped.
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn sessions_are_restored_renamed_and_deleted() {
+        let store = MemoryConversationStore::new();
+        let mut conversation = Conversation::new("stored");
+        conversation.metadata.bot_id = Some(BotId::new("bot"));
+        block_on(store.save(&conversation)).unwrap();
+
+        let manager = SessionManager::new_arc();
+        manager.lock().unwrap().set_store(store.clone());
+        block_on(SessionManager::restore(&manager)).unwrap();
+
+        let sessions = manager.lock().unwrap().sessions();
+        assert_eq!(sessions.len(), 1);
+        assert!(!sessions[0].is_loaded);
+
+        block_on(SessionManager::rename(
+            &manager,
+            "stored",
+            Some("Title".into()),
+        ))
+        .unwrap();
+        let controller = block_on(SessionManager::open(&manager, "stored")).unwrap();
+        assert_eq!(
+            controller.lock().unwrap().state().bot_id,
+            Some(BotId::new("bot"))
+        );
+        let session = manager.lock().unwrap().session("stored").unwrap();
+        assert_eq!(session.metadata.title.as_deref(), Some("Title"));
+
+        block_on(SessionManager::delete(&manager, "stored")).unwrap();
+        assert!(manager.lock().unwrap().sessions().is_empty());
+        assert!(store.is_empty());
+    }
+}
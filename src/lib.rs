@@ -3,6 +3,9 @@ pub mod controllers;
 #[cfg(feature = "mcp")]
 pub mod mcp;
 pub mod protocol;
+pub mod store;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod utils;
 
 pub mod prelude;
@@ -23,6 +23,9 @@ pub use crate::clients::{map::MapClient, tester::TesterClient};
 #[cfg(feature = "mcp")]
 pub use crate::mcp::mcp_manager::{McpManagerClient, McpTransport};
 
+// Persistence is needed by most apps, sooner or later.
+pub use crate::store::{Conversation, ConversationMetadata, ConversationStore};
+
 // Only used by users that want the built-in chat business logic. But this is expected.
 pub use crate::controllers::chat::*;
 
@@ -1,5 +1,6 @@
 use super::*;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{error::Error, fmt};
 
 /// The standard error kinds a client implementatiin should facilitate.
@@ -44,6 +45,8 @@ pub struct ClientError {
     message: String,
     source: Option<Arc<dyn Error + Send + Sync + 'static>>,
     details: Option<String>,
+    status_code: Option<u16>,
+    retry_after: Option<Duration>,
 }
 
 impl fmt::Display for ClientError {
@@ -81,6 +84,8 @@ impl ClientError {
             message,
             source: None,
             details: None,
+            status_code: None,
+            retry_after: None,
         }
     }
 
@@ -94,6 +99,8 @@ impl ClientError {
             message,
             source: source.map(|s| Arc::new(s) as _),
             details: None,
+            status_code: None,
+            retry_after: None,
         }
     }
 
@@ -103,6 +110,18 @@ impl ClientError {
         self
     }
 
+    /// Attach the status code of the response that caused this error (e.g. HTTP 429).
+    pub fn with_status_code(mut self, status_code: u16) -> Self {
+        self.status_code = Some(status_code);
+        self
+    }
+
+    /// Attach how long the remote server asked to wait before trying again.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+
     /// Error kind accessor.
     pub fn kind(&self) -> ClientErrorKind {
         self.kind
@@ -118,6 +137,16 @@ impl ClientError {
         self.details.as_deref()
     }
 
+    /// Status code of the response that caused this error, if any.
+    pub fn status_code(&self) -> Option<u16> {
+        self.status_code
+    }
+
+    /// How long the remote server asked to wait before trying again, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
     /// Take the raw technical details out, leaving `None` in place.
     pub fn take_details(&mut self) -> Option<String> {
         self.details.take()
@@ -308,6 +337,33 @@ impl<T> From<Result<T, ClientError>> for ClientResult<T> {
     }
 }
 
+/// Rate limits of a provider, as last reported by it (e.g. with `x-ratelimit-*`
+/// headers).
+///
+/// Every field is optional because providers report different subsets of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimits {
+    /// Requests allowed per window.
+    pub limit_requests: Option<u64>,
+    /// Tokens allowed per window.
+    pub limit_tokens: Option<u64>,
+    /// Requests left in the current window.
+    pub remaining_requests: Option<u64>,
+    /// Tokens left in the current window.
+    pub remaining_tokens: Option<u64>,
+    /// Time until the requests limit is fully restored.
+    pub reset_requests: Option<Duration>,
+    /// Time until the tokens limit is fully restored.
+    pub reset_tokens: Option<Duration>,
+}
+
+impl RateLimits {
+    /// If nothing was reported.
+    pub fn is_empty(&self) -> bool {
+        *self == RateLimits::default()
+    }
+}
+
 /// A standard interface to fetch bots information and send messages to them.
 ///
 /// Keep this [`Clone`] and [`Sync`] as it may be required by the async context.
@@ -331,6 +387,12 @@ pub trait BotClient: Send {
 
     /// Make a boxed dynamic clone of this client to pass around.
     fn clone_box(&self) -> Box<dyn BotClient>;
+
+    /// Rate limits reported by the provider in its last response, if this client
+    /// exposes them.
+    fn rate_limits(&self) -> Option<RateLimits> {
+        None
+    }
 }
 
 impl Clone for Box<dyn BotClient> {
@@ -338,3 +400,28 @@ impl Clone for Box<dyn BotClient> {
         self.clone_box()
     }
 }
+
+/// Allows type-erased clients to be wrapped by generic ones, like
+/// [`crate::clients::rate_limit::RateLimitedClient`].
+impl BotClient for Box<dyn BotClient> {
+    fn send(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        self.as_mut().send(bot_id, messages, tools)
+    }
+
+    fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        self.as_mut().bots()
+    }
+
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        self.as_ref().clone_box()
+    }
+
+    fn rate_limits(&self) -> Option<RateLimits> {
+        self.as_ref().rate_limits()
+    }
+}
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 use std::collections::HashSet;
 use std::fmt;
+use std::sync::Arc;
 
 /// The picture/avatar of an entity that may be represented/encoded in different ways.
 // TODO: Consider Arc<str> where applicable.
@@ -62,6 +63,11 @@ pub enum BotCapability {
     ToolInput,
     /// Bot supports starting a realtime audio call for conversation.
     AudioCall,
+    /// Bot continues a trailing message of its own, instead of answering it
+    /// (a.k.a. assistant prefill).
+    ///
+    /// Few providers support this, so no built-in client reports it.
+    Prefill,
 }
 
 /// Set of capabilities that a bot supports
@@ -148,11 +154,71 @@ pub struct Bot {
     pub capabilities: BotCapabilities,
 }
 
+impl Bot {
+    /// If this bot can hold realtime sessions through a [`super::RealtimeClient`].
+    pub fn supports_realtime(&self) -> bool {
+        self.capabilities.has_capability(&BotCapability::AudioCall)
+    }
+}
+
 /// Identifies any kind of bot, local or remote, model or agent, whatever.
 ///
-/// Normally, this is just the model name or id as known by the provider.
-#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, Serialize)]
-pub struct BotId(SmolStr);
+/// Normally, this is just the model name or id as known by the provider. When the bot is
+/// reached through intermediaries (like a [`crate::clients::router::RouterClient`]), the id
+/// also holds the path of keys used by them, outermost first, so model ids containing `/`
+/// (like OpenRouter's `openai/gpt-4o`) are never confused with those keys.
+///
+/// Ids without a provider path are serialized as plain strings, like they have always been.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BotId(BotIdInner);
+
+/// Ids with a provider path are behind a pointer to keep ids (and messages) small, as
+/// most don't have one.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum BotIdInner {
+    Plain(SmolStr),
+    Routed(Arc<RoutedBotId>),
+}
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct RoutedBotId {
+    provider_path: Vec<SmolStr>,
+    id: SmolStr,
+    /// Display form of the whole id, kept around so it can be borrowed.
+    joined: SmolStr,
+}
+
+impl Default for BotId {
+    fn default() -> Self {
+        BotId(BotIdInner::Plain(SmolStr::default()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum BotIdRepr {
+    Plain(SmolStr),
+    Structured {
+        provider_path: Vec<SmolStr>,
+        id: SmolStr,
+    },
+}
+
+impl Serialize for BotId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match &self.0 {
+            BotIdInner::Plain(id) => id.serialize(serializer),
+            BotIdInner::Routed(routed) => BotIdRepr::Structured {
+                provider_path: routed.provider_path.clone(),
+                id: routed.id.clone(),
+            }
+            .serialize(serializer),
+        }
+    }
+}
 
 impl<'de> Deserialize<'de> for BotId {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -170,45 +236,132 @@ impl<'de> Deserialize<'de> for BotId {
         fn v1(raw: &SmolStr) -> Option<SmolStr> {
             let (id_length, raw) = raw.split_once(';')?;
             let id_length = id_length.parse::<usize>().ok()?;
-            let id = &raw[..id_length];
+            let id = raw.get(..id_length)?;
             // + 1 skips the semantic `@` separator
-            let _provider = &raw[id_length + 1..];
+            let _provider = raw.get(id_length + 1..)?;
             Some(id.into())
         }
 
-        // Read the raw payload.
-        let raw = SmolStr::deserialize(deserializer)?;
-
-        // Try to parse as v1 first.
-        if let Some(s) = v1(&raw) {
-            return Ok(BotId(s));
+        match BotIdRepr::deserialize(deserializer)? {
+            BotIdRepr::Structured { provider_path, id } => Ok(BotId::from_parts(provider_path, id)),
+            // Try to parse as v1 first, otherwise it's a plain id.
+            BotIdRepr::Plain(raw) => Ok(BotId::new(v1(&raw).unwrap_or(raw))),
         }
-
-        // Raw should be the current representation.
-        Ok(BotId(raw))
     }
 }
 
 impl BotId {
+    /// The display form of this id, with the keys of the provider path and the id
+    /// joined by `/`.
+    ///
+    /// Use it for logging or display. Different ids may look the same in this form.
     pub fn as_str(&self) -> &str {
-        self.0.as_str()
+        match &self.0 {
+            BotIdInner::Plain(id) => id.as_str(),
+            BotIdInner::Routed(routed) => routed.joined.as_str(),
+        }
     }
 
     /// Creates a new bot id from a provider specific id.
     pub fn new(id: impl AsRef<str>) -> Self {
-        BotId(id.as_ref().into())
+        BotId::from_parts(std::iter::empty::<&str>(), id)
+    }
+
+    /// Creates a bot id reached through the given provider keys, outermost first.
+    pub fn from_parts(
+        provider_path: impl IntoIterator<Item = impl AsRef<str>>,
+        id: impl AsRef<str>,
+    ) -> Self {
+        let provider_path: Vec<SmolStr> = provider_path
+            .into_iter()
+            .map(|key| key.as_ref().into())
+            .collect();
+        let id: SmolStr = id.as_ref().into();
+
+        if provider_path.is_empty() {
+            return BotId(BotIdInner::Plain(id));
+        }
+
+        let joined = format!("{}/{}", provider_path.join("/"), id).into();
+        BotId(BotIdInner::Routed(Arc::new(RoutedBotId {
+            provider_path,
+            id,
+            joined,
+        })))
     }
 
     /// The id of the bot as it is known by its provider. The "model name".
-    ///
-    /// This should be equivalent to [`BotId::as_str`].
     pub fn id(&self) -> &str {
-        self.as_str()
+        match &self.0 {
+            BotIdInner::Plain(id) => id.as_str(),
+            BotIdInner::Routed(routed) => routed.id.as_str(),
+        }
+    }
+
+    /// The keys of the providers this bot is reached through, outermost first.
+    pub fn provider_path(&self) -> impl DoubleEndedIterator<Item = &str> + ExactSizeIterator {
+        let keys = match &self.0 {
+            BotIdInner::Plain(_) => &[][..],
+            BotIdInner::Routed(routed) => &routed.provider_path[..],
+        };
+        keys.iter().map(|key| key.as_str())
+    }
+
+    /// Returns this id reached through one more provider, with the given key.
+    pub fn prefixed(&self, key: impl AsRef<str>) -> BotId {
+        let path = std::iter::once(key.as_ref()).chain(self.provider_path());
+        BotId::from_parts(path, self.id())
+    }
+
+    /// Splits the outermost provider key from this id, if any.
+    pub fn unprefixed(&self) -> Option<(&str, BotId)> {
+        let BotIdInner::Routed(routed) = &self.0 else {
+            return None;
+        };
+        let (key, rest) = routed.provider_path.split_first()?;
+        Some((key.as_str(), BotId::from_parts(rest, &routed.id)))
     }
 }
 
 impl fmt::Display for BotId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bot_id_serde_is_backward_compatible() {
+        let plain: BotId = serde_json::from_str(r#""openai/gpt-4o""#).unwrap();
+        assert_eq!(plain, BotId::new("openai/gpt-4o"));
+        assert_eq!(serde_json::to_string(&plain).unwrap(), r#""openai/gpt-4o""#);
+
+        let v1: BotId = serde_json::from_str(r#""9;qwen:0.5b@http://localhost:11434/v1""#).unwrap();
+        assert_eq!(v1, BotId::new("qwen:0.5b"));
+
+        let structured = BotId::new("openai/gpt-4o").prefixed("openrouter");
+        let json = serde_json::to_string(&structured).unwrap();
+        assert_eq!(
+            json,
+            r#"{"provider_path":["openrouter"],"id":"openai/gpt-4o"}"#
+        );
+        assert_eq!(serde_json::from_str::<BotId>(&json).unwrap(), structured);
+    }
+
+    #[test]
+    fn bot_id_provider_path() {
+        let id = BotId::new("a/b").prefixed("inner").prefixed("outer");
+        assert_eq!(id.as_str(), "outer/inner/a/b");
+        assert_eq!(id.id(), "a/b");
+        assert_eq!(id.provider_path().collect::<Vec<_>>(), ["outer", "inner"]);
+        assert_ne!(id, BotId::new("outer/inner/a/b"));
+
+        let (key, rest) = id.unprefixed().unwrap();
+        assert_eq!(key, "outer");
+        assert_eq!(rest, BotId::new("a/b").prefixed("inner"));
+        assert_eq!(BotId::new("a/b").unprefixed(), None);
     }
 }
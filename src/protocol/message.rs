@@ -51,6 +51,13 @@ pub struct MessageContent {
     /// Optional upgrade to realtime communication
     #[serde(skip)]
     pub upgrade: Option<Upgrade>,
+
+    /// Bot that actually produced this content, when a client answers on behalf
+    /// of another one (e.g. [`crate::clients::fallback::FallbackClient`]).
+    ///
+    /// The chat controller moves this into [`MessageMetadata::answered_by`].
+    #[serde(skip)]
+    pub answered_by: Option<BotId>,
 }
 
 impl MessageContent {
@@ -99,6 +106,36 @@ pub struct MessageMetadata {
     /// by [`MessageMetadata::default`], it defaults to "now".
     #[serde(default)]
     pub text_updated_at: DateTime<Utc>,
+
+    /// Provider specific id of the item this message maps to, if any.
+    ///
+    /// For example, the id of the conversation item in a realtime session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_id: Option<String>,
+
+    /// Bot that actually answered, if different from the one this message is from.
+    ///
+    /// For example, the provider that served the message through a fallback client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answered_by: Option<BotId>,
+
+    /// Alternative versions of this message, each one continuing the conversation in
+    /// its own way (e.g. regenerated answers).
+    ///
+    /// This message and the ones after it are the active branch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branches: Option<Box<MessageBranches>>,
+
+    /// If this message must always be sent as context, even when older messages
+    /// are dropped to fit the context window.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+
+    /// If this message must never be sent as context, only shown.
+    ///
+    /// For example, notes for the user that would confuse the model.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden_from_model: bool,
 }
 
 impl Default for MessageMetadata {
@@ -110,6 +147,11 @@ impl Default for MessageMetadata {
             created_at: now,
             reasoning_updated_at: now,
             text_updated_at: now,
+            item_id: None,
+            answered_by: None,
+            branches: None,
+            pinned: false,
+            hidden_from_model: false,
         }
     }
 }
@@ -127,6 +169,11 @@ impl MessageMetadata {
             created_at: DateTime::UNIX_EPOCH,
             reasoning_updated_at: DateTime::UNIX_EPOCH,
             text_updated_at: DateTime::UNIX_EPOCH,
+            item_id: None,
+            answered_by: None,
+            branches: None,
+            pinned: false,
+            hidden_from_model: false,
         }
     }
 }
@@ -147,6 +194,33 @@ impl MessageMetadata {
     }
 }
 
+/// The inactive branches around a message, making conversations a tree.
+///
+/// Each branch is the list of messages that would replace the message holding this,
+/// and the ones after it, if activated. Branches keep their own alternatives nested
+/// in their messages.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct MessageBranches {
+    /// Branches ordered before the active one.
+    #[serde(default)]
+    pub before: Vec<Vec<Message>>,
+    /// Branches ordered after the active one.
+    #[serde(default)]
+    pub after: Vec<Vec<Message>>,
+}
+
+impl MessageBranches {
+    /// The position of the active branch among all of them.
+    pub fn active_index(&self) -> usize {
+        self.before.len()
+    }
+
+    /// The amount of branches, including the active one.
+    pub fn count(&self) -> usize {
+        self.before.len() + 1 + self.after.len()
+    }
+}
+
 /// A message that is part of a conversation.
 #[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct Message {
@@ -192,6 +266,16 @@ impl Message {
         }
     }
 
+    /// The position of this message among its alternatives, and the amount of them.
+    ///
+    /// `(0, 1)` if the message has no alternatives.
+    pub fn branch_position(&self) -> (usize, usize) {
+        match &self.metadata.branches {
+            Some(branches) => (branches.active_index(), branches.count()),
+            None => (0, 1),
+        }
+    }
+
     /// Set the content of a message as a whole (also updates metadata).
     pub fn set_content(&mut self, content: MessageContent) {
         self.update_content(|c| {
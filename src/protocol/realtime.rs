@@ -1,3 +1,4 @@
+use super::*;
 use std::sync::{Arc, Mutex};
 
 /// Upgrade types for enhanced communication modes
@@ -31,6 +32,12 @@ impl PartialEq for RealtimeChannel {
 pub enum RealtimeEvent {
     /// Session is ready for communication
     SessionReady,
+    /// Session ended, either by request or because the connection was lost
+    SessionClosed,
+    /// Connection was lost and a new one is being attempted (starting at 1)
+    Reconnecting { attempt: u32 },
+    /// Connection was restored and the session resumed
+    Reconnected,
     /// Audio data received (PCM16 format)
     AudioData(Vec<u8>),
     /// Text transcript of received audio (delta)
@@ -76,3 +83,42 @@ pub enum RealtimeCommand {
     /// Send function call result back to AI
     SendFunctionCallResult { call_id: String, output: String },
 }
+
+/// A client able to hold realtime sessions with bots.
+///
+/// Different from [`BotClient`], which answers each send with a message, a
+/// realtime client opens a long lived [`RealtimeChannel`] where events and
+/// commands flow in both directions until the session is stopped.
+///
+/// Restoring a lost connection is up to implementations, behind the same channel,
+/// as sessions only end with [`RealtimeEvent::SessionClosed`].
+pub trait RealtimeClient: Send {
+    /// Opens a new session with the given bot.
+    ///
+    /// The given messages are the conversation so far, which implementations
+    /// may use as initial context for the session.
+    fn start(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendFuture<'static, ClientResult<RealtimeChannel>>;
+
+    /// Ends a session previously opened with [`RealtimeClient::start`].
+    ///
+    /// By default, this sends a [`RealtimeCommand::StopSession`] through the channel.
+    fn stop(&mut self, channel: &RealtimeChannel) {
+        let _ = channel
+            .command_sender
+            .unbounded_send(RealtimeCommand::StopSession);
+    }
+
+    /// Make a boxed dynamic clone of this client to pass around.
+    fn clone_box(&self) -> Box<dyn RealtimeClient>;
+}
+
+impl Clone for Box<dyn RealtimeClient> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
@@ -0,0 +1,841 @@
+//! Persistence of conversations, so apps don't need to write their own save and load.
+//!
+//! See [`ConversationStore`] for the interface, and [`AutosavePlugin`] to keep a
+//! [`ChatController`](crate::controllers::chat::ChatController) saved. Titles can be
+//! generated with [`TitlePlugin`].
+
+use crate::controllers::chat::SystemInstructions;
+use crate::protocol::*;
+use crate::utils::asynchronous::BoxPlatformSendFuture;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::utils::hash::KeyHasher;
+
+#[cfg(feature = "async-rt")]
+use crate::controllers::chat::{ChatControllerPlugin, ChatState, ChatStateMutation};
+#[cfg(feature = "async-rt")]
+use crate::utils::asynchronous::{BasicSpawner, ErasedSpawner, Spawner, sleep};
+#[cfg(feature = "async-rt")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "async-rt")]
+use std::time::Duration;
+
+#[cfg(feature = "async-rt")]
+mod title;
+
+#[cfg(feature = "async-rt")]
+pub use title::*;
+
+/// Information about a stored conversation, cheap to list without its messages.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConversationMetadata {
+    /// Unique identifier of the conversation inside its store.
+    pub id: String,
+    /// Human readable title, if one was given or generated.
+    #[serde(default)]
+    pub title: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// The bot selected when the conversation was saved.
+    #[serde(default)]
+    pub bot_id: Option<BotId>,
+    /// The system instructions of the conversation when it was saved.
+    #[serde(default, skip_serializing_if = "SystemInstructions::is_empty")]
+    pub system_instructions: SystemInstructions,
+}
+
+impl ConversationMetadata {
+    /// Metadata for a new conversation, created now.
+    pub fn new(id: impl Into<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: id.into(),
+            title: None,
+            created_at: now,
+            updated_at: now,
+            bot_id: None,
+            system_instructions: SystemInstructions::default(),
+        }
+    }
+}
+
+/// A conversation as saved in a [`ConversationStore`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Conversation {
+    pub metadata: ConversationMetadata,
+    /// The active branch of messages, holding the alternative ones in their metadata.
+    #[serde(default)]
+    pub messages: Vec<Message>,
+}
+
+impl Conversation {
+    /// An empty conversation with the given id, created now.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            metadata: ConversationMetadata::new(id),
+            messages: Vec::new(),
+        }
+    }
+}
+
+/// Where conversations are saved and loaded from.
+///
+/// Stores are responsible for persisting the content of attachments too, and for
+/// restoring their readers on load, so they can be read right away.
+pub trait ConversationStore: Send + Sync + 'static {
+    /// Metadata of all the stored conversations, most recently updated first.
+    fn list(&self) -> BoxPlatformSendFuture<'static, std::io::Result<Vec<ConversationMetadata>>>;
+
+    /// Loads the conversation with the given id.
+    fn load(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<Conversation>>;
+
+    /// Saves a conversation, replacing the previous version with the same id.
+    fn save(
+        &self,
+        conversation: &Conversation,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>>;
+
+    /// Deletes the conversation with the given id, including its attachments.
+    fn delete(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<()>>;
+
+    /// Changes the title of the conversation with the given id, without loading it.
+    fn rename(
+        &self,
+        id: &str,
+        title: Option<String>,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>>;
+}
+
+impl<S: ConversationStore + ?Sized> ConversationStore for Arc<S> {
+    fn list(&self) -> BoxPlatformSendFuture<'static, std::io::Result<Vec<ConversationMetadata>>> {
+        (**self).list()
+    }
+
+    fn load(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<Conversation>> {
+        (**self).load(id)
+    }
+
+    fn save(
+        &self,
+        conversation: &Conversation,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        (**self).save(conversation)
+    }
+
+    fn delete(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        (**self).delete(id)
+    }
+
+    fn rename(
+        &self,
+        id: &str,
+        title: Option<String>,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        (**self).rename(id, title)
+    }
+}
+
+fn not_found(id: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("Conversation '{id}' not found"),
+    )
+}
+
+/// Keeps conversations in memory, for the lifetime of the store.
+///
+/// Clones share the same conversations.
+#[derive(Clone, Default)]
+pub struct MemoryConversationStore {
+    conversations: Arc<Mutex<HashMap<String, Conversation>>>,
+}
+
+impl MemoryConversationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many conversations are currently stored.
+    pub fn len(&self) -> usize {
+        self.conversations.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ConversationStore for MemoryConversationStore {
+    fn list(&self) -> BoxPlatformSendFuture<'static, std::io::Result<Vec<ConversationMetadata>>> {
+        let mut list: Vec<_> = self
+            .conversations
+            .lock()
+            .unwrap()
+            .values()
+            .map(|c| c.metadata.clone())
+            .collect();
+        list.sort_by_key(|m| std::cmp::Reverse(m.updated_at));
+        Box::pin(async move { Ok(list) })
+    }
+
+    fn load(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<Conversation>> {
+        let result = self
+            .conversations
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| not_found(id));
+        Box::pin(async move { result })
+    }
+
+    fn save(
+        &self,
+        conversation: &Conversation,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        self.conversations
+            .lock()
+            .unwrap()
+            .insert(conversation.metadata.id.clone(), conversation.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        let result = match self.conversations.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(not_found(id)),
+        };
+        Box::pin(async move { result })
+    }
+
+    fn rename(
+        &self,
+        id: &str,
+        title: Option<String>,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        let result = match self.conversations.lock().unwrap().get_mut(id) {
+            Some(conversation) => {
+                conversation.metadata.title = title;
+                Ok(())
+            }
+            None => Err(not_found(id)),
+        };
+        Box::pin(async move { result })
+    }
+}
+
+/// Stores each conversation as JSON files in its own directory.
+///
+/// Inside the root directory, a conversation with id `<id>` is laid out as:
+///
+/// - `<id>/metadata.json`: The [`ConversationMetadata`], the only file read when listing.
+/// - `<id>/messages.json`: The messages.
+/// - `<id>/attachments/<hash>`: The content of each attachment, named by its hash,
+///   so repeated attachments are stored once.
+///
+/// Ids must be valid file names, and can't start with a dot. Attachments of loaded
+/// conversations read their content from their files when needed.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct FileConversationStore {
+    dir: std::path::PathBuf,
+    /// Keys of the attachments in the last save of each conversation, so the ones
+    /// still in memory are not read and hashed again on every save.
+    keys: Arc<Mutex<HashMap<String, HashMap<Attachment, String>>>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const METADATA_FILE: &str = "metadata.json";
+#[cfg(not(target_arch = "wasm32"))]
+const MESSAGES_FILE: &str = "messages.json";
+#[cfg(not(target_arch = "wasm32"))]
+const ATTACHMENTS_DIR: &str = "attachments";
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileConversationStore {
+    /// Uses the given directory as root. It's created when needed.
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        FileConversationStore {
+            dir: dir.into(),
+            keys: Default::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    fn conversation_dir(&self, id: &str) -> std::io::Result<std::path::PathBuf> {
+        validate_file_name(id)?;
+        Ok(self.dir.join(id))
+    }
+
+    /// Runs file system work outside of the async context.
+    fn blocking<T: Send + 'static>(
+        f: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<T>> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        crate::utils::thread::queue_blocking(move || {
+            let _ = tx.send(f());
+        });
+
+        Box::pin(async move {
+            rx.await.unwrap_or_else(|_| {
+                Err(std::io::Error::other(
+                    "Failed to receive file operation result",
+                ))
+            })
+        })
+    }
+
+    fn read_json<T: serde::de::DeserializeOwned>(path: &std::path::Path) -> std::io::Result<T> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Writes to a temporary file first, so a failure never leaves a partial file.
+    fn write_atomic(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ConversationStore for FileConversationStore {
+    fn list(&self) -> BoxPlatformSendFuture<'static, std::io::Result<Vec<ConversationMetadata>>> {
+        let dir = self.dir.clone();
+        Self::blocking(move || {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(Vec::new());
+                }
+                Err(error) => return Err(error),
+            };
+
+            let mut list = Vec::new();
+            for entry in entries {
+                let path = entry?.path().join(METADATA_FILE);
+                if !path.is_file() {
+                    continue;
+                }
+
+                match Self::read_json::<ConversationMetadata>(&path) {
+                    Ok(metadata) => list.push(metadata),
+                    Err(error) => {
+                        log::warn!("Skipping unreadable conversation at {path:?}: {error}")
+                    }
+                }
+            }
+
+            list.sort_by_key(|m| std::cmp::Reverse(m.updated_at));
+            Ok(list)
+        })
+    }
+
+    fn load(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<Conversation>> {
+        let id = id.to_string();
+        let dir = self.conversation_dir(&id);
+        // Loaded attachments have their keys already.
+        self.keys.lock().unwrap().remove(&id);
+
+        Box::pin(async move {
+            let dir = dir?;
+            let read_dir = dir.clone();
+            let (metadata, mut messages) = Self::blocking(move || {
+                let metadata = match Self::read_json(&read_dir.join(METADATA_FILE)) {
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                        return Err(not_found(&id));
+                    }
+                    result => result?,
+                };
+                let messages: Vec<Message> = Self::read_json(&read_dir.join(MESSAGES_FILE))?;
+                Ok((metadata, messages))
+            })
+            .await?;
+
+            let attachments_dir = dir.join(ATTACHMENTS_DIR);
+            for_each_attachment(&mut messages, &mut |attachment| {
+                if !attachment.has_persistence_key() {
+                    return;
+                }
+
+                let attachments_dir = attachments_dir.clone();
+                attachment.set_persistence_reader(move |key| {
+                    let path = validate_file_name(key).map(|_| attachments_dir.join(key));
+                    Self::blocking(move || Ok(Arc::from(std::fs::read(path?)?)))
+                });
+            });
+
+            Ok(Conversation { metadata, messages })
+        })
+    }
+
+    fn save(
+        &self,
+        conversation: &Conversation,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        let dir = self.conversation_dir(&conversation.metadata.id);
+        let mut conversation = conversation.clone();
+        let cache = self.keys.clone();
+
+        Box::pin(async move {
+            let dir = dir?;
+            let attachments_dir = dir.join(ATTACHMENTS_DIR);
+
+            let mut attachments = Vec::new();
+            for_each_attachment(&mut conversation.messages, &mut |attachment| {
+                attachments.push(attachment.clone());
+            });
+
+            // Content already stored next to this conversation is not read again.
+            let keys: Vec<Option<String>> = {
+                let cache = cache.lock().unwrap();
+                let cached = cache.get(&conversation.metadata.id);
+                attachments
+                    .iter()
+                    .map(|a| {
+                        a.get_persistence_key()
+                            .or_else(|| cached?.get(a).map(String::as_str))
+                            .map(str::to_string)
+                    })
+                    .collect()
+            };
+            let check_dir = attachments_dir.clone();
+            let stored = Self::blocking(move || {
+                Ok(keys
+                    .into_iter()
+                    .map(|key| {
+                        key.filter(|key| {
+                            validate_file_name(key).is_ok() && check_dir.join(key).is_file()
+                        })
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .await?;
+
+            let mut new_keys = Vec::with_capacity(attachments.len());
+            let mut contents = Vec::new();
+            for (attachment, stored) in attachments.iter().zip(stored) {
+                if stored.is_some() || !attachment.is_available() {
+                    new_keys.push(stored);
+                    continue;
+                }
+
+                match attachment.read().await {
+                    Ok(content) => {
+                        let mut hasher = KeyHasher::new();
+                        hasher.update(&content);
+                        let key = hasher.finish();
+                        contents.push((key.clone(), content));
+                        new_keys.push(Some(key));
+                    }
+                    Err(error) => {
+                        log::warn!(
+                            "Saving attachment {} as unavailable: {error}",
+                            attachment.name
+                        );
+                        new_keys.push(None);
+                    }
+                }
+            }
+
+            let cached: HashMap<Attachment, String> = attachments
+                .into_iter()
+                .zip(&new_keys)
+                .filter(|(a, _)| !a.has_persistence_key())
+                .filter_map(|(a, key)| Some((a, key.clone()?)))
+                .collect();
+
+            let mut new_keys = new_keys.into_iter();
+            for_each_attachment(&mut conversation.messages, &mut |attachment| {
+                if let Some(Some(key)) = new_keys.next() {
+                    attachment.set_persistence_key(key);
+                }
+            });
+
+            let metadata = serde_json::to_vec_pretty(&conversation.metadata)?;
+            let messages = serde_json::to_vec(&conversation.messages)?;
+            let mut referenced = Vec::new();
+            for_each_attachment(&mut conversation.messages, &mut |attachment| {
+                if let Some(key) = attachment.get_persistence_key() {
+                    referenced.push(key.to_string());
+                }
+            });
+
+            Self::blocking(move || {
+                std::fs::create_dir_all(&attachments_dir)?;
+                for (key, content) in contents {
+                    let path = attachments_dir.join(&key);
+                    if !path.is_file() {
+                        Self::write_atomic(&path, &content)?;
+                    }
+                }
+
+                Self::write_atomic(&dir.join(MESSAGES_FILE), &messages)?;
+                Self::write_atomic(&dir.join(METADATA_FILE), &metadata)?;
+
+                for entry in std::fs::read_dir(&attachments_dir)? {
+                    let entry = entry?;
+                    let name = entry.file_name();
+                    if !referenced.iter().any(|key| name == key.as_str()) {
+                        std::fs::remove_file(entry.path())?;
+                    }
+                }
+
+                Ok(())
+            })
+            .await?;
+
+            cache
+                .lock()
+                .unwrap()
+                .insert(conversation.metadata.id, cached);
+            Ok(())
+        })
+    }
+
+    fn delete(&self, id: &str) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        let id = id.to_string();
+        let dir = self.conversation_dir(&id);
+        self.keys.lock().unwrap().remove(&id);
+        Self::blocking(move || match std::fs::remove_dir_all(dir?) {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Err(not_found(&id)),
+            result => result,
+        })
+    }
+
+    fn rename(
+        &self,
+        id: &str,
+        title: Option<String>,
+    ) -> BoxPlatformSendFuture<'static, std::io::Result<()>> {
+        let id = id.to_string();
+        let dir = self.conversation_dir(&id);
+        Self::blocking(move || {
+            let path = dir?.join(METADATA_FILE);
+            let mut metadata: ConversationMetadata = match Self::read_json(&path) {
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(not_found(&id));
+                }
+                result => result?,
+            };
+
+            metadata.title = title;
+            Self::write_atomic(&path, &serde_json::to_vec_pretty(&metadata)?)
+        })
+    }
+}
+
+/// Rejects names that could escape the directory they are joined to.
+#[cfg(not(target_arch = "wasm32"))]
+fn validate_file_name(name: &str) -> std::io::Result<()> {
+    let invalid = name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', ':', '\0']);
+
+    if invalid {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{name}' is not a valid file name"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Visits the attachments of the given messages, including the ones in alternative
+/// branches, always in the same order.
+#[cfg(not(target_arch = "wasm32"))]
+fn for_each_attachment(messages: &mut [Message], f: &mut dyn FnMut(&mut Attachment)) {
+    for message in messages {
+        message.content.attachments.iter_mut().for_each(&mut *f);
+
+        if let Some(branches) = message.metadata.branches.as_deref_mut() {
+            for branch in branches.before.iter_mut().chain(branches.after.iter_mut()) {
+                for_each_attachment(branch, f);
+            }
+        }
+    }
+}
+
+/// Saves the conversation of a [`ChatController`](crate::controllers::chat::ChatController)
+/// when its messages or its selected bot change.
+///
+/// Saves are debounced, so a burst of changes is saved once, after the configured
+/// delay passes without more changes. Nothing is saved while an answer is being
+/// written, only once it's done.
+///
+/// Pending saves still happen if the controller is dropped, unless cancelled
+/// through [`Self::cancel_handle`].
+#[cfg(feature = "async-rt")]
+pub struct AutosavePlugin {
+    store: Arc<dyn ConversationStore>,
+    metadata: Arc<Mutex<ConversationMetadata>>,
+    delay: Duration,
+    spawner: Box<dyn ErasedSpawner>,
+    dirty: bool,
+    /// Incremented for each scheduled save, so only the last one happens.
+    generation: Arc<AtomicU64>,
+    cancel: AutosaveCancel,
+}
+
+/// Stops an [`AutosavePlugin`] from saving, including the save waiting for its
+/// delay, like when its conversation is deleted.
+#[cfg(feature = "async-rt")]
+#[derive(Clone, Debug, Default)]
+pub struct AutosaveCancel(Arc<AtomicBool>);
+
+#[cfg(feature = "async-rt")]
+impl AutosaveCancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(feature = "async-rt")]
+impl AutosavePlugin {
+    /// Saves into the given store, as the conversation described by `metadata`.
+    ///
+    /// The selected bot, the system instructions and the update time of the metadata
+    /// are kept in sync with the controller.
+    pub fn new(store: impl ConversationStore, metadata: ConversationMetadata) -> Self {
+        Self::new_shared(store, Arc::new(Mutex::new(metadata)))
+    }
+
+    /// Like [`Self::new`], but with metadata shared with others, like a
+    /// [`SessionManager`](crate::controllers::session::SessionManager).
+    pub fn new_shared(
+        store: impl ConversationStore,
+        metadata: Arc<Mutex<ConversationMetadata>>,
+    ) -> Self {
+        Self {
+            store: Arc::new(store),
+            metadata,
+            delay: Duration::from_secs(1),
+            spawner: Box::new(BasicSpawner),
+            dirty: false,
+            generation: Arc::new(AtomicU64::new(0)),
+            cancel: AutosaveCancel::default(),
+        }
+    }
+
+    /// Sets how long to wait for more changes before saving. Defaults to 1 second.
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Sets the spawner used to save in the background. Defaults to [`BasicSpawner`].
+    pub fn set_spawner<S>(&mut self, spawner: S)
+    where
+        S: ErasedSpawner + 'static,
+    {
+        self.spawner = Box::new(spawner);
+    }
+
+    /// The metadata saved with the conversation.
+    ///
+    /// Changes to it (like the title) are included in the next save.
+    pub fn metadata(&self) -> &Arc<Mutex<ConversationMetadata>> {
+        &self.metadata
+    }
+
+    /// Gives a handle to stop saving from outside, once the plugin is in a controller.
+    pub fn cancel_handle(&self) -> AutosaveCancel {
+        self.cancel.clone()
+    }
+}
+
+#[cfg(feature = "async-rt")]
+impl ChatControllerPlugin for AutosavePlugin {
+    fn on_state_ready(&mut self, state: &ChatState, mutations: &[ChatStateMutation]) {
+        self.dirty |= mutations.iter().any(|m| {
+            matches!(
+                m,
+                ChatStateMutation::MutateMessages(_)
+                    | ChatStateMutation::SetBotId(_)
+                    | ChatStateMutation::SetSystemInstructions(_)
+            )
+        });
+
+        let is_writing = state.is_streaming || state.messages.iter().any(|m| m.metadata.is_writing);
+        if !self.dirty || is_writing || self.cancel.is_cancelled() {
+            return;
+        }
+
+        self.dirty = false;
+        {
+            let mut metadata = self.metadata.lock().unwrap();
+            metadata.updated_at = Utc::now();
+            metadata.bot_id = state.bot_id.clone();
+            metadata.system_instructions = state.system_instructions.clone();
+        }
+
+        let messages = state.messages.clone();
+        let metadata = self.metadata.clone();
+        let store = self.store.clone();
+        let delay = self.delay;
+        let generation = self.generation.clone();
+        let current = generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let cancel = self.cancel.clone();
+
+        self.spawner.spawn(async move {
+            sleep(delay).await;
+            if generation.load(Ordering::SeqCst) != current || cancel.is_cancelled() {
+                return;
+            }
+
+            // Read now, to include changes made while waiting, like a new title.
+            let conversation = Conversation {
+                metadata: metadata.lock().unwrap().clone(),
+                messages,
+            };
+
+            if let Err(error) = store.save(&conversation).await {
+                log::warn!(
+                    "Failed to save conversation '{}': {error}",
+                    conversation.metadata.id
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn message(text: &str, attachments: Vec<Attachment>) -> Message {
+        Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: text.into(),
+                attachments,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn conversation(id: &str, messages: Vec<Message>) -> Conversation {
+        Conversation {
+            messages,
+            ..Conversation::new(id)
+        }
+    }
+
+    #[test]
+    fn memory_store_operations() {
+        let store = MemoryConversationStore::new();
+        block_on(store.save(&conversation("a", vec![message("hi", vec![])]))).unwrap();
+        let mut newer = conversation("b", vec![]);
+        newer.metadata.updated_at += chrono::Duration::seconds(1);
+        block_on(store.save(&newer)).unwrap();
+
+        let list = block_on(store.list()).unwrap();
+        assert_eq!(list[0].id, "b");
+        assert_eq!(list[1].id, "a");
+
+        block_on(store.rename("a", Some("Greeting".into()))).unwrap();
+        let loaded = block_on(store.load("a")).unwrap();
+        assert_eq!(loaded.metadata.title.as_deref(), Some("Greeting"));
+        assert_eq!(loaded.messages[0].content.text, "hi");
+
+        block_on(store.delete("a")).unwrap();
+        assert_eq!(
+            block_on(store.load("a")).unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        assert_eq!(store.len(), 1);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn file_store_persists_attachments() {
+        let dir = std::env::temp_dir().join(format!("aitk-store-test-{}", std::process::id()));
+        let store = FileConversationStore::new(&dir);
+
+        let image = Attachment::from_bytes("a.png".into(), Some("image/png".into()), b"png");
+        let mut saved = conversation(
+            "chat",
+            vec![
+                message("one", vec![image.clone()]),
+                message("two", vec![image]),
+            ],
+        );
+        block_on(store.save(&saved)).unwrap();
+
+        let attachments = dir.join("chat").join(ATTACHMENTS_DIR);
+        assert_eq!(std::fs::read_dir(&attachments).unwrap().count(), 1);
+        // Saving again doesn't need to read the content to find its key.
+        assert_eq!(store.keys.lock().unwrap()["chat"].len(), 1);
+
+        let mut loaded = block_on(store.load("chat")).unwrap();
+        assert_eq!(loaded.metadata, saved.metadata);
+        let attachment = &loaded.messages[1].content.attachments[0];
+        assert!(attachment.has_persistence_reader());
+        assert_eq!(&*block_on(attachment.read()).unwrap(), b"png");
+
+        // Saving what was loaded keeps the stored content, and unused one is removed.
+        loaded.messages[0].content.attachments.clear();
+        block_on(store.save(&loaded)).unwrap();
+        assert_eq!(std::fs::read_dir(&attachments).unwrap().count(), 1);
+        loaded.messages[1].content.attachments.clear();
+        block_on(store.save(&loaded)).unwrap();
+        assert_eq!(std::fs::read_dir(&attachments).unwrap().count(), 0);
+
+        block_on(store.rename("chat", Some("Title".into()))).unwrap();
+        let list = block_on(store.list()).unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].title.as_deref(), Some("Title"));
+
+        saved.metadata.id = "../escape".into();
+        assert_eq!(
+            block_on(store.save(&saved)).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+
+        block_on(store.delete("chat")).unwrap();
+        assert!(block_on(store.list()).unwrap().is_empty());
+        assert!(store.keys.lock().unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "async-rt")]
+    #[test]
+    fn autosave_debounces_changes() {
+        use crate::controllers::chat::ChatController;
+        use crate::utils::vec::VecMutation;
+
+        let store = MemoryConversationStore::new();
+        let mut plugin = AutosavePlugin::new(store.clone(), ConversationMetadata::new("chat"));
+        plugin.set_delay(Duration::from_millis(50));
+
+        let controller = ChatController::builder()
+            .with_plugin_append(plugin)
+            .build_arc();
+
+        let mut c = controller.lock().unwrap();
+        c.dispatch_mutation(VecMutation::Push(message("one", vec![])));
+        c.dispatch_mutation(VecMutation::Push(message("two", vec![])));
+        drop(c);
+
+        assert!(store.is_empty());
+        std::thread::sleep(Duration::from_millis(300));
+
+        let saved = block_on(store.load("chat")).unwrap();
+        assert_eq!(saved.messages.len(), 2);
+    }
+}
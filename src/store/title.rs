@@ -0,0 +1,314 @@
+use super::{ConversationMetadata, ConversationStore};
+use crate::controllers::chat::{ChatControllerPlugin, ChatState, ChatStateMutation};
+use crate::protocol::*;
+use crate::utils::asynchronous::{BasicSpawner, ErasedSpawner, Spawner, sleep};
+use futures::StreamExt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Instruction sent after the first exchange to get its title.
+const DEFAULT_PROMPT: &str = "Write a short title, of at most 6 words, for the conversation above. \
+Answer only with the title, without quotes or punctuation at the end.";
+
+/// Maximum number of characters of a title, generated or not.
+const MAX_TITLE_CHARS: usize = 60;
+
+/// Gives a title to a conversation once the first answer to it finishes.
+///
+/// The title is asked in the background to the configured bot, and written into
+/// the given metadata, which can be shared with an [`AutosavePlugin`](super::AutosavePlugin).
+/// If the bot fails even after retrying, the start of the first user message is
+/// used instead.
+///
+/// Conversations that already have a title are left untouched.
+pub struct TitlePlugin {
+    client: Box<dyn BotClient>,
+    bot_id: BotId,
+    metadata: Arc<Mutex<ConversationMetadata>>,
+    store: Option<Arc<dyn ConversationStore>>,
+    spawner: Box<dyn ErasedSpawner>,
+    prompt: String,
+    max_retries: u32,
+    retry_delay: Duration,
+    was_streaming: bool,
+    requested: bool,
+}
+
+impl TitlePlugin {
+    /// Asks `bot_id` through `client` for titles, writing them into `metadata`.
+    pub fn new(
+        client: Box<dyn BotClient>,
+        bot_id: BotId,
+        metadata: Arc<Mutex<ConversationMetadata>>,
+    ) -> Self {
+        Self {
+            client,
+            bot_id,
+            metadata,
+            store: None,
+            spawner: Box::new(BasicSpawner),
+            prompt: DEFAULT_PROMPT.to_string(),
+            max_retries: 1,
+            retry_delay: Duration::from_secs(1),
+            was_streaming: false,
+            requested: false,
+        }
+    }
+
+    /// Also renames the conversation in the given store once titled, so the title
+    /// is saved even if nothing else changes.
+    pub fn set_store(&mut self, store: Option<Arc<dyn ConversationStore>>) {
+        self.store = store;
+    }
+
+    /// Sets the spawner used to ask for titles. Defaults to [`BasicSpawner`].
+    pub fn set_spawner<S>(&mut self, spawner: S)
+    where
+        S: ErasedSpawner + 'static,
+    {
+        self.spawner = Box::new(spawner);
+    }
+
+    /// Replaces the instruction sent after the conversation to ask for its title.
+    pub fn set_prompt(&mut self, prompt: impl Into<String>) {
+        self.prompt = prompt.into();
+    }
+
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    /// Sets how many times to ask again after a failure, and how long to wait
+    /// before each. Defaults to once, after 1 second.
+    pub fn set_retries(&mut self, max_retries: u32, delay: Duration) {
+        self.max_retries = max_retries;
+        self.retry_delay = delay;
+    }
+
+    /// The metadata where titles are written.
+    pub fn metadata(&self) -> &Arc<Mutex<ConversationMetadata>> {
+        &self.metadata
+    }
+}
+
+impl ChatControllerPlugin for TitlePlugin {
+    fn on_state_ready(&mut self, state: &ChatState, _mutations: &[ChatStateMutation]) {
+        let finished = self.was_streaming && !state.is_streaming;
+        self.was_streaming = state.is_streaming;
+
+        if !finished || self.requested || self.metadata.lock().unwrap().title.is_some() {
+            return;
+        }
+
+        let Some(question) = state.messages.iter().find(|m| m.from == EntityId::User) else {
+            return;
+        };
+        let Some(answer) = state
+            .messages
+            .iter()
+            .find(|m| matches!(m.from, EntityId::Bot(_)) && !m.content.text.trim().is_empty())
+        else {
+            return;
+        };
+
+        self.requested = true;
+
+        // Only text is sent, as attachments would be costly and rarely help.
+        let messages = [question, answer]
+            .into_iter()
+            .map(|m| Message {
+                from: m.from.clone(),
+                content: MessageContent {
+                    text: m.content.text.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .chain(std::iter::once(Message {
+                from: EntityId::User,
+                content: MessageContent {
+                    text: self.prompt.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }))
+            .collect::<Vec<_>>();
+
+        let fallback = fallback_title(&question.content.text);
+        let mut client = self.client.clone();
+        let bot_id = self.bot_id.clone();
+        let metadata = self.metadata.clone();
+        let store = self.store.clone();
+        let max_retries = self.max_retries;
+        let retry_delay = self.retry_delay;
+
+        self.spawner.spawn(async move {
+            let mut title = None;
+            for attempt in 0..=max_retries {
+                if attempt > 0 {
+                    sleep(retry_delay).await;
+                }
+
+                match ask_title(&mut client, &bot_id, &messages).await {
+                    Ok(generated) => {
+                        title = Some(generated);
+                        break;
+                    }
+                    Err(error) => log::warn!("Failed to generate a title: {error}"),
+                }
+            }
+
+            let Some(title) = title.or(fallback) else {
+                return;
+            };
+
+            let id = {
+                let mut metadata = metadata.lock().unwrap();
+                // Titled by someone else meanwhile, like the user renaming it.
+                if metadata.title.is_some() {
+                    return;
+                }
+                metadata.title = Some(title.clone());
+                metadata.id.clone()
+            };
+
+            // If not saved yet, the title is included when it is.
+            if let Some(store) = store
+                && let Err(error) = store.rename(&id, Some(title)).await
+                && error.kind() != std::io::ErrorKind::NotFound
+            {
+                log::warn!("Failed to save the title of conversation '{id}': {error}");
+            }
+        });
+    }
+}
+
+/// Asks for the title, failing if the bot errors or answers nothing usable.
+async fn ask_title(
+    client: &mut Box<dyn BotClient>,
+    bot_id: &BotId,
+    messages: &[Message],
+) -> Result<String, ClientError> {
+    let mut stream = client.send(bot_id, messages, &[]);
+    let mut content = None;
+    while let Some(result) = stream.next().await {
+        let (value, errors) = result.into_value_and_errors();
+        if let Some(error) = errors.into_iter().next() {
+            return Err(error);
+        }
+        content = value.or(content);
+    }
+
+    content
+        .and_then(|c| clean_title(&c.text))
+        .ok_or_else(|| ClientError::new(ClientErrorKind::Response, "Empty title".into()))
+}
+
+/// Takes the first line of a generated title, without the quotes and trailing
+/// punctuation models tend to add anyway.
+fn clean_title(text: &str) -> Option<String> {
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line
+        .trim_start_matches(['"', '\'', '*', '#'])
+        .trim_end_matches(['"', '\'', '*', '.'])
+        .trim();
+    let line = line.strip_prefix("Title:").unwrap_or(line).trim();
+
+    (!line.is_empty()).then(|| truncate(line))
+}
+
+/// Title made of the start of the first user message.
+fn fallback_title(text: &str) -> Option<String> {
+    let words = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!words.is_empty()).then(|| truncate(&words))
+}
+
+/// Shortens a title to [`MAX_TITLE_CHARS`], at a word boundary if possible.
+fn truncate(title: &str) -> String {
+    if title.chars().count() <= MAX_TITLE_CHARS {
+        return title.to_string();
+    }
+
+    let end = title
+        .char_indices()
+        .nth(MAX_TITLE_CHARS)
+        .map(|(i, _)| i)
+        .unwrap_or(title.len());
+    let cut = &title[..end];
+    let cut = cut.rfind(' ').map(|i| &cut[..i]).unwrap_or(cut);
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::chat::ChatController;
+    use crate::testing::*;
+    use crate::utils::vec::VecMutation;
+
+    fn exchange(controller: &Arc<Mutex<ChatController>>) {
+        let message = |from: EntityId, text: &str| Message {
+            from,
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut c = controller.lock().unwrap();
+        c.dispatch_mutation(VecMutation::Push(message(EntityId::User, "Hello   there")));
+        c.dispatch_mutation(ChatStateMutation::SetIsStreaming(true));
+        c.dispatch_mutation(VecMutation::Push(message(
+            EntityId::Bot(BotId::new("bot")),
+            "Hi!",
+        )));
+        c.dispatch_mutation(ChatStateMutation::SetIsStreaming(false));
+    }
+
+    /// Fails the given number of times, then answers with a quoted title.
+    fn titled(failures: usize) -> (Option<String>, usize) {
+        let client = MockClient::responding(move |call| match call.number > failures {
+            true => MockReply::text("\"Greeting the bot.\"\n"),
+            false => MockReply::error(ClientError::new(ClientErrorKind::Network, "offline".into())),
+        });
+        let metadata = Arc::new(Mutex::new(ConversationMetadata::new("chat")));
+        let mut plugin = TitlePlugin::new(
+            Box::new(client.clone()),
+            BotId::new("titler"),
+            metadata.clone(),
+        );
+        plugin.set_retries(1, Duration::from_millis(10));
+
+        let controller = ChatController::builder()
+            .with_plugin_append(plugin)
+            .build_arc();
+        exchange(&controller);
+        // Another answer doesn't ask again.
+        exchange(&controller);
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let title = loop {
+            let title = metadata.lock().unwrap().title.clone();
+            if title.is_some() || std::time::Instant::now() > deadline {
+                break title;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        (title, client.call_count())
+    }
+
+    #[test]
+    fn title_is_generated_after_retrying() {
+        assert_eq!(titled(1), (Some("Greeting the bot".into()), 2));
+    }
+
+    #[test]
+    fn title_falls_back_to_first_user_message() {
+        assert_eq!(titled(5), (Some("Hello there".into()), 2));
+        assert_eq!(
+            truncate(&"word ".repeat(20)),
+            format!("{}…", ["word"; 12].join(" "))
+        );
+    }
+}
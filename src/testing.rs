@@ -0,0 +1,27 @@
+//! Utilities to test code built on aitk without network.
+//!
+//! [`MockClient`] is an in-process [`BotClient`](crate::protocol::BotClient)
+//! answering with scripted [`MockReply`]s, to test code sitting on top of clients,
+//! like client wrappers or a [`crate::controllers::chat::ChatController`].
+//!
+//! [`MockServer`] is a local HTTP server imitating OpenAI-compatible and Gemini APIs,
+//! answering with scripted [`MockResponse`]s. Point the real clients to it to test
+//! them, or a controller using them, end to end.
+//!
+//! ```no_run
+//! # use aitk::prelude::*;
+//! # use aitk::testing::*;
+//! let server = MockServer::start().unwrap();
+//! server.push_response(MockResponse::text("Hello world").with_delta_size(3));
+//!
+//! let client = OpenAiClient::new(server.openai_url());
+//! // ...
+//! ```
+
+mod client;
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+mod server;
+
+pub use client::*;
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+pub use server::*;
@@ -0,0 +1,282 @@
+use crate::protocol::*;
+#[cfg(feature = "async-rt")]
+use crate::utils::asynchronous::sleep;
+use crate::utils::asynchronous::{BoxPlatformSendFuture, BoxPlatformSendStream};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "async-rt")]
+use std::time::Duration;
+
+/// What a [`MockClient`] answers to a send: the results it streams, in order.
+#[derive(Clone, Debug, Default)]
+pub struct MockReply {
+    results: Vec<ClientResult<MessageContent>>,
+    #[cfg(feature = "async-rt")]
+    delay: Duration,
+    endless: bool,
+}
+
+impl MockReply {
+    /// A reply streaming the given text at once.
+    pub fn text(text: impl Into<String>) -> Self {
+        MockReply::default().then_text(text)
+    }
+
+    /// A reply streaming the given content at once.
+    pub fn content(content: MessageContent) -> Self {
+        MockReply::default().then_content(content)
+    }
+
+    /// A reply failing with the given error, before any content.
+    pub fn error(error: ClientError) -> Self {
+        MockReply::default().then_error(error)
+    }
+
+    /// A reply ending without streaming anything.
+    pub fn empty() -> Self {
+        MockReply::default()
+    }
+
+    pub fn then_text(self, text: impl Into<String>) -> Self {
+        self.then_content(MessageContent {
+            text: text.into(),
+            ..Default::default()
+        })
+    }
+
+    pub fn then_content(mut self, content: MessageContent) -> Self {
+        self.results.push(ClientResult::new_ok(content));
+        self
+    }
+
+    pub fn then_error(mut self, error: ClientError) -> Self {
+        self.results.push(ClientResult::new_err(vec![error]));
+        self
+    }
+
+    /// Waits before streaming anything.
+    #[cfg(feature = "async-rt")]
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Never ends after streaming its results, like a connection left open.
+    pub fn endless(mut self) -> Self {
+        self.endless = true;
+        self
+    }
+}
+
+/// A send received by a [`MockClient`].
+#[derive(Clone, Debug)]
+pub struct MockCall {
+    /// Starting at 1 for the first send.
+    pub number: usize,
+    pub bot_id: BotId,
+    pub messages: Vec<Message>,
+    pub tools: Vec<Tool>,
+}
+
+type Responder = Arc<dyn Fn(&MockCall) -> MockReply + Send + Sync>;
+
+#[derive(Default)]
+struct Inner {
+    bots: Vec<Bot>,
+    replies: VecDeque<MockReply>,
+    responder: Option<Responder>,
+    calls: Vec<MockCall>,
+    rate_limits: Option<RateLimits>,
+    in_flight: usize,
+    max_in_flight: usize,
+}
+
+/// An in-process [`BotClient`] answering with scripted [`MockReply`]s, and
+/// recording the sends it gets.
+///
+/// Sends are answered with the pushed replies, in order. When there are none left,
+/// the responder set with [`MockClient::set_responder`] answers, and without one,
+/// sends fail. Clones share everything, so keep one to inspect the calls.
+///
+/// ```
+/// # use aitk::prelude::*;
+/// # use aitk::testing::*;
+/// let client = MockClient::responding(|call| {
+///     MockReply::text(format!("{}: {}", call.number, call.messages.len()))
+/// });
+/// client.push_reply(MockReply::text("scripted first"));
+/// ```
+#[derive(Clone, Default)]
+pub struct MockClient(Arc<Mutex<Inner>>);
+
+impl MockClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A client answering every send with the given responder.
+    pub fn responding(responder: impl Fn(&MockCall) -> MockReply + Send + Sync + 'static) -> Self {
+        let client = Self::new();
+        client.set_responder(responder);
+        client
+    }
+
+    /// Sets the bots listed by the client. There are none by default.
+    pub fn set_bots(&self, bots: impl IntoIterator<Item = Bot>) {
+        self.0.lock().unwrap().bots = bots.into_iter().collect();
+    }
+
+    /// Lists bots with the given ids, without capabilities.
+    pub fn set_bot_ids(&self, ids: impl IntoIterator<Item = impl AsRef<str>>) {
+        self.set_bots(ids.into_iter().map(|id| Bot {
+            id: BotId::new(id.as_ref()),
+            name: id.as_ref().to_string(),
+            avatar: EntityAvatar::Text("M".into()),
+            capabilities: BotCapabilities::new(),
+        }));
+    }
+
+    /// Adds a reply for the next send not answered yet.
+    pub fn push_reply(&self, reply: MockReply) {
+        self.0.lock().unwrap().replies.push_back(reply);
+    }
+
+    /// Answers the sends without a pushed reply.
+    pub fn set_responder(
+        &self,
+        responder: impl Fn(&MockCall) -> MockReply + Send + Sync + 'static,
+    ) {
+        self.0.lock().unwrap().responder = Some(Arc::new(responder));
+    }
+
+    /// Sets what [`BotClient::rate_limits`] reports.
+    pub fn set_rate_limits(&self, rate_limits: Option<RateLimits>) {
+        self.0.lock().unwrap().rate_limits = rate_limits;
+    }
+
+    /// The sends received so far, oldest first.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.0.lock().unwrap().calls.clone()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.0.lock().unwrap().calls.len()
+    }
+
+    /// The most replies that were streaming at the same time.
+    pub fn max_in_flight(&self) -> usize {
+        self.0.lock().unwrap().max_in_flight
+    }
+}
+
+/// Counts a reply as streaming until dropped, even if the stream is.
+struct InFlight(Arc<Mutex<Inner>>);
+
+impl InFlight {
+    fn start(inner: Arc<Mutex<Inner>>) -> Self {
+        {
+            let mut inner = inner.lock().unwrap();
+            inner.in_flight += 1;
+            inner.max_in_flight = inner.max_in_flight.max(inner.in_flight);
+        }
+        InFlight(inner)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().in_flight -= 1;
+    }
+}
+
+impl BotClient for MockClient {
+    fn bots(&mut self) -> BoxPlatformSendFuture<'static, ClientResult<Vec<Bot>>> {
+        let bots = self.0.lock().unwrap().bots.clone();
+        Box::pin(async move { ClientResult::new_ok(bots) })
+    }
+
+    fn send(
+        &mut self,
+        bot_id: &BotId,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> BoxPlatformSendStream<'static, ClientResult<MessageContent>> {
+        let (call, reply, responder) = {
+            let mut inner = self.0.lock().unwrap();
+            let call = MockCall {
+                number: inner.calls.len() + 1,
+                bot_id: bot_id.clone(),
+                messages: messages.to_vec(),
+                tools: tools.to_vec(),
+            };
+            inner.calls.push(call.clone());
+            (call, inner.replies.pop_front(), inner.responder.clone())
+        };
+
+        // Called without the lock, in case it uses the client.
+        let reply = reply
+            .or_else(|| responder.map(|responder| responder(&call)))
+            .unwrap_or_else(|| {
+                MockReply::error(ClientError::new(
+                    ClientErrorKind::Unknown,
+                    format!("No reply scripted for send {}", call.number),
+                ))
+            });
+
+        let inner = self.0.clone();
+        Box::pin(async_stream::stream! {
+            let _in_flight = InFlight::start(inner);
+
+            #[cfg(feature = "async-rt")]
+            if !reply.delay.is_zero() {
+                sleep(reply.delay).await;
+            }
+
+            for result in reply.results {
+                yield result;
+            }
+
+            if reply.endless {
+                futures::future::pending::<()>().await;
+            }
+        })
+    }
+
+    fn clone_box(&self) -> Box<dyn BotClient> {
+        Box::new(self.clone())
+    }
+
+    fn rate_limits(&self) -> Option<RateLimits> {
+        self.0.lock().unwrap().rate_limits.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn send_texts(client: &mut MockClient) -> Vec<String> {
+        let stream = client.send(&BotId::new("bot"), &[], &[]);
+        futures::executor::block_on(stream.collect::<Vec<_>>())
+            .into_iter()
+            .map(|result| match result.into_result() {
+                Ok(content) => content.text,
+                Err(errors) => errors[0].message().to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replies_in_order_then_with_responder() {
+        let mut client = MockClient::new();
+        client.push_reply(MockReply::text("a").then_text("ab"));
+        assert_eq!(send_texts(&mut client), ["a", "ab"]);
+        assert_eq!(send_texts(&mut client), ["No reply scripted for send 2"]);
+
+        client.set_responder(|call| MockReply::text(format!("call {}", call.number)));
+        assert_eq!(send_texts(&mut client), ["call 3"]);
+        assert_eq!(client.call_count(), 3);
+        assert_eq!(client.max_in_flight(), 1);
+    }
+}
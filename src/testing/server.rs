@@ -0,0 +1,711 @@
+use crate::utils::asynchronous::{AbortOnDropHandle, sleep, spawn, spawn_abort_on_drop};
+use base64::Engine;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A tool call scripted in a [`MockResponse`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// What the [`MockServer`] answers to a generation request.
+///
+/// The same script is rendered in the format of the endpoint it answers to.
+#[derive(Clone, Debug, Default)]
+pub struct MockResponse {
+    text: String,
+    reasoning: String,
+    tool_calls: Vec<MockToolCall>,
+    image: Option<Vec<u8>>,
+    error: Option<(u16, String)>,
+    headers: Vec<(String, String)>,
+    delta_size: Option<usize>,
+    write_size: Option<usize>,
+    delay: Duration,
+    interrupt_after: Option<usize>,
+}
+
+impl MockResponse {
+    /// A response streaming the given text.
+    pub fn text(text: impl Into<String>) -> Self {
+        MockResponse::default().with_text(text)
+    }
+
+    /// A response requesting a single tool call.
+    pub fn tool_call(id: impl Into<String>, name: impl Into<String>, arguments: Value) -> Self {
+        MockResponse::default().with_tool_call(id, name, arguments)
+    }
+
+    /// A response to the image generation endpoint.
+    pub fn image(content: impl Into<Vec<u8>>) -> Self {
+        MockResponse {
+            image: Some(content.into()),
+            ..Default::default()
+        }
+    }
+
+    /// An unsuccessful response with the given status and body.
+    pub fn error(status: u16, body: impl Into<String>) -> Self {
+        MockResponse {
+            error: Some((status, body.into())),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.text = text.into();
+        self
+    }
+
+    /// Reasoning streamed before the text. Only in the OpenAI format.
+    pub fn with_reasoning(mut self, reasoning: impl Into<String>) -> Self {
+        self.reasoning = reasoning.into();
+        self
+    }
+
+    /// Adds a tool call, streamed after the text.
+    pub fn with_tool_call(
+        mut self,
+        id: impl Into<String>,
+        name: impl Into<String>,
+        arguments: Value,
+    ) -> Self {
+        self.tool_calls.push(MockToolCall {
+            id: id.into(),
+            name: name.into(),
+            arguments,
+        });
+        self
+    }
+
+    /// Adds a response header (e.g. `retry-after` or `x-ratelimit-*`).
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Splits the text, reasoning and tool call arguments in deltas of this many
+    /// characters. By default, each is sent in a single delta.
+    ///
+    /// Gemini doesn't stream tool call arguments, so they are never split there.
+    pub fn with_delta_size(mut self, delta_size: usize) -> Self {
+        self.delta_size = Some(delta_size.max(1));
+        self
+    }
+
+    /// Writes the body to the network in pieces of this many bytes, regardless of
+    /// where SSE events end. By default, each event is written separately.
+    pub fn with_write_size(mut self, write_size: usize) -> Self {
+        self.write_size = Some(write_size.max(1));
+        self
+    }
+
+    /// Waits this long before writing each piece of the body.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Drops the connection after writing this many pieces of the body, so the
+    /// client sees the stream interrupted.
+    pub fn interrupted_after(mut self, pieces: usize) -> Self {
+        self.interrupt_after = Some(pieces);
+        self
+    }
+
+    fn deltas<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let Some(size) = self.delta_size else {
+            return if text.is_empty() { vec![] } else { vec![text] };
+        };
+
+        let mut deltas = Vec::new();
+        let mut rest = text;
+        while !rest.is_empty() {
+            let end = rest
+                .char_indices()
+                .nth(size)
+                .map_or(rest.len(), |(index, _)| index);
+            let (delta, tail) = rest.split_at(end);
+            deltas.push(delta);
+            rest = tail;
+        }
+        deltas
+    }
+
+    fn openai_events(&self) -> Vec<String> {
+        let chunk = |delta: Value, finish_reason: Value| {
+            let chunk = json!({
+                "object": "chat.completion.chunk",
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            });
+            format!("data: {chunk}\n\n")
+        };
+
+        let mut events = Vec::new();
+
+        for delta in self.deltas(&self.reasoning) {
+            events.push(chunk(json!({ "reasoning_content": delta }), Value::Null));
+        }
+
+        for delta in self.deltas(&self.text) {
+            events.push(chunk(json!({ "content": delta }), Value::Null));
+        }
+
+        for (index, tool_call) in self.tool_calls.iter().enumerate() {
+            events.push(chunk(
+                json!({ "tool_calls": [{
+                    "index": index,
+                    "id": tool_call.id,
+                    "type": "function",
+                    "function": { "name": tool_call.name, "arguments": "" },
+                }]}),
+                Value::Null,
+            ));
+
+            for delta in self.deltas(&tool_call.arguments.to_string()) {
+                events.push(chunk(
+                    json!({ "tool_calls": [{
+                        "index": index,
+                        "function": { "arguments": delta },
+                    }]}),
+                    Value::Null,
+                ));
+            }
+        }
+
+        let finish_reason = if self.tool_calls.is_empty() {
+            "stop"
+        } else {
+            "tool_calls"
+        };
+        events.push(chunk(json!({}), json!(finish_reason)));
+        events.push("data: [DONE]\n\n".to_string());
+        events
+    }
+
+    fn gemini_events(&self) -> Vec<String> {
+        let chunk = |parts: Value| {
+            let chunk = json!({
+                "candidates": [{ "content": { "role": "model", "parts": parts } }],
+            });
+            format!("data: {chunk}\n\n")
+        };
+
+        let mut events = Vec::new();
+
+        for delta in self.deltas(&self.text) {
+            events.push(chunk(json!([{ "text": delta }])));
+        }
+
+        for tool_call in &self.tool_calls {
+            events.push(chunk(json!([{ "functionCall": {
+                "id": tool_call.id,
+                "name": tool_call.name,
+                "args": tool_call.arguments,
+            }}])));
+        }
+
+        events
+    }
+}
+
+/// A request received by the [`MockServer`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockRequest {
+    pub method: String,
+    /// Path and query, as requested.
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    /// The body, if any and if it is JSON.
+    pub body: Option<Value>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    models: Vec<String>,
+    responses: VecDeque<MockResponse>,
+    requests: Vec<MockRequest>,
+}
+
+/// A local HTTP server imitating OpenAI-compatible and Gemini APIs.
+///
+/// Serves `/models`, `/chat/completions` and `/images/generations` under
+/// [`MockServer::openai_url`], and `/models` and `:streamGenerateContent` under
+/// [`MockServer::gemini_url`].
+///
+/// Generation requests are answered with the scripted responses, in order, no
+/// matter the endpoint. When there are none left, they fail with status 500.
+///
+/// The server stops when dropped.
+pub struct MockServer {
+    address: std::net::SocketAddr,
+    state: Arc<Mutex<State>>,
+    _abort_on_drop: AbortOnDropHandle,
+}
+
+impl MockServer {
+    /// Starts the server on a random local port, serving a single `mock-model`.
+    pub fn start() -> std::io::Result<Self> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+
+        let state = Arc::new(Mutex::new(State {
+            models: vec!["mock-model".to_string()],
+            responses: VecDeque::new(),
+            requests: Vec::new(),
+        }));
+
+        let server_state = state.clone();
+        let abort_on_drop = spawn_abort_on_drop(async move {
+            let listener = match TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(error) => {
+                    log::error!("Mock server could not listen: {error}");
+                    return;
+                }
+            };
+
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                spawn(async move {
+                    if let Err(error) = handle_connection(stream, state).await {
+                        log::warn!("Mock server connection failed: {error}");
+                    }
+                });
+            }
+        });
+
+        Ok(MockServer {
+            address,
+            state,
+            _abort_on_drop: abort_on_drop,
+        })
+    }
+
+    /// Root url of the server.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Base url to give to OpenAI-compatible clients.
+    pub fn openai_url(&self) -> String {
+        format!("{}/openai", self.url())
+    }
+
+    /// Base url to give to [`crate::clients::gemini::GeminiClient`].
+    pub fn gemini_url(&self) -> String {
+        format!("{}/gemini", self.url())
+    }
+
+    /// Sets the ids of the models listed by the server.
+    pub fn set_models(&self, models: impl IntoIterator<Item = impl Into<String>>) {
+        self.state.lock().unwrap().models = models.into_iter().map(Into::into).collect();
+    }
+
+    /// Queues a response for the next generation request.
+    pub fn push_response(&self, response: MockResponse) {
+        self.state.lock().unwrap().responses.push_back(response);
+    }
+
+    /// How many scripted responses have not been used yet.
+    pub fn remaining_responses(&self) -> usize {
+        self.state.lock().unwrap().responses.len()
+    }
+
+    /// The requests received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> std::io::Result<MockRequest> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            break;
+        }
+
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+    }
+
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await?;
+
+    Ok(MockRequest {
+        method,
+        path,
+        headers,
+        body: serde_json::from_slice(&body).ok(),
+    })
+}
+
+/// What to write back for a request.
+enum Reply {
+    Json(u16, Value),
+    Stream(Vec<String>),
+}
+
+fn reply(request: &MockRequest, state: &Mutex<State>) -> (Reply, MockResponse) {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let mut state = state.lock().unwrap();
+
+    let models = &state.models;
+    let reply = match (request.method.as_str(), path) {
+        ("GET", "/openai/models") => Reply::Json(
+            200,
+            json!({
+                "object": "list",
+                "data": models.iter().map(|id| json!({ "id": id, "object": "model" })).collect::<Vec<_>>(),
+            }),
+        ),
+        ("GET", "/gemini/models") => Reply::Json(
+            200,
+            json!({
+                "models": models.iter().map(|id| json!({
+                    "name": format!("models/{id}"),
+                    "displayName": id,
+                    "supportedGenerationMethods": ["generateContent"],
+                })).collect::<Vec<_>>(),
+            }),
+        ),
+        ("POST", "/openai/chat/completions" | "/openai/images/generations") => {
+            Reply::Stream(Vec::new())
+        }
+        ("POST", path)
+            if path.starts_with("/gemini/") && path.ends_with(":streamGenerateContent") =>
+        {
+            Reply::Stream(Vec::new())
+        }
+        _ => {
+            return (
+                Reply::Json(404, json!({ "error": "Not found in the mock server" })),
+                MockResponse::default(),
+            );
+        }
+    };
+
+    // Generation endpoints consume a scripted response.
+    if let Reply::Json(..) = reply {
+        return (reply, MockResponse::default());
+    }
+
+    let Some(response) = state.responses.pop_front() else {
+        return (
+            Reply::Json(
+                500,
+                json!({ "error": "No scripted response left in the mock server" }),
+            ),
+            MockResponse::default(),
+        );
+    };
+
+    let reply = if let Some((status, body)) = &response.error {
+        let body = serde_json::from_str(body).unwrap_or_else(|_| json!(body));
+        Reply::Json(*status, body)
+    } else if path.ends_with("/images/generations") {
+        match &response.image {
+            Some(image) => Reply::Json(
+                200,
+                json!({
+                    "created": 0,
+                    "data": [{ "b64_json": base64::engine::general_purpose::STANDARD.encode(image) }],
+                }),
+            ),
+            None => Reply::Json(
+                500,
+                json!({ "error": "The scripted response has no image" }),
+            ),
+        }
+    } else if path.starts_with("/gemini/") {
+        Reply::Stream(response.gemini_events())
+    } else {
+        Reply::Stream(response.openai_events())
+    };
+
+    (reply, response)
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<Mutex<State>>) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let request = read_request(&mut stream).await?;
+    let (reply, response) = reply(&request, &state);
+    state.lock().unwrap().requests.push(request);
+
+    let mut stream = stream.into_inner();
+    let extra_headers: String = response
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\r\n"))
+        .collect();
+
+    match reply {
+        Reply::Json(status, body) => {
+            let body = body.to_string();
+            sleep(response.delay).await;
+            let head = format!(
+                "HTTP/1.1 {status} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n{extra_headers}\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(body.as_bytes()).await?;
+        }
+        Reply::Stream(events) => {
+            let head = format!(
+                "HTTP/1.1 200 Mock\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\nconnection: close\r\n{extra_headers}\r\n"
+            );
+            stream.write_all(head.as_bytes()).await?;
+            stream.flush().await?;
+
+            let body = events.concat();
+            let pieces: Vec<&[u8]> = match response.write_size {
+                Some(size) => body.as_bytes().chunks(size).collect(),
+                None => events.iter().map(|e| e.as_bytes()).collect(),
+            };
+
+            for (index, piece) in pieces.into_iter().enumerate() {
+                if response.interrupt_after == Some(index) {
+                    // Closing without the last chunk makes the body incomplete.
+                    return stream.shutdown().await;
+                }
+
+                if !response.delay.is_zero() {
+                    sleep(response.delay).await;
+                }
+
+                stream
+                    .write_all(format!("{:x}\r\n", piece.len()).as_bytes())
+                    .await?;
+                stream.write_all(piece).await?;
+                stream.write_all(b"\r\n").await?;
+                stream.flush().await?;
+            }
+
+            stream.write_all(b"0\r\n\r\n").await?;
+        }
+    }
+
+    stream.flush().await?;
+    stream.shutdown().await
+}
+
+#[cfg(all(test, feature = "api-clients"))]
+mod tests {
+    use super::*;
+    use crate::clients::gemini::GeminiClient;
+    use crate::clients::openai::OpenAiClient;
+    use crate::clients::openai_image::OpenAiImageClient;
+    use crate::protocol::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn user_messages(text: &str) -> Vec<Message> {
+        vec![Message {
+            from: EntityId::User,
+            content: MessageContent {
+                text: text.into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }]
+    }
+
+    async fn send(
+        client: &mut dyn BotClient,
+        bot_id: &str,
+        text: &str,
+    ) -> Vec<ClientResult<MessageContent>> {
+        let stream = client.send(&BotId::new(bot_id), &user_messages(text), &[]);
+        futures::StreamExt::collect(stream).await
+    }
+
+    #[test]
+    fn openai_client_streams_chunked_text_and_reasoning() {
+        let server = MockServer::start().unwrap();
+        server.push_response(
+            MockResponse::text("Hello world")
+                .with_reasoning("Greeting")
+                .with_delta_size(2)
+                .with_write_size(7),
+        );
+
+        let mut client = OpenAiClient::new(server.openai_url());
+        client.set_key("secret").unwrap();
+
+        let results = block_on(send(&mut client, "mock-model", "Hi"));
+        let content = results.last().unwrap().value().unwrap();
+        assert_eq!(content.text, "Hello world");
+        assert_eq!(content.reasoning, "Greeting");
+        // Every delta was yielded.
+        assert!(results.len() > 10);
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/openai/chat/completions");
+        assert_eq!(request.header("authorization"), Some("Bearer secret"));
+        assert_eq!(request.body.as_ref().unwrap()["model"], "mock-model");
+    }
+
+    #[test]
+    fn openai_client_assembles_tool_call_deltas() {
+        let server = MockServer::start().unwrap();
+        server.push_response(
+            MockResponse::text("Let me check.")
+                .with_tool_call("call_1", "get_weather", json!({ "city": "Tokyo" }))
+                .with_tool_call("call_2", "get_time", json!({}))
+                .with_delta_size(4),
+        );
+
+        let mut client = OpenAiClient::new(server.openai_url());
+        let results = block_on(send(&mut client, "mock-model", "Weather?"));
+        let content = results.last().unwrap().value().unwrap();
+
+        assert_eq!(content.tool_calls.len(), 2);
+        assert_eq!(content.tool_calls[0].name, "get_weather");
+        assert_eq!(content.tool_calls[0].arguments["city"], "Tokyo");
+        assert_eq!(content.tool_calls[1].id, "call_2");
+    }
+
+    #[test]
+    fn openai_client_reports_errors() {
+        let server = MockServer::start().unwrap();
+        server.push_response(
+            MockResponse::error(429, r#"{"error":"slow down"}"#).with_header("retry-after", "2"),
+        );
+        server.push_response(MockResponse::text("Hello world").interrupted_after(1));
+
+        let mut client = OpenAiClient::new(server.openai_url());
+
+        let results = block_on(send(&mut client, "mock-model", "Hi"));
+        let error = &results[0].errors()[0];
+        assert_eq!(error.status_code(), Some(429));
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(2)));
+
+        let results = block_on(send(&mut client, "mock-model", "Hi"));
+        let last = results.last().unwrap();
+        assert_eq!(last.errors()[0].kind(), ClientErrorKind::Network);
+    }
+
+    #[test]
+    fn openai_client_lists_models() {
+        let server = MockServer::start().unwrap();
+        server.set_models(["a", "b"]);
+
+        let mut client = OpenAiClient::new(server.openai_url());
+        let bots = block_on(client.bots()).into_result().unwrap();
+        assert_eq!(bots.len(), 2);
+        assert_eq!(bots[1].id, BotId::new("b"));
+    }
+
+    #[test]
+    fn gemini_client_streams_text_and_function_calls() {
+        let server = MockServer::start().unwrap();
+        server.push_response(
+            MockResponse::text("Sure")
+                .with_tool_call("call_1", "get_weather", json!({ "city": "Tokyo" }))
+                .with_delay(Duration::from_millis(1)),
+        );
+
+        let mut client = GeminiClient::new(server.gemini_url());
+        let bots = block_on(client.bots()).into_result().unwrap();
+        assert_eq!(bots[0].id, BotId::new("mock-model"));
+
+        let results = block_on(send(&mut client, "mock-model", "Weather?"));
+        let content = results.last().unwrap().value().unwrap();
+        assert_eq!(content.text, "Sure");
+        assert_eq!(content.tool_calls[0].name, "get_weather");
+        assert_eq!(content.tool_calls[0].arguments["city"], "Tokyo");
+    }
+
+    #[test]
+    fn image_client_gets_generated_image() {
+        let server = MockServer::start().unwrap();
+        server.push_response(MockResponse::image(b"not really a png".to_vec()));
+
+        let mut client = OpenAiImageClient::new(server.openai_url());
+        let results = block_on(send(&mut client, "mock-model", "A cat"));
+        let content = results.last().unwrap().value().unwrap();
+
+        let image = block_on(content.attachments[0].read()).unwrap();
+        assert_eq!(&*image, b"not really a png");
+    }
+
+    #[test]
+    fn chat_controller_answers_through_the_mock() {
+        use crate::controllers::chat::*;
+        use crate::utils::vec::VecMutation;
+
+        let server = MockServer::start().unwrap();
+        server.push_response(MockResponse::text("Hello from the mock").with_delta_size(5));
+
+        block_on(async {
+            let controller = ChatController::builder()
+                .with_basic_spawner()
+                .with_client(OpenAiClient::new(server.openai_url()))
+                .build_arc();
+
+            {
+                let mut controller = controller.lock().unwrap();
+                controller
+                    .dispatch_mutation(ChatStateMutation::SetBotId(Some(BotId::new("mock-model"))));
+                controller.dispatch_mutation(ChatStateMutation::MutateMessages(
+                    VecMutation::Extend(user_messages("Hi")),
+                ));
+                controller.dispatch_task(ChatTask::Send);
+            }
+
+            for _ in 0..500 {
+                sleep(Duration::from_millis(10)).await;
+                let controller = controller.lock().unwrap();
+                let state = controller.state();
+                if !state.is_streaming && state.messages.len() == 2 {
+                    break;
+                }
+            }
+
+            let controller = controller.lock().unwrap();
+            let last = controller.state().messages.last().unwrap();
+            assert_eq!(last.from, EntityId::Bot(BotId::new("mock-model")));
+            assert_eq!(last.content.text, "Hello from the mock");
+        });
+    }
+}
@@ -1,6 +1,10 @@
 //! Internally used to hold utility modules but exposes some very helpful ones.
 
 pub mod asynchronous;
+pub mod audio;
+#[cfg(feature = "http")]
+pub mod cassette;
+pub(crate) mod hash;
 #[cfg(feature = "http")]
 pub mod http;
 #[cfg(feature = "api-clients")]
@@ -0,0 +1,660 @@
+//! Utilities to deal with raw audio as exchanged by realtime and speech-to-text clients.
+//!
+//! Realtime providers exchange raw little-endian PCM16 at a fixed rate (see
+//! [`REALTIME_SAMPLE_RATE`]) through [`RealtimeEvent::AudioData`] and
+//! [`RealtimeCommand::SendAudio`]. Audio devices, on the other hand, normally
+//! work with `f32` samples at their own rate. This module bridges both worlds and
+//! also allows wrapping recorded audio as WAV [`Attachment`]s, which is what
+//! speech-to-text clients expect.
+
+use crate::protocol::*;
+
+/// Sample rate used by the OpenAI realtime API for `pcm16` input and output audio.
+pub const REALTIME_SAMPLE_RATE: u32 = 24_000;
+
+/// Mime type used for WAV attachments created by this module.
+pub const WAV_CONTENT_TYPE: &str = "audio/wav";
+
+/// Interprets raw little-endian PCM16 bytes as samples.
+///
+/// A trailing odd byte (an incomplete sample) is ignored.
+pub fn pcm16_from_bytes(bytes: &[u8]) -> Vec<i16> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Serializes PCM16 samples as raw little-endian bytes.
+pub fn pcm16_to_bytes(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+/// Converts PCM16 samples to `f32` samples in the `[-1.0, 1.0]` range.
+pub fn pcm16_to_f32(samples: &[i16]) -> Vec<f32> {
+    samples.iter().map(|&s| s as f32 / 32768.0).collect()
+}
+
+/// Converts `f32` samples to PCM16, clamping anything outside the `[-1.0, 1.0]` range.
+pub fn f32_to_pcm16(samples: &[f32]) -> Vec<i16> {
+    samples
+        .iter()
+        .map(|&s| (s.clamp(-1.0, 1.0) * 32767.0).round() as i16)
+        .collect()
+}
+
+/// Changes the sample rate of interleaved `f32` audio using linear interpolation.
+///
+/// This is cheap and good enough for speech, which is the main use case here.
+/// It's not intended for high fidelity music processing.
+pub fn resample(samples: &[f32], channels: u16, from_rate: u32, to_rate: u32) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+
+    if from_rate == to_rate || from_rate == 0 || to_rate == 0 || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let in_frames = samples.len() / channels;
+    let out_frames = (in_frames as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let step = from_rate as f64 / to_rate as f64;
+
+    let mut output = Vec::with_capacity(out_frames * channels);
+    for frame in 0..out_frames {
+        let position = frame as f64 * step;
+        let index = position.floor() as usize;
+        let next = (index + 1).min(in_frames - 1);
+        let fraction = (position - index as f64) as f32;
+
+        for channel in 0..channels {
+            let a = samples[index * channels + channel];
+            let b = samples[next * channels + channel];
+            output.push(a + (b - a) * fraction);
+        }
+    }
+
+    output
+}
+
+/// Same as [`resample`] but working directly with PCM16 samples.
+pub fn resample_pcm16(samples: &[i16], channels: u16, from_rate: u32, to_rate: u32) -> Vec<i16> {
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+
+    f32_to_pcm16(&resample(
+        &pcm16_to_f32(samples),
+        channels,
+        from_rate,
+        to_rate,
+    ))
+}
+
+/// Amount of interleaved samples that make up a frame of the given duration.
+///
+/// Never returns zero, so it's safe to use for chunking.
+pub fn frame_len(sample_rate: u32, channels: u16, frame_ms: u32) -> usize {
+    let frames = sample_rate as u64 * frame_ms as u64 / 1000;
+    (frames as usize * channels.max(1) as usize).max(1)
+}
+
+/// Splits interleaved samples into frames of the given duration.
+///
+/// The last frame may be shorter than the others.
+pub fn frames<T>(
+    samples: &[T],
+    sample_rate: u32,
+    channels: u16,
+    frame_ms: u32,
+) -> std::slice::Chunks<'_, T> {
+    samples.chunks(frame_len(sample_rate, channels, frame_ms))
+}
+
+/// Splits raw mono PCM16 bytes into frames of the given duration, never splitting
+/// a sample in half.
+///
+/// Useful to feed [`RealtimeCommand::SendAudio`] with evenly sized chunks.
+pub fn pcm16_byte_frames(
+    bytes: &[u8],
+    sample_rate: u32,
+    frame_ms: u32,
+) -> std::slice::Chunks<'_, u8> {
+    bytes.chunks(frame_len(sample_rate, 1, frame_ms) * 2)
+}
+
+// G.711 constants, as defined by the ITU-T reference implementation.
+const MULAW_BIAS: i32 = 0x84;
+const MULAW_CLIP: i32 = 32635;
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+/// Encodes a single PCM16 sample using G.711 μ-law.
+pub fn linear_to_mulaw(sample: i16) -> u8 {
+    let mut sample = sample as i32;
+    let sign = if sample < 0 { 0x80 } else { 0x00 };
+
+    if sample < 0 {
+        sample = -sample;
+    }
+
+    sample = sample.min(MULAW_CLIP) + MULAW_BIAS;
+
+    let exponent = (31 - sample.leading_zeros() as i32 - 7).clamp(0, 7);
+    let mantissa = (sample >> (exponent + 3)) & 0x0F;
+
+    !(sign | (exponent << 4) | mantissa) as u8
+}
+
+/// Decodes a single G.711 μ-law byte into a PCM16 sample.
+pub fn mulaw_to_linear(byte: u8) -> i16 {
+    let byte = !byte as i32;
+    let exponent = (byte >> 4) & 0x07;
+    let mantissa = byte & 0x0F;
+    let magnitude = (((mantissa << 3) + MULAW_BIAS) << exponent) - MULAW_BIAS;
+
+    if byte & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// Encodes a single PCM16 sample using G.711 A-law.
+pub fn linear_to_alaw(sample: i16) -> u8 {
+    // A-law works over 13 bit samples.
+    let mut sample = (sample as i32) >> 3;
+    let mask = if sample >= 0 {
+        0xD5
+    } else {
+        sample = -sample - 1;
+        0x55
+    };
+
+    let Some(segment) = ALAW_SEGMENT_ENDS.iter().position(|&end| sample <= end) else {
+        return (0x7F ^ mask) as u8;
+    };
+
+    let shift = if segment < 2 { 1 } else { segment };
+    let value = ((segment as i32) << 4) | ((sample >> shift) & 0x0F);
+
+    (value ^ mask) as u8
+}
+
+/// Decodes a single G.711 A-law byte into a PCM16 sample.
+pub fn alaw_to_linear(byte: u8) -> i16 {
+    let byte = (byte ^ 0x55) as i32;
+    let segment = (byte & 0x70) >> 4;
+    let mut magnitude = (byte & 0x0F) << 4;
+
+    match segment {
+        0 => magnitude += 8,
+        1 => magnitude += 0x108,
+        _ => magnitude = (magnitude + 0x108) << (segment - 1),
+    }
+
+    if byte & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+/// Encodes PCM16 samples using G.711 μ-law.
+pub fn encode_mulaw(samples: &[i16]) -> Vec<u8> {
+    samples.iter().map(|&s| linear_to_mulaw(s)).collect()
+}
+
+/// Decodes G.711 μ-law bytes into PCM16 samples.
+pub fn decode_mulaw(bytes: &[u8]) -> Vec<i16> {
+    bytes.iter().map(|&b| mulaw_to_linear(b)).collect()
+}
+
+/// Encodes PCM16 samples using G.711 A-law.
+pub fn encode_alaw(samples: &[i16]) -> Vec<u8> {
+    samples.iter().map(|&s| linear_to_alaw(s)).collect()
+}
+
+/// Decodes G.711 A-law bytes into PCM16 samples.
+pub fn decode_alaw(bytes: &[u8]) -> Vec<i16> {
+    bytes.iter().map(|&b| alaw_to_linear(b)).collect()
+}
+
+// WAVE format tags supported by `decode_wav`.
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_ALAW: u16 = 0x0006;
+const WAVE_FORMAT_MULAW: u16 = 0x0007;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Audio decoded from a WAV file, normalized to interleaved PCM16.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WavAudio {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<i16>,
+}
+
+/// Wraps interleaved PCM16 samples in a WAV (RIFF) container.
+pub fn encode_wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+    let channels = channels.max(1);
+    let block_align = channels * 2;
+    let byte_rate = sample_rate * block_align as u32;
+    let data_len = (samples.len() * 2) as u32;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    wav
+}
+
+/// Parses a WAV (RIFF) file into interleaved PCM16 samples.
+///
+/// Supports 8, 16, 24 and 32 bit integer PCM, 32 bit float, μ-law and A-law
+/// encoded data. Everything is converted to PCM16 on the way.
+pub fn decode_wav(bytes: &[u8]) -> std::io::Result<WavAudio> {
+    fn invalid(message: &str) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+    }
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("Not a RIFF/WAVE file"));
+    }
+
+    // (format tag, channels, sample rate, bits per sample)
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+
+    let mut cursor = 12;
+    while cursor + 8 <= bytes.len() {
+        let id = &bytes[cursor..cursor + 4];
+        let len = u32_at(bytes, cursor + 4) as usize;
+        let start = cursor + 8;
+        // Tolerate a truncated last chunk, which is common on streamed recordings.
+        let end = start.saturating_add(len).min(bytes.len());
+        let body = &bytes[start..end];
+
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err(invalid("WAV format chunk is too short"));
+                }
+
+                let mut tag = u16_at(body, 0);
+                if tag == WAVE_FORMAT_EXTENSIBLE {
+                    if body.len() < 26 {
+                        return Err(invalid("WAV extensible format chunk is too short"));
+                    }
+                    // The actual format is in the first bytes of the sub-format GUID.
+                    tag = u16_at(body, 24);
+                }
+
+                format = Some((tag, u16_at(body, 2), u32_at(body, 4), u16_at(body, 14)));
+            }
+            b"data" => data = Some(body),
+            _ => {}
+        }
+
+        // Chunks are padded to an even size.
+        cursor = start.saturating_add(len).saturating_add(len % 2);
+    }
+
+    let (tag, channels, sample_rate, bits) =
+        format.ok_or_else(|| invalid("WAV file is missing its format chunk"))?;
+    let data = data.ok_or_else(|| invalid("WAV file is missing its data chunk"))?;
+
+    if channels == 0 {
+        return Err(invalid("WAV file declares zero channels"));
+    }
+
+    let samples = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => data.iter().map(|&b| ((b as i16) - 128) << 8).collect(),
+        (WAVE_FORMAT_PCM, 16) => pcm16_from_bytes(data),
+        (WAVE_FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|b| i16::from_le_bytes([b[1], b[2]]))
+            .collect(),
+        (WAVE_FORMAT_PCM, 32) => data
+            .chunks_exact(4)
+            .map(|b| i16::from_le_bytes([b[2], b[3]]))
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => {
+            let samples: Vec<f32> = data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            f32_to_pcm16(&samples)
+        }
+        (WAVE_FORMAT_MULAW, 8) => decode_mulaw(data),
+        (WAVE_FORMAT_ALAW, 8) => decode_alaw(data),
+        _ => {
+            return Err(invalid(&format!(
+                "Unsupported WAV encoding (format {tag:#06x}, {bits} bits per sample)"
+            )));
+        }
+    };
+
+    Ok(WavAudio {
+        sample_rate,
+        channels,
+        samples,
+    })
+}
+
+/// Creates an in-memory WAV [`Attachment`] from raw mono PCM16 bytes.
+///
+/// If `target_rate` is given and differs from `sample_rate`, the audio is resampled
+/// first. Speech-to-text models usually work at 16 kHz internally, so downsampling
+/// realtime audio before upload saves bandwidth without hurting accuracy.
+pub fn pcm16_wav_attachment(
+    name: impl Into<String>,
+    pcm16: &[u8],
+    sample_rate: u32,
+    target_rate: Option<u32>,
+) -> Attachment {
+    let mut samples = pcm16_from_bytes(pcm16);
+    let mut rate = sample_rate;
+
+    if let Some(target_rate) = target_rate {
+        samples = resample_pcm16(&samples, 1, sample_rate, target_rate);
+        rate = target_rate;
+    }
+
+    Attachment::from_bytes(
+        name.into(),
+        Some(WAV_CONTENT_TYPE.to_string()),
+        &encode_wav(&samples, rate, 1),
+    )
+}
+
+/// Accumulates the audio exchanged during a realtime session.
+///
+/// Feed it with every [`RealtimeEvent`] received from the channel and every
+/// [`RealtimeCommand`] sent to it. Audio from the user and from the bot are kept
+/// in separate tracks, which can later be turned into WAV attachments, for example,
+/// to send the user side to [`crate::clients::openai_stt::OpenAiSttClient`].
+#[derive(Clone, Debug)]
+pub struct RealtimeRecorder {
+    sample_rate: u32,
+    user_audio: Vec<u8>,
+    bot_audio: Vec<u8>,
+}
+
+impl Default for RealtimeRecorder {
+    fn default() -> Self {
+        Self::new(REALTIME_SAMPLE_RATE)
+    }
+}
+
+impl RealtimeRecorder {
+    /// Creates an empty recorder for mono PCM16 audio at the given rate.
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            user_audio: Vec::new(),
+            bot_audio: Vec::new(),
+        }
+    }
+
+    /// Sample rate of the recorded audio.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Records audio coming from the bot. Other events are ignored.
+    pub fn record_event(&mut self, event: &RealtimeEvent) {
+        if let RealtimeEvent::AudioData(data) = event {
+            self.bot_audio.extend_from_slice(data);
+        }
+    }
+
+    /// Records audio sent by the user. Other commands are ignored.
+    pub fn record_command(&mut self, command: &RealtimeCommand) {
+        if let RealtimeCommand::SendAudio(data) = command {
+            self.user_audio.extend_from_slice(data);
+        }
+    }
+
+    /// Raw PCM16 bytes sent by the user so far.
+    pub fn user_audio(&self) -> &[u8] {
+        &self.user_audio
+    }
+
+    /// Raw PCM16 bytes received from the bot so far.
+    pub fn bot_audio(&self) -> &[u8] {
+        &self.bot_audio
+    }
+
+    /// Duration of the user track, in seconds.
+    pub fn user_duration_seconds(&self) -> f32 {
+        self.duration_seconds(&self.user_audio)
+    }
+
+    /// Duration of the bot track, in seconds.
+    pub fn bot_duration_seconds(&self) -> f32 {
+        self.duration_seconds(&self.bot_audio)
+    }
+
+    /// Wraps the user track as a WAV attachment, optionally resampled.
+    pub fn user_wav_attachment(
+        &self,
+        name: impl Into<String>,
+        target_rate: Option<u32>,
+    ) -> Attachment {
+        pcm16_wav_attachment(name, &self.user_audio, self.sample_rate, target_rate)
+    }
+
+    /// Wraps the bot track as a WAV attachment, optionally resampled.
+    pub fn bot_wav_attachment(
+        &self,
+        name: impl Into<String>,
+        target_rate: Option<u32>,
+    ) -> Attachment {
+        pcm16_wav_attachment(name, &self.bot_audio, self.sample_rate, target_rate)
+    }
+
+    /// Discards everything recorded so far.
+    pub fn clear(&mut self) {
+        self.user_audio.clear();
+        self.bot_audio.clear();
+    }
+
+    fn duration_seconds(&self, audio: &[u8]) -> f32 {
+        if self.sample_rate == 0 {
+            return 0.0;
+        }
+
+        (audio.len() / 2) as f32 / self.sample_rate as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcm16_bytes_roundtrip() {
+        let samples = vec![0, 1, -1, i16::MAX, i16::MIN, 1234];
+        let bytes = pcm16_to_bytes(&samples);
+        assert_eq!(bytes.len(), samples.len() * 2);
+        assert_eq!(pcm16_from_bytes(&bytes), samples);
+    }
+
+    #[test]
+    fn test_pcm16_from_bytes_ignores_odd_byte() {
+        assert_eq!(pcm16_from_bytes(&[0x01, 0x00, 0xFF]), vec![1]);
+    }
+
+    #[test]
+    fn test_f32_conversion() {
+        assert_eq!(
+            f32_to_pcm16(&[0.0, 1.0, -1.0, 2.0, -2.0]),
+            vec![0, 32767, -32767, 32767, -32767]
+        );
+
+        let floats = pcm16_to_f32(&[0, i16::MIN, 16384]);
+        assert_eq!(floats, vec![0.0, -1.0, 0.5]);
+    }
+
+    #[test]
+    fn test_resample_lengths() {
+        let input = vec![0.0f32; 24_000];
+        assert_eq!(resample(&input, 1, 24_000, 16_000).len(), 16_000);
+        assert_eq!(resample(&input, 1, 24_000, 48_000).len(), 48_000);
+        assert_eq!(resample(&input, 2, 24_000, 12_000).len(), 12_000);
+    }
+
+    #[test]
+    fn test_resample_interpolates() {
+        let output = resample(&[0.0, 1.0], 1, 1, 2);
+        assert_eq!(output, vec![0.0, 0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_resample_keeps_channels_apart() {
+        let input = [1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        let output = resample(&input, 2, 4, 2);
+        assert_eq!(output, vec![1.0, -1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_frames() {
+        let samples = vec![0i16; 250];
+        let lens: Vec<_> = frames(&samples, 1000, 1, 100).map(|f| f.len()).collect();
+        assert_eq!(lens, vec![100, 100, 50]);
+
+        let bytes = vec![0u8; 500];
+        let lens: Vec<_> = pcm16_byte_frames(&bytes, 1000, 100)
+            .map(|f| f.len())
+            .collect();
+        assert_eq!(lens, vec![200, 200, 100]);
+    }
+
+    #[test]
+    fn test_mulaw_known_values() {
+        assert_eq!(linear_to_mulaw(0), 0xFF);
+        assert_eq!(mulaw_to_linear(0xFF), 0);
+        assert_eq!(linear_to_mulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_mulaw(i16::MIN), 0x00);
+    }
+
+    #[test]
+    fn test_alaw_known_values() {
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(alaw_to_linear(0xD5), 8);
+        assert_eq!(linear_to_alaw(i16::MAX), 0xAA);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2A);
+    }
+
+    #[test]
+    fn test_g711_roundtrip_is_close() {
+        for sample in (i16::MIN..=i16::MAX).step_by(97) {
+            let tolerance = (sample as i32).abs() / 16 + 16;
+
+            let mulaw = mulaw_to_linear(linear_to_mulaw(sample)) as i32;
+            assert!(
+                (mulaw - sample as i32).abs() <= tolerance,
+                "mulaw {sample} -> {mulaw}"
+            );
+
+            let alaw = alaw_to_linear(linear_to_alaw(sample)) as i32;
+            assert!(
+                (alaw - sample as i32).abs() <= tolerance,
+                "alaw {sample} -> {alaw}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_wav_roundtrip() {
+        let samples = vec![0, 100, -100, i16::MAX, i16::MIN];
+        let wav = encode_wav(&samples, 16_000, 1);
+        assert_eq!(wav.len(), 44 + samples.len() * 2);
+
+        let decoded = decode_wav(&wav).unwrap();
+        assert_eq!(
+            decoded,
+            WavAudio {
+                sample_rate: 16_000,
+                channels: 1,
+                samples,
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_wav_skips_unknown_chunks() {
+        let mut wav = encode_wav(&[1, 2, 3], 8_000, 1);
+        // Insert an odd sized `LIST` chunk (with padding) right after the header.
+        let list = [b"LIST".as_slice(), &3u32.to_le_bytes(), &[1, 2, 3, 0]].concat();
+        wav.splice(12..12, list);
+
+        let decoded = decode_wav(&wav).unwrap();
+        assert_eq!(decoded.samples, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_decode_wav_mulaw() {
+        let mut wav = encode_wav(&[], 8_000, 1);
+        // Patch the format tag and bits per sample, then append μ-law data.
+        wav[20..22].copy_from_slice(&WAVE_FORMAT_MULAW.to_le_bytes());
+        wav[34..36].copy_from_slice(&8u16.to_le_bytes());
+        wav[40..44].copy_from_slice(&2u32.to_le_bytes());
+        wav.extend_from_slice(&[0xFF, 0x7F]);
+
+        let decoded = decode_wav(&wav).unwrap();
+        assert_eq!(decoded.samples, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_decode_wav_rejects_garbage() {
+        assert!(decode_wav(b"not a wav file").is_err());
+        assert!(decode_wav(b"RIFF\0\0\0\0WAVE").is_err());
+    }
+
+    #[test]
+    fn test_realtime_recorder() {
+        let mut recorder = RealtimeRecorder::default();
+        recorder.record_command(&RealtimeCommand::SendAudio(vec![0; 48_000]));
+        recorder.record_command(&RealtimeCommand::SendText("ignored".into()));
+        recorder.record_event(&RealtimeEvent::AudioData(vec![0; 24_000]));
+        recorder.record_event(&RealtimeEvent::SpeechStarted);
+
+        assert_eq!(recorder.user_duration_seconds(), 1.0);
+        assert_eq!(recorder.bot_duration_seconds(), 0.5);
+
+        let attachment = recorder.user_wav_attachment("user.wav", Some(16_000));
+        assert_eq!(attachment.content_type.as_deref(), Some(WAV_CONTENT_TYPE));
+
+        let bytes = futures::executor::block_on(attachment.read()).unwrap();
+        let decoded = decode_wav(&bytes).unwrap();
+        assert_eq!(decoded.sample_rate, 16_000);
+        assert_eq!(decoded.samples.len(), 16_000);
+
+        recorder.clear();
+        assert!(recorder.user_audio().is_empty());
+        assert!(recorder.bot_audio().is_empty());
+    }
+}
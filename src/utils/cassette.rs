@@ -0,0 +1,452 @@
+//! Record and replay of raw HTTP exchanges, to test clients without network.
+//!
+//! In [`CassetteMode::Record`], the responses of real requests are captured as they
+//! are streamed, keeping the boundaries of the received chunks (and so, of SSE
+//! events) intact. In [`CassetteMode::Replay`], requests are answered from the
+//! recorded exchanges instead, so client parsing and controller flows can be tested
+//! offline and deterministically.
+//!
+//! Request headers are never recorded, so API keys don't end up in cassette files.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "api-clients")]
+use crate::utils::asynchronous::BoxPlatformSendStream;
+#[cfg(feature = "api-clients")]
+use async_stream::stream;
+#[cfg(feature = "api-clients")]
+use base64::Engine;
+#[cfg(feature = "api-clients")]
+use reqwest::StatusCode;
+#[cfg(feature = "api-clients")]
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// What a [`Cassette`] does with the requests going through it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Requests reach the network and their responses are recorded.
+    Record,
+    /// Requests are answered from recorded exchanges, never reaching the network.
+    Replay,
+}
+
+/// A piece of a response body, as it was received from the network.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Chunk {
+    /// A chunk that is valid UTF-8 by itself, kept readable.
+    Text(String),
+    /// Any other chunk (e.g. one splitting a multi-byte character).
+    Binary { base64: String },
+}
+
+#[cfg(feature = "api-clients")]
+impl Chunk {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Chunk::Text(text.to_string()),
+            Err(_) => Chunk::Binary {
+                base64: base64::engine::general_purpose::STANDARD.encode(bytes),
+            },
+        }
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        match self {
+            Chunk::Text(text) => Ok(text.as_bytes().to_vec()),
+            Chunk::Binary { base64 } => base64::engine::general_purpose::STANDARD.decode(base64),
+        }
+    }
+}
+
+/// A request and the response it got.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    pub url: String,
+    /// The request body, if any. Non-JSON bodies are kept as a JSON string.
+    ///
+    /// When replaying, an exchange without body matches requests with any body,
+    /// which is handy for hand-written cassettes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<serde_json::Value>,
+    pub status: u16,
+    #[serde(default)]
+    pub response_headers: Vec<(String, String)>,
+    /// The response body, split as it was received.
+    #[serde(default)]
+    pub response_body: Vec<Chunk>,
+}
+
+/// Format of cassette files.
+#[derive(Serialize, Deserialize)]
+struct CassetteFile {
+    exchanges: Vec<Exchange>,
+}
+
+#[derive(Debug)]
+struct CassetteInner {
+    mode: CassetteMode,
+    exchanges: Vec<Exchange>,
+    /// Which exchanges have been replayed already.
+    replayed: Vec<bool>,
+    /// File updated after every recorded exchange.
+    #[cfg(not(target_arch = "wasm32"))]
+    path: Option<std::path::PathBuf>,
+}
+
+/// A list of recorded HTTP exchanges that clients can record into or replay from.
+///
+/// Give it to a client with its `set_cassette` method (e.g.
+/// `OpenAiClient::set_cassette`). Clones share the same exchanges.
+///
+/// When replaying, each request is answered with the first exchange not replayed
+/// yet with the same method, url and body (see [`Exchange::request_body`]).
+/// Requests without a matching exchange fail.
+#[derive(Debug, Clone)]
+pub struct Cassette(Arc<Mutex<CassetteInner>>);
+
+impl Cassette {
+    fn new(mode: CassetteMode, exchanges: Vec<Exchange>) -> Self {
+        Cassette(Arc::new(Mutex::new(CassetteInner {
+            mode,
+            replayed: vec![false; exchanges.len()],
+            exchanges,
+            #[cfg(not(target_arch = "wasm32"))]
+            path: None,
+        })))
+    }
+
+    /// An empty cassette recording in memory.
+    pub fn record() -> Self {
+        Cassette::new(CassetteMode::Record, Vec::new())
+    }
+
+    /// A cassette replaying the given exchanges.
+    pub fn replay(exchanges: Vec<Exchange>) -> Self {
+        Cassette::new(CassetteMode::Replay, exchanges)
+    }
+
+    /// An empty cassette recording into the given file, which is (over)written after
+    /// every exchange.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn record_to(path: impl Into<std::path::PathBuf>) -> Self {
+        let cassette = Cassette::record();
+        cassette.0.lock().unwrap().path = Some(path.into());
+        cassette
+    }
+
+    /// A cassette replaying the exchanges of the given file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn replay_from(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let content = std::fs::read(path)?;
+        let file: CassetteFile = serde_json::from_slice(&content)?;
+        Ok(Cassette::replay(file.exchanges))
+    }
+
+    /// Writes the exchanges into the given file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let file = CassetteFile {
+            exchanges: self.exchanges(),
+        };
+        std::fs::write(path, serde_json::to_vec_pretty(&file)?)
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.0.lock().unwrap().mode
+    }
+
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.0.lock().unwrap().exchanges.clone()
+    }
+
+    /// How many exchanges have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        let inner = self.0.lock().unwrap();
+        inner.replayed.iter().filter(|replayed| !**replayed).count()
+    }
+
+    #[cfg(feature = "api-clients")]
+    fn take(
+        &self,
+        method: &str,
+        url: &str,
+        request_body: &Option<serde_json::Value>,
+    ) -> Option<Exchange> {
+        let mut inner = self.0.lock().unwrap();
+        let index = inner.exchanges.iter().enumerate().position(|(i, e)| {
+            !inner.replayed[i]
+                && e.method == method
+                && e.url == url
+                && (e.request_body.is_none() || e.request_body == *request_body)
+        })?;
+
+        inner.replayed[index] = true;
+        Some(inner.exchanges[index].clone())
+    }
+
+    #[cfg(feature = "api-clients")]
+    fn push(&self, exchange: Exchange) {
+        let mut inner = self.0.lock().unwrap();
+        inner.exchanges.push(exchange);
+        inner.replayed.push(false);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = inner.path.clone() {
+            drop(inner);
+            if let Err(error) = self.save(&path) {
+                log::error!(
+                    "Could not save the cassette to {}: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+    }
+}
+
+/// Error of a request sent with [`send`].
+#[cfg(feature = "api-clients")]
+#[derive(Debug)]
+pub(crate) enum HttpError {
+    Network(reqwest::Error),
+    Cassette(String),
+}
+
+#[cfg(feature = "api-clients")]
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Network(error) => error.fmt(f),
+            HttpError::Cassette(message) => f.write_str(message),
+        }
+    }
+}
+
+#[cfg(feature = "api-clients")]
+impl std::error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpError::Network(error) => Some(error),
+            HttpError::Cassette(_) => None,
+        }
+    }
+}
+
+/// A response coming from the network or from a [`Cassette`].
+///
+/// Mirrors the parts of [`reqwest::Response`] used by the clients.
+#[cfg(feature = "api-clients")]
+pub(crate) struct HttpResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: BoxPlatformSendStream<'static, Result<Vec<u8>, HttpError>>,
+}
+
+#[cfg(feature = "api-clients")]
+impl HttpResponse {
+    pub(crate) fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub(crate) fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub(crate) async fn text(self) -> Result<String, HttpError> {
+        use futures::StreamExt;
+
+        let mut body = Vec::new();
+        let mut chunks = self.body;
+        while let Some(chunk) = chunks.next().await {
+            body.extend(chunk?);
+        }
+
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    pub(crate) fn bytes_stream(self) -> BoxPlatformSendStream<'static, Result<Vec<u8>, HttpError>> {
+        self.body
+    }
+
+    fn from_exchange(exchange: Exchange) -> Result<Self, HttpError> {
+        let status = StatusCode::from_u16(exchange.status)
+            .map_err(|_| HttpError::Cassette(format!("Invalid status {}", exchange.status)))?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &exchange.response_headers {
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) else {
+                return Err(HttpError::Cassette(format!("Invalid header {name}")));
+            };
+            headers.append(name, value);
+        }
+
+        let chunks = exchange
+            .response_body
+            .into_iter()
+            .map(|chunk| {
+                chunk
+                    .to_bytes()
+                    .map_err(|_| HttpError::Cassette("Invalid base64 chunk".to_string()))
+            })
+            .collect::<Vec<_>>();
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body: Box::pin(futures::stream::iter(chunks)),
+        })
+    }
+}
+
+/// Sends the request, going through the cassette if given.
+#[cfg(feature = "api-clients")]
+pub(crate) async fn send(
+    request: reqwest::RequestBuilder,
+    cassette: Option<&Cassette>,
+) -> Result<HttpResponse, HttpError> {
+    let (client, request) = request.build_split();
+    let request = request.map_err(HttpError::Network)?;
+
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+    let request_body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(|bytes| {
+            serde_json::from_slice(bytes).unwrap_or_else(|_| {
+                serde_json::Value::String(String::from_utf8_lossy(bytes).into_owned())
+            })
+        });
+
+    if let Some(cassette) = cassette
+        && cassette.mode() == CassetteMode::Replay
+    {
+        let exchange = cassette.take(&method, &url, &request_body).ok_or_else(|| {
+            HttpError::Cassette(format!("No recorded exchange for {method} {url}"))
+        })?;
+        return HttpResponse::from_exchange(exchange);
+    }
+
+    let response = client.execute(request).await.map_err(HttpError::Network)?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let chunks = response.bytes_stream();
+
+    let Some(cassette) = cassette.cloned() else {
+        let body = stream! {
+            for await chunk in chunks {
+                yield chunk.map(|bytes| bytes.to_vec()).map_err(HttpError::Network);
+            }
+        };
+
+        return Ok(HttpResponse {
+            status,
+            headers,
+            body: Box::pin(body),
+        });
+    };
+
+    let mut exchange = Exchange {
+        method,
+        url,
+        request_body,
+        status: status.as_u16(),
+        response_headers: headers
+            .iter()
+            .filter(|(name, _)| *name != reqwest::header::SET_COOKIE)
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        response_body: Vec::new(),
+    };
+
+    // Recorded once the body is consumed, with whatever was received.
+    let body = stream! {
+        for await chunk in chunks {
+            match chunk {
+                Ok(bytes) => {
+                    exchange.response_body.push(Chunk::from_bytes(&bytes));
+                    yield Ok(bytes.to_vec());
+                }
+                Err(error) => {
+                    yield Err(HttpError::Network(error));
+                    break;
+                }
+            }
+        }
+
+        cassette.push(exchange);
+    };
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body: Box::pin(body),
+    })
+}
+
+#[cfg(all(test, feature = "api-clients"))]
+mod tests {
+    use super::*;
+
+    fn exchange(body: &str) -> Exchange {
+        Exchange {
+            method: "POST".into(),
+            url: "http://localhost/chat/completions".into(),
+            request_body: Some(serde_json::json!({ "n": 1 })),
+            status: 200,
+            response_headers: vec![("x-ratelimit-remaining-requests".into(), "9".into())],
+            response_body: vec![Chunk::Text(body.into())],
+        }
+    }
+
+    fn request(body: serde_json::Value) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .post("http://localhost/chat/completions")
+            .json(&body)
+    }
+
+    #[test]
+    fn replays_matching_exchanges_in_order() {
+        let cassette = Cassette::replay(vec![exchange("first"), exchange("second")]);
+
+        let text = futures::executor::block_on(async {
+            let response = send(request(serde_json::json!({ "n": 1 })), Some(&cassette))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["x-ratelimit-remaining-requests"], "9");
+            response.text().await.unwrap()
+        });
+        assert_eq!(text, "first");
+        assert_eq!(cassette.remaining(), 1);
+
+        // Another body doesn't match anything.
+        let result = futures::executor::block_on(send(
+            request(serde_json::json!({ "n": 2 })),
+            Some(&cassette),
+        ));
+        assert!(matches!(result, Err(HttpError::Cassette(_))));
+    }
+
+    #[test]
+    fn chunks_keep_invalid_utf8() {
+        // "é" split in half.
+        let bytes = "é".as_bytes();
+        let chunks = [
+            Chunk::from_bytes(&bytes[..1]),
+            Chunk::from_bytes(&bytes[1..]),
+        ];
+        assert!(matches!(chunks[0], Chunk::Binary { .. }));
+
+        let json = serde_json::to_string(&chunks).unwrap();
+        let chunks: Vec<Chunk> = serde_json::from_str(&json).unwrap();
+        let joined: Vec<u8> = chunks.iter().flat_map(|c| c.to_bytes().unwrap()).collect();
+        assert_eq!(joined, bytes);
+    }
+}
@@ -0,0 +1,42 @@
+use serde::Serialize;
+
+/// 128-bit FNV-1a, stable across runs and platforms (unlike `std` hashers), so keys
+/// can be persisted.
+pub(crate) struct KeyHasher(u128);
+
+impl KeyHasher {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    pub(crate) fn new() -> Self {
+        KeyHasher(Self::OFFSET_BASIS)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u128;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Hashes the JSON representation of a value, with a separator to avoid ambiguity.
+    pub(crate) fn update_json(&mut self, value: &impl Serialize) {
+        serde_json::to_writer(&mut *self, value).expect("serializable value");
+        self.update(&[0]);
+    }
+
+    pub(crate) fn finish(&self) -> String {
+        format!("{:032x}", self.0)
+    }
+}
+
+impl std::io::Write for KeyHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
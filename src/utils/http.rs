@@ -23,3 +23,155 @@ pub(crate) fn default_client() -> reqwest::Client {
     // fetch API under the hood, which handles connection issues properly.
     reqwest::Client::new()
 }
+
+/// Parses the `Retry-After` header, given either in seconds or as an HTTP date.
+#[cfg(feature = "api-clients")]
+pub(crate) fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<std::time::Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means there is no need to wait.
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Attaches the status code and the `Retry-After` hint of an unsuccessful response
+/// to the given error.
+#[cfg(feature = "api-clients")]
+pub(crate) fn with_response_info(
+    error: crate::protocol::ClientError,
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+) -> crate::protocol::ClientError {
+    let error = error.with_status_code(status.as_u16());
+    match retry_after(headers) {
+        Some(retry_after) => error.with_retry_after(retry_after),
+        None => error,
+    }
+}
+
+/// Parses the `x-ratelimit-*` headers used by OpenAI and compatible providers.
+#[cfg(feature = "api-clients")]
+pub(crate) fn rate_limits(
+    headers: &reqwest::header::HeaderMap,
+) -> Option<crate::protocol::RateLimits> {
+    let header = |name: &str| headers.get(name)?.to_str().ok().map(str::trim);
+    let number = |name: &str| header(name)?.parse::<u64>().ok();
+    let duration = |name: &str| parse_reset_duration(header(name)?);
+
+    let limits = crate::protocol::RateLimits {
+        limit_requests: number("x-ratelimit-limit-requests"),
+        limit_tokens: number("x-ratelimit-limit-tokens"),
+        remaining_requests: number("x-ratelimit-remaining-requests"),
+        remaining_tokens: number("x-ratelimit-remaining-tokens"),
+        reset_requests: duration("x-ratelimit-reset-requests"),
+        reset_tokens: duration("x-ratelimit-reset-tokens"),
+    };
+
+    (!limits.is_empty()).then_some(limits)
+}
+
+/// Parses durations like `1s`, `6m0s`, `20ms` or `1h2m3.5s`, as used by the
+/// `x-ratelimit-reset-*` headers. Plain numbers are taken as seconds.
+#[cfg(feature = "api-clients")]
+fn parse_reset_duration(value: &str) -> Option<std::time::Duration> {
+    if let Ok(seconds) = value.parse::<f64>() {
+        return std::time::Duration::try_from_secs_f64(seconds).ok();
+    }
+
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number = rest[..number_len].parse::<f64>().ok()?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let factor = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+
+        total += number * factor;
+    }
+
+    std::time::Duration::try_from_secs_f64(total).ok()
+}
+
+#[cfg(all(test, feature = "api-clients"))]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use std::time::Duration;
+
+    #[test]
+    fn retry_after_in_seconds() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn retry_after_as_past_date() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_missing_or_invalid() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn rate_limits_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-limit-requests", HeaderValue::from_static("60"));
+        headers.insert(
+            "x-ratelimit-remaining-tokens",
+            HeaderValue::from_static("149984"),
+        );
+        headers.insert("x-ratelimit-reset-requests", HeaderValue::from_static("1s"));
+        headers.insert(
+            "x-ratelimit-reset-tokens",
+            HeaderValue::from_static("6m0.5s"),
+        );
+
+        let limits = rate_limits(&headers).unwrap();
+        assert_eq!(limits.limit_requests, Some(60));
+        assert_eq!(limits.remaining_tokens, Some(149984));
+        assert_eq!(limits.reset_requests, Some(Duration::from_secs(1)));
+        assert_eq!(limits.reset_tokens, Some(Duration::from_millis(360_500)));
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+
+        assert_eq!(rate_limits(&HeaderMap::new()), None);
+    }
+}
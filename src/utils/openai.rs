@@ -1,6 +1,7 @@
 //! Shared definitions and utilities for the OpenAI spec (and extensions).
 
 use crate::protocol::*;
+use crate::utils::cassette::Cassette;
 use serde::Deserialize;
 
 /// A model from the models endpoint.
@@ -23,23 +24,32 @@ pub(crate) async fn get_models(
     client: &reqwest::Client,
     url: &str,
     headers: reqwest::header::HeaderMap,
+    cassette: Option<&Cassette>,
 ) -> Result<Vec<Model>, ClientError> {
     let url = format!("{}/models", url);
     let request = client.get(&url).headers(headers);
 
-    let response = request.send().await.map_err(|e| {
-        ClientError::new_with_source(
-            ClientErrorKind::Network,
-            format!("An error ocurred sending a request to {url}."),
-            Some(e),
-        )
-    })?;
+    let response = crate::utils::cassette::send(request, cassette)
+        .await
+        .map_err(|e| {
+            ClientError::new_with_source(
+                ClientErrorKind::Network,
+                format!("An error ocurred sending a request to {url}."),
+                Some(e),
+            )
+        })?;
 
     if !response.status().is_success() {
-        let code = response.status().as_u16();
-        return Err(ClientError::new(
+        let status = response.status();
+        let code = status.as_u16();
+        let error = ClientError::new(
             ClientErrorKind::Response,
             format!("Got unexpected HTTP status code {code} from {url}."),
+        );
+        return Err(crate::utils::http::with_response_info(
+            error,
+            status,
+            response.headers(),
         ));
     }
 
@@ -74,8 +84,9 @@ pub(crate) async fn get_bots(
     url: &str,
     headers: reqwest::header::HeaderMap,
     capabilities: &BotCapabilities,
+    cassette: Option<&Cassette>,
 ) -> Result<Vec<Bot>, ClientError> {
-    let models = get_models(client, url, headers).await?;
+    let models = get_models(client, url, headers, cassette).await?;
 
     let bots: Vec<Bot> = models
         .iter()
@@ -12,7 +12,7 @@ pub fn create_tool_output_summary(_tool_name: &str, content: &str) -> String {
             // Otherwise return a truncated pretty print
             if let Ok(pretty) = serde_json::to_string_pretty(&obj) {
                 if pretty.len() > 100 {
-                    return format!("{}...", &pretty[..100]);
+                    return format!("{}...", truncate_at_char_boundary(&pretty, 100));
                 }
                 return pretty;
             }
@@ -21,12 +21,21 @@ pub fn create_tool_output_summary(_tool_name: &str, content: &str) -> String {
 
     // For non-JSON or simple text, truncate if too long
     if content.len() > 100 {
-        format!("{}...", &content[..100])
+        format!("{}...", truncate_at_char_boundary(content, 100))
     } else {
         content.to_string()
     }
 }
 
+/// Cuts the string at `max` bytes at most, without splitting a character.
+fn truncate_at_char_boundary(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
 /// Parses a namespaced tool name into server_id and tool_name components
 /// "filesystem__read_file" -> ("filesystem", "read_file")
 /// "mcp-internet-speed__test-speed" -> ("mcp-internet-speed", "test-speed")
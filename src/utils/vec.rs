@@ -246,6 +246,34 @@ impl<T: Clone> VecMutation<T> {
             ),
         }
     }
+
+    /// Mutations undoing this one, to be applied in order after applying this one
+    /// to the given target.
+    pub fn inverse(&self, target: &[T]) -> Vec<VecMutation<T>> {
+        let mut inverse: Vec<_> = self
+            .effects(target)
+            .map(|effect| match effect {
+                VecEffect::Insert(index, items) => {
+                    VecMutation::RemoveRange(index, index + items.len())
+                }
+                VecEffect::Update(index, previous, _) => {
+                    VecMutation::Update(index, previous.clone())
+                }
+                VecEffect::Remove(start, _, items) => {
+                    VecMutation::InsertMany(start, items.to_vec())
+                }
+            })
+            .collect();
+
+        // Sparse removals all refer to indices before any of them happens, so they are
+        // undone in the same order. Other effects happen one after the other, so they
+        // are undone backwards.
+        if !matches!(self, Self::RemoveMany(_)) {
+            inverse.reverse();
+        }
+
+        inverse
+    }
 }
 
 /// A primitive operation that will be performed on a `Vec<T>` as a result of a
@@ -275,6 +303,36 @@ pub enum VecEffect<'a, T> {
 mod tests {
     use super::{IndexSet, VecMutation};
 
+    #[test]
+    fn test_inverse_undoes_every_mutation() {
+        let original = vec![1, 2, 3, 4, 5];
+        let mutations = [
+            VecMutation::Splice(1, 3, vec![10, 20, 30]),
+            VecMutation::InsertMany(2, vec![10, 20]),
+            VecMutation::InsertOne(0, 10),
+            VecMutation::Extend(vec![10, 20]),
+            VecMutation::Push(10),
+            VecMutation::RemoveRange(1, 4),
+            VecMutation::RemoveOne(4),
+            VecMutation::RemoveMany(IndexSet::from(vec![3, 0, 1])),
+            VecMutation::RemoveLast,
+            VecMutation::Clear,
+            VecMutation::Update(2, 10),
+            VecMutation::UpdateLast(10),
+            VecMutation::Set(vec![10, 20]),
+        ];
+
+        for mutation in mutations {
+            let mut vec = original.clone();
+            let inverse = mutation.inverse(&vec);
+            mutation.clone().apply(&mut vec);
+            for undo in inverse {
+                undo.apply(&mut vec);
+            }
+            assert_eq!(vec, original, "{:?}", mutation);
+        }
+    }
+
     #[test]
     fn test_splice_replace() {
         let mut vec = vec![1, 2, 3, 4, 5];